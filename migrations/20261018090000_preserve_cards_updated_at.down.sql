@@ -0,0 +1,9 @@
+-- down.sql
+CREATE OR REPLACE FUNCTION update_cards_modified_column()
+RETURNS TRIGGER AS $$
+BEGIN
+   NEW.updated_at = CURRENT_TIMESTAMP;
+RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
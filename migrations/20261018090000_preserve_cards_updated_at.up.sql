@@ -0,0 +1,11 @@
+-- up.sql
+CREATE OR REPLACE FUNCTION update_cards_modified_column()
+RETURNS TRIGGER AS $$
+BEGIN
+   IF current_setting('cards.preserve_updated_at', true) IS DISTINCT FROM 'on' THEN
+       NEW.updated_at = CURRENT_TIMESTAMP;
+   END IF;
+RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
@@ -0,0 +1,10 @@
+-- down.sql
+DROP TRIGGER IF EXISTS update_cards_related_card_ids ON card_relations;
+
+DROP TRIGGER IF EXISTS check_card_relations_owner ON card_relations;
+
+DROP TABLE card_relations;
+
+DROP FUNCTION IF EXISTS update_related_card_ids_column;
+
+DROP FUNCTION IF EXISTS check_card_relation_owner;
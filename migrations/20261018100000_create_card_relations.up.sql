@@ -0,0 +1,94 @@
+-- up.sql
+CREATE TABLE card_relations (
+    card_id         INTEGER REFERENCES cards (id) ON DELETE CASCADE NOT NULL,
+    related_card_id INTEGER REFERENCES cards (id) ON DELETE CASCADE NOT NULL,
+    relation_type   VARCHAR(100),
+    created_at      TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (card_id, related_card_id),
+    -- relations are symmetric, each pair is stored once with the lower id first
+    CHECK (card_id < related_card_id),
+    CHECK (relation_type IN ('synonym', 'antonym', 'false_friend'))
+);
+
+CREATE INDEX card_relations_related_card_id_idx ON card_relations (related_card_id);
+
+CREATE OR REPLACE FUNCTION check_card_relation_owner()
+RETURNS TRIGGER AS $$
+BEGIN
+   IF (SELECT COUNT(DISTINCT decks.user_id)
+       FROM cards
+       JOIN decks ON decks.id = cards.deck_id
+       WHERE cards.id IN (NEW.card_id, NEW.related_card_id)) > 1 THEN
+       RAISE EXCEPTION 'related cards must belong to the same user';
+END IF;
+RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER check_card_relations_owner
+    BEFORE INSERT OR UPDATE
+    ON card_relations
+    FOR EACH ROW
+    EXECUTE FUNCTION check_card_relation_owner();
+
+-- cards.related_card_ids is kept as a read only cache of card_relations
+
+CREATE OR REPLACE FUNCTION update_related_card_ids_column()
+RETURNS TRIGGER AS $$
+DECLARE
+   relation card_relations;
+   preserve_updated_at TEXT := current_setting('cards.preserve_updated_at', true);
+BEGIN
+   IF TG_OP = 'DELETE' THEN
+       relation = OLD;
+   ELSE
+       relation = NEW;
+END IF;
+
+   PERFORM set_config('cards.preserve_updated_at', 'on', true);
+
+   UPDATE cards
+   SET related_card_ids = ARRAY(
+       SELECT CASE WHEN card_relations.card_id = cards.id THEN card_relations.related_card_id ELSE card_relations.card_id END
+       FROM card_relations
+       WHERE card_relations.card_id = cards.id OR card_relations.related_card_id = cards.id
+       ORDER BY 1
+   )
+   WHERE id IN (relation.card_id, relation.related_card_id);
+
+   PERFORM set_config('cards.preserve_updated_at', COALESCE(preserve_updated_at, ''), true);
+RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER update_cards_related_card_ids
+    AFTER INSERT OR UPDATE OR DELETE
+    ON card_relations
+    FOR EACH ROW
+    EXECUTE FUNCTION update_related_card_ids_column();
+
+-- move existing links over, dropping those to missing cards or other users' cards
+
+INSERT INTO card_relations (card_id, related_card_id)
+SELECT DISTINCT LEAST(cards.id, related_cards.id), GREATEST(cards.id, related_cards.id)
+FROM cards
+JOIN decks ON decks.id = cards.deck_id
+JOIN cards related_cards ON related_cards.id = ANY (cards.related_card_ids)
+JOIN decks related_decks ON related_decks.id = related_cards.deck_id
+WHERE cards.id <> related_cards.id
+  AND decks.user_id = related_decks.user_id
+ON CONFLICT DO NOTHING;
+
+ALTER TABLE cards DISABLE TRIGGER update_cards_modtime;
+
+UPDATE cards
+SET related_card_ids = ARRAY(
+    SELECT CASE WHEN card_relations.card_id = cards.id THEN card_relations.related_card_id ELSE card_relations.card_id END
+    FROM card_relations
+    WHERE card_relations.card_id = cards.id OR card_relations.related_card_id = cards.id
+    ORDER BY 1
+);
+
+ALTER TABLE cards ENABLE TRIGGER update_cards_modtime;
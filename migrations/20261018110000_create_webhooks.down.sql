@@ -0,0 +1,8 @@
+-- down.sql
+DROP TABLE webhook_deliveries;
+
+DROP FUNCTION IF EXISTS update_webhook_deliveries_modified_column;
+
+DROP TABLE webhooks;
+
+DROP FUNCTION IF EXISTS update_webhooks_modified_column;
@@ -0,0 +1,57 @@
+-- up.sql
+CREATE TABLE webhooks (
+    id          SERIAL PRIMARY KEY,
+    user_id     INTEGER REFERENCES users (id) NOT NULL,
+    url         VARCHAR(255)                  NOT NULL,
+    secret      VARCHAR(100)                  NOT NULL,
+    event_types VARCHAR(100)[] NOT NULL DEFAULT ARRAY[]:: VARCHAR(100) [],
+    active      BOOLEAN                       NOT NULL DEFAULT TRUE,
+    created_at  TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at  TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE OR REPLACE FUNCTION update_webhooks_modified_column()
+RETURNS TRIGGER AS $$
+BEGIN
+   NEW.updated_at = CURRENT_TIMESTAMP;
+RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER update_webhooks_modtime
+    BEFORE UPDATE
+    ON webhooks
+    FOR EACH ROW
+    EXECUTE FUNCTION update_webhooks_modified_column();
+
+CREATE TABLE webhook_deliveries (
+    id              SERIAL PRIMARY KEY,
+    webhook_id      INTEGER REFERENCES webhooks (id) ON DELETE CASCADE NOT NULL,
+    event_type      VARCHAR(100)                  NOT NULL,
+    payload         JSONB                         NOT NULL,
+    status          VARCHAR(100)                  NOT NULL DEFAULT 'pending',
+    attempts        INTEGER                       NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    response_status INTEGER,
+    last_error      TEXT,
+    created_at      TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at      TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX webhook_deliveries_pending_idx ON webhook_deliveries (next_attempt_at) WHERE status = 'pending';
+
+CREATE OR REPLACE FUNCTION update_webhook_deliveries_modified_column()
+RETURNS TRIGGER AS $$
+BEGIN
+   NEW.updated_at = CURRENT_TIMESTAMP;
+RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER update_webhook_deliveries_modtime
+    BEFORE UPDATE
+    ON webhook_deliveries
+    FOR EACH ROW
+    EXECUTE FUNCTION update_webhook_deliveries_modified_column();
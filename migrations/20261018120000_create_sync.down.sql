@@ -0,0 +1,23 @@
+-- down.sql
+DROP TRIGGER IF EXISTS record_decks_sync_change ON decks;
+
+DROP TRIGGER IF EXISTS record_cards_sync_change ON cards;
+
+DROP TABLE sync_changes;
+
+DROP TRIGGER IF EXISTS insert_cards_review ON cards;
+
+DROP FUNCTION IF EXISTS insert_review_row;
+
+DROP TABLE reviews;
+
+DROP FUNCTION IF EXISTS record_sync_change;
+
+CREATE OR REPLACE FUNCTION update_decks_modified_column()
+RETURNS TRIGGER AS $$
+BEGIN
+   NEW.updated_at = CURRENT_TIMESTAMP;
+RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
@@ -0,0 +1,111 @@
+-- up.sql
+CREATE OR REPLACE FUNCTION update_decks_modified_column()
+RETURNS TRIGGER AS $$
+BEGIN
+   IF current_setting('decks.preserve_updated_at', true) IS DISTINCT FROM 'on' THEN
+       NEW.updated_at = CURRENT_TIMESTAMP;
+   END IF;
+RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TABLE reviews (
+    id          SERIAL PRIMARY KEY,
+    card_id     INTEGER REFERENCES cards (id) ON DELETE CASCADE NOT NULL,
+    rating      INTEGER                       NOT NULL,
+    prev_rating INTEGER                       NOT NULL,
+    seen_at     TIMESTAMP WITHOUT TIME ZONE NOT NULL,
+    seen_for    INTEGER,
+    reviewed_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    created_at  TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX reviews_card_id_idx ON reviews (card_id);
+
+-- every write of a rating counts as a review, even if the rating stays the same
+CREATE OR REPLACE FUNCTION insert_review_row()
+RETURNS TRIGGER AS $$
+BEGIN
+   INSERT INTO reviews (card_id, rating, prev_rating, seen_at, seen_for, reviewed_at)
+   VALUES (NEW.id, NEW.rating, NEW.prev_rating, NEW.seen_at, NEW.seen_for, NEW.updated_at);
+RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER insert_cards_review
+    AFTER UPDATE OF rating
+    ON cards
+    FOR EACH ROW
+    EXECUTE FUNCTION insert_review_row();
+
+-- one row per deck, card and review, stamped with the id of the last transaction that changed it
+CREATE TABLE sync_changes (
+    entity_type    VARCHAR(100)                  NOT NULL,
+    entity_id      INTEGER                       NOT NULL,
+    user_id        INTEGER REFERENCES users (id) ON DELETE CASCADE NOT NULL,
+    transaction_id BIGINT                        NOT NULL,
+    deleted        BOOLEAN                       NOT NULL DEFAULT FALSE,
+    changed_at     TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (entity_type, entity_id)
+);
+
+CREATE INDEX sync_changes_user_id_transaction_id_idx ON sync_changes (user_id, transaction_id);
+
+CREATE OR REPLACE FUNCTION record_sync_change()
+RETURNS TRIGGER AS $$
+DECLARE
+   change_user_id INTEGER;
+BEGIN
+   -- cascading deletes can run after the parent row is gone, so tombstones reuse the stored owner
+   IF TG_OP = 'DELETE' THEN
+       UPDATE sync_changes
+       SET deleted = TRUE, transaction_id = pg_current_xact_id()::TEXT::BIGINT, changed_at = CURRENT_TIMESTAMP
+       WHERE entity_type = TG_TABLE_NAME AND entity_id = OLD.id;
+       RETURN OLD;
+   END IF;
+
+   IF TG_TABLE_NAME = 'decks' THEN
+       change_user_id = NEW.user_id;
+   ELSIF TG_TABLE_NAME = 'cards' THEN
+       SELECT user_id INTO change_user_id FROM decks WHERE id = NEW.deck_id;
+   ELSE
+       SELECT decks.user_id INTO change_user_id FROM cards JOIN decks ON decks.id = cards.deck_id WHERE cards.id = NEW.card_id;
+   END IF;
+
+   INSERT INTO sync_changes (entity_type, entity_id, user_id, transaction_id)
+   VALUES (TG_TABLE_NAME, NEW.id, change_user_id, pg_current_xact_id()::TEXT::BIGINT)
+   ON CONFLICT (entity_type, entity_id) DO UPDATE
+   SET user_id = EXCLUDED.user_id, transaction_id = EXCLUDED.transaction_id, deleted = FALSE, changed_at = CURRENT_TIMESTAMP;
+RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER record_decks_sync_change
+    AFTER INSERT OR UPDATE OR DELETE
+    ON decks
+    FOR EACH ROW
+    EXECUTE FUNCTION record_sync_change();
+
+CREATE TRIGGER record_cards_sync_change
+    AFTER INSERT OR UPDATE OR DELETE
+    ON cards
+    FOR EACH ROW
+    EXECUTE FUNCTION record_sync_change();
+
+CREATE TRIGGER record_reviews_sync_change
+    AFTER INSERT OR DELETE
+    ON reviews
+    FOR EACH ROW
+    EXECUTE FUNCTION record_sync_change();
+
+INSERT INTO sync_changes (entity_type, entity_id, user_id, transaction_id)
+SELECT 'decks', id, user_id, pg_current_xact_id()::TEXT::BIGINT
+FROM decks;
+
+INSERT INTO sync_changes (entity_type, entity_id, user_id, transaction_id)
+SELECT 'cards', cards.id, decks.user_id, pg_current_xact_id()::TEXT::BIGINT
+FROM cards
+JOIN decks ON decks.id = cards.deck_id;
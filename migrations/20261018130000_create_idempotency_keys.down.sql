@@ -0,0 +1,4 @@
+-- down.sql
+DROP TABLE idempotency_keys;
+
+DROP FUNCTION IF EXISTS update_idempotency_keys_modified_column;
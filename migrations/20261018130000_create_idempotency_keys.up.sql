@@ -0,0 +1,29 @@
+-- up.sql
+CREATE TABLE idempotency_keys (
+    id              SERIAL PRIMARY KEY,
+    user_id         INTEGER REFERENCES users (id) ON DELETE CASCADE NOT NULL,
+    idempotency_key VARCHAR(255)                  NOT NULL,
+    request_hash    VARCHAR(64)                   NOT NULL,
+    response_status INTEGER,
+    response_body   TEXT,
+    -- when the request in progress took the key, so that an abandoned claim can be taken over
+    claimed_at      TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    created_at      TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at      TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (user_id, idempotency_key)
+);
+
+CREATE OR REPLACE FUNCTION update_idempotency_keys_modified_column()
+RETURNS TRIGGER AS $$
+BEGIN
+   NEW.updated_at = CURRENT_TIMESTAMP;
+RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER update_idempotency_keys_modtime
+    BEFORE UPDATE
+    ON idempotency_keys
+    FOR EACH ROW
+    EXECUTE FUNCTION update_idempotency_keys_modified_column();
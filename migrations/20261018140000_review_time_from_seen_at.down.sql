@@ -0,0 +1,10 @@
+-- down.sql
+CREATE OR REPLACE FUNCTION insert_review_row()
+RETURNS TRIGGER AS $$
+BEGIN
+   INSERT INTO reviews (card_id, rating, prev_rating, seen_at, seen_for, reviewed_at)
+   VALUES (NEW.id, NEW.rating, NEW.prev_rating, NEW.seen_at, NEW.seen_for, NEW.updated_at);
+RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
@@ -0,0 +1,18 @@
+-- up.sql
+-- a write that also sets seen_at says when the card was reviewed, e.g. reviews submitted offline
+CREATE OR REPLACE FUNCTION insert_review_row()
+RETURNS TRIGGER AS $$
+BEGIN
+   INSERT INTO reviews (card_id, rating, prev_rating, seen_at, seen_for, reviewed_at)
+   VALUES (
+       NEW.id,
+       NEW.rating,
+       NEW.prev_rating,
+       NEW.seen_at,
+       NEW.seen_for,
+       CASE WHEN NEW.seen_at IS DISTINCT FROM OLD.seen_at THEN NEW.seen_at ELSE NEW.updated_at END
+   );
+RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
@@ -0,0 +1,6 @@
+-- down.sql
+DROP TRIGGER IF EXISTS insert_cards_revision ON cards;
+
+DROP FUNCTION IF EXISTS insert_card_revision_row;
+
+DROP TABLE card_revisions;
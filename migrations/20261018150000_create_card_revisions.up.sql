@@ -0,0 +1,35 @@
+-- up.sql
+CREATE TABLE card_revisions (
+    id                SERIAL PRIMARY KEY,
+    card_id           INTEGER REFERENCES cards (id) ON DELETE CASCADE NOT NULL,
+    from_text         VARCHAR(100)                  NOT NULL,
+    to_text_primary   VARCHAR(100)                  NOT NULL,
+    to_text_secondary VARCHAR(100),
+    example_text      VARCHAR(255),
+    created_at        TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX card_revisions_card_id_idx ON card_revisions (card_id);
+
+-- keeps the texts a card had before they were changed
+CREATE OR REPLACE FUNCTION insert_card_revision_row()
+RETURNS TRIGGER AS $$
+BEGIN
+   INSERT INTO card_revisions (card_id, from_text, to_text_primary, to_text_secondary, example_text)
+   VALUES (OLD.id, OLD.from_text, OLD.to_text_primary, OLD.to_text_secondary, OLD.example_text);
+RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER insert_cards_revision
+    AFTER UPDATE
+    ON cards
+    FOR EACH ROW
+    WHEN (
+        OLD.from_text IS DISTINCT FROM NEW.from_text
+        OR OLD.to_text_primary IS DISTINCT FROM NEW.to_text_primary
+        OR OLD.to_text_secondary IS DISTINCT FROM NEW.to_text_secondary
+        OR OLD.example_text IS DISTINCT FROM NEW.example_text
+    )
+    EXECUTE FUNCTION insert_card_revision_row();
@@ -0,0 +1,34 @@
+-- down.sql
+CREATE OR REPLACE FUNCTION update_prev_rating_column()
+RETURNS TRIGGER AS $$
+BEGIN
+   IF OLD.rating IS DISTINCT FROM NEW.rating THEN
+       NEW.prev_rating = OLD.rating;
+END IF;
+RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION insert_review_row()
+RETURNS TRIGGER AS $$
+BEGIN
+   INSERT INTO reviews (card_id, rating, prev_rating, seen_at, seen_for, reviewed_at)
+   VALUES (
+       NEW.id,
+       NEW.rating,
+       NEW.prev_rating,
+       NEW.seen_at,
+       NEW.seen_for,
+       CASE WHEN NEW.seen_at IS DISTINCT FROM OLD.seen_at THEN NEW.seen_at ELSE NEW.updated_at END
+   );
+RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+ALTER TABLE reviews
+    DROP COLUMN undo_rating,
+    DROP COLUMN undo_prev_rating,
+    DROP COLUMN undo_seen_at,
+    DROP COLUMN undo_seen_for,
+    DROP COLUMN undo_updated_at;
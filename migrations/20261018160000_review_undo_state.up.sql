@@ -0,0 +1,46 @@
+-- up.sql
+-- the card state before a review, so that the review can be undone
+-- undo_rating is needed besides prev_rating, which only moves when the rating changes
+ALTER TABLE reviews
+    ADD COLUMN undo_rating      INTEGER,
+    ADD COLUMN undo_prev_rating INTEGER,
+    ADD COLUMN undo_seen_at     TIMESTAMP WITHOUT TIME ZONE,
+    ADD COLUMN undo_seen_for    INTEGER,
+    ADD COLUMN undo_updated_at  TIMESTAMP WITHOUT TIME ZONE;
+
+-- an undo writes the old rating back, which is neither a review nor a rating change to track
+CREATE OR REPLACE FUNCTION insert_review_row()
+RETURNS TRIGGER AS $$
+BEGIN
+   IF current_setting('reviews.undo', true) = 'on' THEN
+       RETURN NEW;
+   END IF;
+
+   INSERT INTO reviews (card_id, rating, prev_rating, seen_at, seen_for, reviewed_at, undo_rating, undo_prev_rating, undo_seen_at, undo_seen_for, undo_updated_at)
+   VALUES (
+       NEW.id,
+       NEW.rating,
+       NEW.prev_rating,
+       NEW.seen_at,
+       NEW.seen_for,
+       CASE WHEN NEW.seen_at IS DISTINCT FROM OLD.seen_at THEN NEW.seen_at ELSE NEW.updated_at END,
+       OLD.rating,
+       OLD.prev_rating,
+       OLD.seen_at,
+       OLD.seen_for,
+       OLD.updated_at
+   );
+RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION update_prev_rating_column()
+RETURNS TRIGGER AS $$
+BEGIN
+   IF OLD.rating IS DISTINCT FROM NEW.rating AND current_setting('reviews.undo', true) IS DISTINCT FROM 'on' THEN
+       NEW.prev_rating = OLD.rating;
+END IF;
+RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,18 @@
+-- down.sql
+DROP TRIGGER IF EXISTS record_card_tags_sync_change ON card_tags;
+
+DROP TRIGGER IF EXISTS record_tags_sync_change ON tags;
+
+DROP TRIGGER IF EXISTS check_card_tags_owner ON card_tags;
+
+DROP TABLE card_tags;
+
+DROP FUNCTION IF EXISTS check_card_tag_owner;
+
+DROP TRIGGER IF EXISTS update_tags_modtime ON tags;
+
+DROP TABLE tags;
+
+DROP FUNCTION IF EXISTS update_tags_modified_column;
+
+DROP FUNCTION IF EXISTS record_card_tags_sync_change;
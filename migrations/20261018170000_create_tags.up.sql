@@ -0,0 +1,83 @@
+-- up.sql
+CREATE TABLE tags (
+    id         SERIAL PRIMARY KEY,
+    user_id    INTEGER REFERENCES users (id) ON DELETE CASCADE NOT NULL,
+    name       VARCHAR(100)                  NOT NULL,
+    created_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (user_id, name)
+);
+
+CREATE OR REPLACE FUNCTION update_tags_modified_column()
+RETURNS TRIGGER AS $$
+BEGIN
+   NEW.updated_at = CURRENT_TIMESTAMP;
+RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER update_tags_modtime
+    BEFORE UPDATE
+    ON tags
+    FOR EACH ROW
+    EXECUTE FUNCTION update_tags_modified_column();
+
+CREATE TABLE card_tags (
+    card_id    INTEGER REFERENCES cards (id) ON DELETE CASCADE NOT NULL,
+    tag_id     INTEGER REFERENCES tags (id) ON DELETE CASCADE NOT NULL,
+    created_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (card_id, tag_id)
+);
+
+CREATE INDEX card_tags_tag_id_idx ON card_tags (tag_id);
+
+CREATE OR REPLACE FUNCTION check_card_tag_owner()
+RETURNS TRIGGER AS $$
+BEGIN
+   IF (SELECT decks.user_id FROM cards JOIN decks ON decks.id = cards.deck_id WHERE cards.id = NEW.card_id)
+      IS DISTINCT FROM (SELECT user_id FROM tags WHERE id = NEW.tag_id) THEN
+       RAISE EXCEPTION 'tagged cards must belong to the owner of the tag';
+END IF;
+RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER check_card_tags_owner
+    BEFORE INSERT OR UPDATE
+    ON card_tags
+    FOR EACH ROW
+    EXECUTE FUNCTION check_card_tag_owner();
+
+-- a card whose tags change is synced again, renamed or deleted tags change all of their cards
+CREATE OR REPLACE FUNCTION record_card_tags_sync_change()
+RETURNS TRIGGER AS $$
+BEGIN
+   IF TG_TABLE_NAME = 'tags' THEN
+       UPDATE sync_changes
+       SET transaction_id = pg_current_xact_id()::TEXT::BIGINT, changed_at = CURRENT_TIMESTAMP
+       WHERE entity_type = 'cards' AND entity_id IN (SELECT card_id FROM card_tags WHERE tag_id = NEW.id);
+       RETURN NEW;
+   END IF;
+
+   UPDATE sync_changes
+   SET transaction_id = pg_current_xact_id()::TEXT::BIGINT, changed_at = CURRENT_TIMESTAMP
+   WHERE entity_type = 'cards' AND entity_id = CASE WHEN TG_OP = 'DELETE' THEN OLD.card_id ELSE NEW.card_id END;
+RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER record_card_tags_sync_change
+    AFTER INSERT OR DELETE
+    ON card_tags
+    FOR EACH ROW
+    EXECUTE FUNCTION record_card_tags_sync_change();
+
+CREATE TRIGGER record_tags_sync_change
+    AFTER UPDATE OF name
+    ON tags
+    FOR EACH ROW
+    WHEN (OLD.name IS DISTINCT FROM NEW.name)
+    EXECUTE FUNCTION record_card_tags_sync_change();
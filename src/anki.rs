@@ -0,0 +1,991 @@
+use crate::imports::{
+    is_audio_file, local_media_path, read_zip_entry, strip_html, ImportCard, ImportDeck,
+    PendingImport, ZipBudget,
+};
+use crate::{Card, Deck};
+use serde_json::{json, Map, Value};
+use sha1::{Digest, Sha1};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteConnection};
+use sqlx::{ConnectOptions, Connection, Executor};
+use std::collections::HashMap;
+use std::io::{Cursor, Write};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+// anki 2.1.50+ writes collection.anki21b (zstd) and a collection.anki2 that only holds a note
+// asking to update anki, unless "Support older Anki versions" is chosen on export
+const COLLECTION_FILES: [&str; 2] = ["collection.anki21", "collection.anki2"];
+const LATEST_COLLECTION_FILE: &str = "collection.anki21b";
+const MEDIA_FILE: &str = "media";
+
+const FIELD_SEPARATOR: char = '\x1f';
+
+// exports are written in schema 11, which every anki version since 2.1 still imports
+const EXPORT_COLLECTION_FILE: &str = "collection.anki2";
+const EXPORT_SCHEMA_VERSION: i64 = 11;
+const DEFAULT_DECK_ID: i64 = 1;
+const DEFAULT_DECK_CONFIG_ID: i64 = 1;
+const BASIC_NOTE_TYPE_ID: i64 = 1_700_000_000_001;
+const BASIC_REVERSED_NOTE_TYPE_ID: i64 = 1_700_000_000_002;
+
+const EXPORT_SCHEMA: &str = "
+CREATE TABLE col (id integer PRIMARY KEY, crt integer NOT NULL, mod integer NOT NULL, scm integer NOT NULL, ver integer NOT NULL, dty integer NOT NULL, usn integer NOT NULL, ls integer NOT NULL, conf text NOT NULL, models text NOT NULL, decks text NOT NULL, dconf text NOT NULL, tags text NOT NULL);
+CREATE TABLE notes (id integer PRIMARY KEY, guid text NOT NULL, mid integer NOT NULL, mod integer NOT NULL, usn integer NOT NULL, tags text NOT NULL, flds text NOT NULL, sfld integer NOT NULL, csum integer NOT NULL, flags integer NOT NULL, data text NOT NULL);
+CREATE TABLE cards (id integer PRIMARY KEY, nid integer NOT NULL, did integer NOT NULL, ord integer NOT NULL, mod integer NOT NULL, usn integer NOT NULL, type integer NOT NULL, queue integer NOT NULL, due integer NOT NULL, ivl integer NOT NULL, factor integer NOT NULL, reps integer NOT NULL, lapses integer NOT NULL, left integer NOT NULL, odue integer NOT NULL, odid integer NOT NULL, flags integer NOT NULL, data text NOT NULL);
+CREATE TABLE revlog (id integer PRIMARY KEY, cid integer NOT NULL, usn integer NOT NULL, ease integer NOT NULL, ivl integer NOT NULL, lastIvl integer NOT NULL, factor integer NOT NULL, time integer NOT NULL, type integer NOT NULL);
+CREATE TABLE graves (usn integer NOT NULL, oid integer NOT NULL, type integer NOT NULL);
+CREATE INDEX ix_notes_usn ON notes (usn);
+CREATE INDEX ix_cards_usn ON cards (usn);
+CREATE INDEX ix_revlog_usn ON revlog (usn);
+CREATE INDEX ix_cards_nid ON cards (nid);
+CREATE INDEX ix_cards_sched ON cards (did, queue, due);
+CREATE INDEX ix_revlog_cid ON revlog (cid);
+CREATE INDEX ix_notes_csum ON notes (csum);
+";
+
+struct AnkiNote {
+    note_type_id: i64,
+    fields: String,
+    tags: String,
+    deck_id: Option<i64>,
+}
+
+struct AnkiCollection {
+    deck_names: HashMap<i64, String>,
+    field_names: HashMap<i64, Vec<String>>,
+    notes: Vec<AnkiNote>,
+}
+
+// helpers
+
+fn parse_id(id: &str) -> Option<i64> {
+    id.parse::<i64>().ok()
+}
+
+// [sound:name] references and <img src="name"> references of a field
+fn media_references(field: &str) -> (Vec<String>, Vec<String>) {
+    let mut sounds = Vec::new();
+    let mut images = Vec::new();
+
+    for part in field.split("[sound:").skip(1) {
+        if let Some((name, _)) = part.split_once(']') {
+            sounds.push(String::from(name));
+        }
+    }
+
+    for part in field.split("src=").skip(1) {
+        let quote = part.chars().next();
+        if let Some(quote @ ('"' | '\'')) = quote {
+            if let Some((name, _)) = part[1..].split_once(quote) {
+                images.push(String::from(name));
+            }
+        }
+    }
+
+    (sounds, images)
+}
+
+fn remove_sound_references(field: &str) -> String {
+    let mut text = String::new();
+    let mut rest = field;
+
+    while let Some(start) = rest.find("[sound:") {
+        text.push_str(&rest[..start]);
+        rest = match rest[start..].find(']') {
+            Some(end) => &rest[start + end + 1..],
+            None => "",
+        };
+    }
+
+    text.push_str(rest);
+    text
+}
+
+fn optional_field(fields: &[String], index: usize) -> Option<String> {
+    fields.get(index).filter(|field| !field.is_empty()).cloned()
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// anki finds duplicates by the first 8 hex digits of the sha1 of the sort field
+fn field_checksum(field: &str) -> i64 {
+    let digest = Sha1::digest(field.as_bytes());
+    i64::from(u32::from_be_bytes([
+        digest[0], digest[1], digest[2], digest[3],
+    ]))
+}
+
+// anki tags can not hold spaces and are stored space separated with a space on both ends
+fn anki_tags(tags: &[String]) -> String {
+    if tags.is_empty() {
+        return String::new();
+    }
+
+    let tags: Vec<String> = tags
+        .iter()
+        .map(|tag| tag.split_whitespace().collect::<Vec<&str>>().join("_"))
+        .collect();
+
+    format!(" {} ", tags.join(" "))
+}
+
+// collection
+
+async fn read_collection(path: &std::path::Path) -> Result<AnkiCollection, sqlx::Error> {
+    let mut connection: SqliteConnection = SqliteConnectOptions::new()
+        .filename(path)
+        .read_only(true)
+        .connect()
+        .await?;
+
+    let (decks, models): (String, String) = sqlx::query_as("SELECT decks, models FROM col LIMIT 1")
+        .fetch_one(&mut connection)
+        .await?;
+
+    let mut deck_names = HashMap::new();
+    let mut field_names = HashMap::new();
+
+    // schema 11 keeps decks and note types as json in the col row
+    if let Ok(Value::Object(decks)) = serde_json::from_str::<Value>(&decks) {
+        for (id, deck) in decks {
+            if let (Some(id), Some(name)) = (parse_id(&id), deck["name"].as_str()) {
+                deck_names.insert(id, String::from(name));
+            }
+        }
+    }
+
+    if let Ok(Value::Object(models)) = serde_json::from_str::<Value>(&models) {
+        for (id, model) in models {
+            let names = model["flds"]
+                .as_array()
+                .map(|fields| {
+                    fields
+                        .iter()
+                        .filter_map(|field| field["name"].as_str().map(String::from))
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            if let Some(id) = parse_id(&id) {
+                field_names.insert(id, names);
+            }
+        }
+    }
+
+    // schema 18 moved them into tables
+    if deck_names.is_empty() {
+        let rows: Vec<(i64, String)> = sqlx::query_as("SELECT id, name FROM decks")
+            .fetch_all(&mut connection)
+            .await
+            .unwrap_or_default();
+
+        for (id, name) in rows {
+            deck_names.insert(id, name.replace(FIELD_SEPARATOR, "::"));
+        }
+    }
+
+    if field_names.is_empty() {
+        let rows: Vec<(i64, String)> =
+            sqlx::query_as("SELECT ntid, name FROM fields ORDER BY ntid, ord")
+                .fetch_all(&mut connection)
+                .await
+                .unwrap_or_default();
+
+        for (note_type_id, name) in rows {
+            field_names
+                .entry(note_type_id)
+                .or_insert_with(Vec::new)
+                .push(name);
+        }
+    }
+
+    // a note belongs to the deck of its first card
+    let rows: Vec<(i64, String, String, Option<i64>)> = sqlx::query_as(
+        "SELECT notes.mid, notes.flds, notes.tags, (SELECT cards.did FROM cards WHERE cards.nid = notes.id ORDER BY cards.ord LIMIT 1) FROM notes ORDER BY notes.id",
+    )
+    .fetch_all(&mut connection)
+    .await?;
+
+    connection.close().await?;
+
+    let notes = rows
+        .into_iter()
+        .map(|(note_type_id, fields, tags, deck_id)| AnkiNote {
+            note_type_id,
+            fields,
+            tags,
+            deck_id,
+        })
+        .collect();
+
+    Ok(AnkiCollection {
+        deck_names,
+        field_names,
+        notes,
+    })
+}
+
+// package
+
+// reads an .apkg upload into a pending import, fields map in order onto from_text,
+// to_text_primary, to_text_secondary and example_text
+pub async fn parse_apkg(data: Vec<u8>, user_id: i32) -> Result<PendingImport, String> {
+    let mut archive = ZipArchive::new(Cursor::new(data))
+        .map_err(|_| String::from("is not an Anki package (.apkg)"))?;
+
+    // its legacy collection is a placeholder, so it must not be read instead
+    if archive.by_name(LATEST_COLLECTION_FILE).is_ok() {
+        return Err(String::from(
+            "uses the latest Anki format, export it with \"Support older Anki versions\" enabled",
+        ));
+    }
+
+    let mut budget = ZipBudget::default();
+
+    let mut collection_data = None;
+
+    for name in COLLECTION_FILES {
+        collection_data = read_zip_entry(&mut archive, name, &mut budget)?;
+
+        if collection_data.is_some() {
+            break;
+        }
+    }
+
+    let collection_data = match collection_data {
+        Some(collection_data) => collection_data,
+        None => return Err(String::from("does not contain an Anki collection")),
+    };
+
+    // the media file maps the numbered files in the zip to their names, newer exports encode it
+    // differently and are imported without media
+    let media_names: HashMap<String, String> =
+        read_zip_entry(&mut archive, MEDIA_FILE, &mut budget)?
+            .and_then(|media| serde_json::from_slice::<Map<String, Value>>(&media).ok())
+            .map(|media| {
+                media
+                    .into_iter()
+                    .filter_map(|(file, name)| name.as_str().map(|name| (String::from(name), file)))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+    // sqlite needs a file to open
+    let path = std::env::temp_dir().join(format!("import-{}.anki2", rand::random::<u64>()));
+
+    tokio::fs::write(&path, collection_data)
+        .await
+        .map_err(|err| err.to_string())?;
+
+    let collection = read_collection(&path).await;
+
+    let _ = tokio::fs::remove_file(&path).await;
+
+    let collection =
+        collection.map_err(|err| format!("could not be read as an Anki collection: {}", err))?;
+
+    let mut pending_import = PendingImport::new(user_id, "apkg");
+    let mut deck_indexes: HashMap<Option<i64>, usize> = HashMap::new();
+
+    for note in collection.notes {
+        let raw_fields: Vec<&str> = note.fields.split(FIELD_SEPARATOR).collect();
+
+        let mut audio_file = None;
+
+        for raw_field in &raw_fields {
+            let (sounds, images) = media_references(raw_field);
+
+            for name in sounds.into_iter().chain(images) {
+                let supported = is_audio_file(&name) && media_names.contains_key(&name);
+
+                if supported && audio_file.is_none() {
+                    audio_file = Some(name);
+                } else if !supported && !pending_import.unsupported_media.contains(&name) {
+                    pending_import.unsupported_media.push(name);
+                }
+            }
+        }
+
+        let fields: Vec<String> = raw_fields
+            .iter()
+            .map(|raw_field| strip_html(&remove_sound_references(raw_field)))
+            .collect();
+
+        let deck_index = *deck_indexes.entry(note.deck_id).or_insert_with(|| {
+            let field_names = collection
+                .field_names
+                .get(&note.note_type_id)
+                .cloned()
+                .unwrap_or_default();
+
+            pending_import.decks.push(ImportDeck {
+                deck_id: None,
+                name: note
+                    .deck_id
+                    .and_then(|deck_id| collection.deck_names.get(&deck_id).cloned())
+                    .unwrap_or_else(|| String::from("Anki")),
+                from_language: optional_field(&field_names, 0)
+                    .unwrap_or_else(|| String::from("Front")),
+                to_language_primary: optional_field(&field_names, 1)
+                    .unwrap_or_else(|| String::from("Back")),
+                to_language_secondary: if fields.len() > 2 {
+                    optional_field(&field_names, 2)
+                } else {
+                    None
+                },
+                cards: Vec::new(),
+            });
+
+            pending_import.decks.len() - 1
+        });
+
+        // tag lists are comma separated here
+        let tags = note
+            .tags
+            .split_whitespace()
+            .map(|tag| tag.replace(',', " "))
+            .collect();
+
+        pending_import.add_card(
+            deck_index,
+            ImportCard {
+                from_text: fields.first().cloned().unwrap_or_default(),
+                to_text_primary: fields.get(1).cloned().unwrap_or_default(),
+                to_text_secondary: optional_field(&fields, 2),
+                example_text: optional_field(&fields, 3),
+                audio_file,
+                audio_url: None,
+                tags,
+            },
+            None,
+        );
+    }
+
+    // only the audio that cards point at is kept until the import is committed
+    let audio_files: Vec<String> = pending_import
+        .decks
+        .iter()
+        .flat_map(|deck| &deck.cards)
+        .filter_map(|card| card.audio_file.clone())
+        .collect();
+
+    for audio_file in audio_files {
+        if pending_import.media.contains_key(&audio_file) {
+            continue;
+        }
+
+        let data = match media_names.get(&audio_file) {
+            Some(file) => read_zip_entry(&mut archive, file, &mut budget)?,
+            None => None,
+        };
+
+        if let Some(data) = data {
+            pending_import.media.insert(audio_file, data);
+        }
+    }
+
+    Ok(pending_import)
+}
+
+// export
+
+struct ExportNote {
+    guid: String,
+    front: String,
+    back: String,
+    sort_field: String,
+    tags: String,
+}
+
+fn deck_json(id: i64, name: &str, now: i64) -> Value {
+    json!({
+        "id": id,
+        "name": name,
+        "mod": now,
+        "usn": -1,
+        "desc": "",
+        "dyn": 0,
+        "conf": DEFAULT_DECK_CONFIG_ID,
+        "collapsed": false,
+        "browserCollapsed": false,
+        "newToday": [0, 0],
+        "revToday": [0, 0],
+        "lrnToday": [0, 0],
+        "timeToday": [0, 0],
+        "extendNew": 0,
+        "extendRev": 0,
+    })
+}
+
+fn template_json(name: &str, ord: usize, question: &str, answer: &str) -> Value {
+    json!({
+        "name": name,
+        "ord": ord,
+        "qfmt": question,
+        "afmt": answer,
+        "bqfmt": "",
+        "bafmt": "",
+        "did": null,
+        "bfont": "",
+        "bsize": 0,
+    })
+}
+
+fn field_json(name: &str, ord: usize) -> Value {
+    json!({
+        "name": name,
+        "ord": ord,
+        "sticky": false,
+        "rtl": false,
+        "font": "Arial",
+        "size": 20,
+        "media": [],
+    })
+}
+
+// the basic note type with the front and back of a card, reversed adds a second card per note
+fn note_type_json(id: i64, deck_id: i64, reversed: bool, now: i64) -> Value {
+    let mut templates = vec![template_json(
+        "Card 1",
+        0,
+        "{{Front}}",
+        "{{FrontSide}}\n\n<hr id=answer>\n\n{{Back}}",
+    )];
+    let mut requirements = vec![json!([0, "any", [0]])];
+
+    if reversed {
+        templates.push(template_json(
+            "Card 2",
+            1,
+            "{{Back}}",
+            "{{FrontSide}}\n\n<hr id=answer>\n\n{{Front}}",
+        ));
+        requirements.push(json!([1, "any", [1]]));
+    }
+
+    json!({
+        "id": id,
+        "name": if reversed { "Basic (and reversed card)" } else { "Basic" },
+        "type": 0,
+        "mod": now,
+        "usn": -1,
+        "sortf": 0,
+        "did": deck_id,
+        "tmpls": templates,
+        "flds": [field_json("Front", 0), field_json("Back", 1)],
+        "css": ".card {\n    font-family: arial;\n    font-size: 20px;\n    text-align: center;\n    color: black;\n    background-color: white;\n}\n",
+        "latexPre": "\\documentclass[12pt]{article}\n\\special{papersize=3in,5in}\n\\usepackage[utf8]{inputenc}\n\\usepackage{amssymb,amsmath}\n\\pagestyle{empty}\n\\setlength{\\parindent}{0in}\n\\begin{document}\n",
+        "latexPost": "\\end{document}",
+        "latexsvg": false,
+        "req": requirements,
+        "tags": [],
+        "vers": [],
+    })
+}
+
+fn deck_config_json(now: i64) -> Value {
+    json!({
+        "id": DEFAULT_DECK_CONFIG_ID,
+        "name": "Default",
+        "mod": now,
+        "usn": -1,
+        "maxTaken": 60,
+        "autoplay": true,
+        "timer": 0,
+        "replayq": true,
+        "dyn": false,
+        "new": {
+            "delays": [1.0, 10.0],
+            "ints": [1, 4, 0],
+            "initialFactor": 2500,
+            "order": 1,
+            "perDay": 20,
+            "bury": false,
+        },
+        "rev": {
+            "perDay": 200,
+            "ease4": 1.3,
+            "ivlFct": 1.0,
+            "maxIvl": 36500,
+            "hardFactor": 1.2,
+            "bury": false,
+        },
+        "lapse": {
+            "delays": [10.0],
+            "mult": 0.0,
+            "minInt": 1,
+            "leechFails": 8,
+            "leechAction": 1,
+        },
+    })
+}
+
+fn collection_config_json(deck_id: i64, note_type_id: i64) -> Value {
+    json!({
+        "activeDecks": [deck_id],
+        "curDeck": deck_id,
+        "curModel": note_type_id,
+        "newSpread": 0,
+        "collapseTime": 1200,
+        "timeLim": 0,
+        "estTimes": true,
+        "dueCounts": true,
+        "sortType": "noteFld",
+        "sortBackwards": false,
+        "nextPos": 1,
+    })
+}
+
+async fn write_collection(
+    path: &std::path::Path,
+    deck_name: &str,
+    notes: &[ExportNote],
+    reversed: bool,
+) -> Result<(), sqlx::Error> {
+    let mut connection: SqliteConnection = SqliteConnectOptions::new()
+        .filename(path)
+        .create_if_missing(true)
+        .connect()
+        .await?;
+
+    connection.execute(EXPORT_SCHEMA).await?;
+
+    let now_ms = chrono::Utc::now().timestamp_millis();
+    let now = now_ms / 1000;
+
+    // ids are creation times in milliseconds in anki
+    let deck_id = now_ms;
+    let note_type_id = if reversed {
+        BASIC_REVERSED_NOTE_TYPE_ID
+    } else {
+        BASIC_NOTE_TYPE_ID
+    };
+
+    let decks = json!({
+        DEFAULT_DECK_ID.to_string(): deck_json(DEFAULT_DECK_ID, "Default", now),
+        deck_id.to_string(): deck_json(deck_id, deck_name, now),
+    });
+    let models = json!({
+        note_type_id.to_string(): note_type_json(note_type_id, deck_id, reversed, now),
+    });
+    let deck_configs = json!({
+        DEFAULT_DECK_CONFIG_ID.to_string(): deck_config_json(now),
+    });
+
+    let mut transaction = connection.begin().await?;
+
+    sqlx::query("INSERT INTO col VALUES (1, ?, ?, ?, ?, 0, 0, 0, ?, ?, ?, ?, '{}')")
+        .bind(now)
+        .bind(now_ms)
+        .bind(now_ms)
+        .bind(EXPORT_SCHEMA_VERSION)
+        .bind(collection_config_json(deck_id, note_type_id).to_string())
+        .bind(models.to_string())
+        .bind(decks.to_string())
+        .bind(deck_configs.to_string())
+        .execute(&mut *transaction)
+        .await?;
+
+    let num_templates = if reversed { 2 } else { 1 };
+
+    for (index, note) in notes.iter().enumerate() {
+        let note_id = now_ms + index as i64;
+
+        sqlx::query("INSERT INTO notes VALUES (?, ?, ?, ?, -1, ?, ?, ?, ?, 0, '')")
+            .bind(note_id)
+            .bind(&note.guid)
+            .bind(note_type_id)
+            .bind(now)
+            .bind(&note.tags)
+            .bind(format!("{}{}{}", note.front, FIELD_SEPARATOR, note.back))
+            .bind(&note.sort_field)
+            .bind(field_checksum(&note.sort_field))
+            .execute(&mut *transaction)
+            .await?;
+
+        // new cards, due in the order of the deck
+        for ord in 0..num_templates {
+            sqlx::query(
+                "INSERT INTO cards VALUES (?, ?, ?, ?, ?, -1, 0, 0, ?, 0, 0, 0, 0, 0, 0, 0, 0, '')",
+            )
+            .bind(now_ms + index as i64 * num_templates + ord)
+            .bind(note_id)
+            .bind(deck_id)
+            .bind(ord)
+            .bind(now)
+            .bind(index as i64 + 1)
+            .execute(&mut *transaction)
+            .await?;
+        }
+    }
+
+    transaction.commit().await?;
+    connection.close().await?;
+
+    Ok(())
+}
+
+// writes the cards of a deck into an .apkg with the basic note type, the front holds from_text
+// and the back the translations and example, audio stored in the assets goes along
+pub async fn write_apkg(
+    deck: &Deck,
+    cards: &[Card],
+    card_tags: &HashMap<i32, Vec<String>>,
+    reversed: bool,
+) -> Result<Vec<u8>, String> {
+    let mut media: Vec<(String, Vec<u8>)> = Vec::new();
+    let mut media_names: HashMap<String, String> = HashMap::new();
+    let mut notes = Vec::with_capacity(cards.len());
+
+    for card in cards {
+        let mut front = escape_html(&card.from_text);
+
+        if let Some(audio_url) = &card.audio_url {
+            if !media_names.contains_key(audio_url) {
+                if let Some(path) = local_media_path(audio_url) {
+                    if let Ok(data) = tokio::fs::read(&path).await {
+                        let name = path
+                            .file_name()
+                            .map(|name| name.to_string_lossy().into_owned())
+                            .unwrap_or_default();
+
+                        media_names.insert(audio_url.clone(), name.clone());
+                        media.push((name, data));
+                    }
+                }
+            }
+
+            if let Some(name) = media_names.get(audio_url) {
+                front.push_str(&format!(" [sound:{}]", name));
+            }
+        }
+
+        let mut back = escape_html(&card.to_text_primary);
+
+        if let Some(to_text_secondary) = &card.to_text_secondary {
+            back.push_str(&format!("<br>{}", escape_html(to_text_secondary)));
+        }
+
+        if let Some(example_text) = &card.example_text {
+            back.push_str(&format!("<br><br><i>{}</i>", escape_html(example_text)));
+        }
+
+        // anki sorts and finds duplicates by the first field as stored, without its html
+        let sort_field = strip_html(&front);
+
+        notes.push(ExportNote {
+            // stable across exports, so that importing again updates the notes
+            guid: format!("square-cards-{}", card.id),
+            front,
+            back,
+            sort_field,
+            tags: anki_tags(card_tags.get(&card.id).map_or(&[], Vec::as_slice)),
+        });
+    }
+
+    let deck_name = format!("{} - {}", deck.from_language, deck.to_language_primary);
+
+    // sqlite needs a file to write
+    let path = std::env::temp_dir().join(format!("export-{}.anki2", rand::random::<u64>()));
+
+    let collection = write_collection(&path, &deck_name, &notes, reversed).await;
+    let collection_data = match collection {
+        Ok(()) => tokio::fs::read(&path).await.map_err(|err| err.to_string()),
+        Err(err) => Err(format!("could not write the Anki collection: {}", err)),
+    };
+
+    let _ = tokio::fs::remove_file(&path).await;
+
+    let collection_data = collection_data?;
+
+    let mut archive = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    let media_map: Map<String, Value> = media
+        .iter()
+        .enumerate()
+        .map(|(index, (name, _))| (index.to_string(), Value::from(name.as_str())))
+        .collect();
+
+    let mut files = vec![
+        (String::from(EXPORT_COLLECTION_FILE), collection_data),
+        (
+            String::from(MEDIA_FILE),
+            Value::Object(media_map).to_string().into_bytes(),
+        ),
+    ];
+
+    // media is stored under its index in the media map
+    files.extend(
+        media
+            .into_iter()
+            .enumerate()
+            .map(|(index, (_, data))| (index.to_string(), data)),
+    );
+
+    for (name, data) in files {
+        archive
+            .start_file(name, options)
+            .and_then(|_| archive.write_all(&data).map_err(Into::into))
+            .map_err(|err| err.to_string())?;
+    }
+
+    let archive = archive.finish().map_err(|err| err.to_string())?;
+
+    Ok(archive.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imports::{card_fixture, deck_fixture, save_media_file};
+
+    // an sqlite collection built from the given statements
+    async fn collection_file(statements: &str) -> Vec<u8> {
+        let path = std::env::temp_dir().join(format!("test-{}.anki2", rand::random::<u64>()));
+
+        let mut connection: SqliteConnection = SqliteConnectOptions::new()
+            .filename(&path)
+            .create_if_missing(true)
+            .connect()
+            .await
+            .unwrap();
+
+        connection.execute(statements).await.unwrap();
+        connection.close().await.unwrap();
+
+        let data = tokio::fs::read(&path).await.unwrap();
+        let _ = tokio::fs::remove_file(&path).await;
+
+        data
+    }
+
+    fn package(files: Vec<(&str, Vec<u8>)>) -> Vec<u8> {
+        let mut archive = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+        for (name, data) in files {
+            archive.start_file(name, options).unwrap();
+            archive.write_all(&data).unwrap();
+        }
+
+        archive.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn media_references_finds_sounds_and_images() {
+        let (sounds, images) = media_references(
+            "hola [sound:hola.mp3] <img src=\"hola.png\"> <img src='mundo.jpg'> [sound:mundo.ogg]",
+        );
+
+        assert_eq!(sounds, vec!["hola.mp3", "mundo.ogg"]);
+        assert_eq!(images, vec!["hola.png", "mundo.jpg"]);
+        assert_eq!(media_references("hola"), (Vec::new(), Vec::new()));
+    }
+
+    #[test]
+    fn remove_sound_references_keeps_the_text() {
+        assert_eq!(
+            remove_sound_references("hola [sound:hola.mp3] mundo[sound:mundo.mp3]"),
+            "hola  mundo"
+        );
+        assert_eq!(remove_sound_references("hola [sound:hola.mp3"), "hola ");
+        assert_eq!(remove_sound_references("hola"), "hola");
+    }
+
+    #[tokio::test]
+    async fn parse_apkg_reads_schema_11_decks_and_fields() {
+        let decks = json!({
+            "1": { "name": "Default" },
+            "20": { "name": "Französisch" },
+        });
+        let models = json!({
+            "30": { "flds": [{ "name": "Französisch" }, { "name": "Deutsch" }] },
+        });
+
+        let collection = collection_file(&format!(
+            "{}
+            INSERT INTO col VALUES (1, 0, 0, 0, 11, 0, 0, 0, '{{}}', '{}', '{}', '{{}}', '{{}}');
+            INSERT INTO notes VALUES (1, 'a', 30, 0, -1, '', 'le chat\x1fdie Katze', 'le chat', 0, 0, '');
+            INSERT INTO cards VALUES (1, 1, 20, 0, 0, -1, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, '');",
+            EXPORT_SCHEMA, models, decks
+        ))
+        .await;
+
+        let pending_import = parse_apkg(package(vec![("collection.anki2", collection)]), 1)
+            .await
+            .unwrap();
+
+        assert_eq!(pending_import.decks.len(), 1);
+
+        let deck = &pending_import.decks[0];
+
+        assert_eq!(deck.name, "Französisch");
+        assert_eq!(deck.from_language, "Französisch");
+        assert_eq!(deck.to_language_primary, "Deutsch");
+        assert_eq!(deck.to_language_secondary, None);
+        assert_eq!(deck.cards.len(), 1);
+        assert_eq!(deck.cards[0].from_text, "le chat");
+        assert_eq!(deck.cards[0].to_text_primary, "die Katze");
+    }
+
+    #[tokio::test]
+    async fn parse_apkg_reads_schema_18_decks_and_fields() {
+        let collection = collection_file(
+            "CREATE TABLE col (decks text, models text);
+            CREATE TABLE decks (id integer, name text);
+            CREATE TABLE fields (ntid integer, ord integer, name text);
+            CREATE TABLE notes (id integer, mid integer, flds text, tags text);
+            CREATE TABLE cards (id integer, nid integer, did integer, ord integer);
+            INSERT INTO col VALUES ('', '');
+            INSERT INTO decks VALUES (10, 'Spanisch\x1fVerben'), (11, 'Spanisch');
+            INSERT INTO fields VALUES (40, 1, 'Deutsch'), (40, 0, 'Spanisch'), (40, 2, 'Extra'), (40, 3, 'Beispiel');
+            INSERT INTO notes VALUES
+                (1, 40, 'hablar [sound:hablar.mp3]\x1f<b>sprechen</b>\x1f\x1fYo hablo.', ' verbos rápido,fácil '),
+                (2, 40, 'comer <img src=\"comer.png\">\x1fessen\x1f\x1f', '');
+            INSERT INTO cards VALUES (100, 1, 11, 1), (101, 1, 10, 0), (102, 2, 10, 0);",
+        )
+        .await;
+
+        let data = package(vec![
+            ("collection.anki21", collection),
+            (
+                "media",
+                json!({ "0": "hablar.mp3" }).to_string().into_bytes(),
+            ),
+            ("0", b"ID3".to_vec()),
+        ]);
+
+        let pending_import = parse_apkg(data, 1).await.unwrap();
+
+        // both notes go to the deck of their first card
+        assert_eq!(pending_import.decks.len(), 1);
+
+        let deck = &pending_import.decks[0];
+
+        assert_eq!(deck.name, "Spanisch::Verben");
+        assert_eq!(deck.from_language, "Spanisch");
+        assert_eq!(deck.to_language_primary, "Deutsch");
+        assert_eq!(deck.to_language_secondary.as_deref(), Some("Extra"));
+        assert_eq!(deck.cards.len(), 2);
+
+        let card = &deck.cards[0];
+
+        assert_eq!(card.from_text, "hablar");
+        assert_eq!(card.to_text_primary, "sprechen");
+        assert_eq!(card.to_text_secondary, None);
+        assert_eq!(card.example_text.as_deref(), Some("Yo hablo."));
+        assert_eq!(card.audio_file.as_deref(), Some("hablar.mp3"));
+        assert_eq!(card.tags, vec!["verbos", "rápido fácil"]);
+
+        assert_eq!(deck.cards[1].from_text, "comer");
+        assert_eq!(deck.cards[1].audio_file, None);
+        assert_eq!(pending_import.unsupported_media, vec!["comer.png"]);
+        assert_eq!(
+            pending_import.media.get("hablar.mp3"),
+            Some(&b"ID3".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn parse_apkg_rejects_the_latest_format() {
+        let data = package(vec![
+            ("collection.anki21b", vec![0; 16]),
+            ("collection.anki2", collection_file(EXPORT_SCHEMA).await),
+        ]);
+
+        let err = parse_apkg(data, 1).await.err().unwrap();
+
+        assert!(err.contains("Support older Anki versions"));
+    }
+
+    #[tokio::test]
+    async fn write_apkg_round_trip_keeps_cards_tags_and_audio() {
+        let audio_data = b"apkg round trip audio".to_vec();
+        let audio_url = save_media_file("apkg-round-trip-test.mp3", &audio_data)
+            .await
+            .unwrap();
+
+        let cards = vec![
+            Card {
+                audio_url: Some(audio_url.clone()),
+                ..card_fixture(7, "Hund & <Katze>", "dog & cat")
+            },
+            card_fixture(9, "Maus", "mouse"),
+        ];
+        let card_tags = HashMap::from([(7, vec![String::from("a1"), String::from("two words")])]);
+
+        let data = write_apkg(&deck_fixture("Deutsch"), &cards, &card_tags, true)
+            .await
+            .unwrap();
+
+        let _ = tokio::fs::remove_file(local_media_path(&audio_url).unwrap()).await;
+
+        // anki sorts and checksums the stored first field without its html
+        let mut archive = ZipArchive::new(Cursor::new(data.clone())).unwrap();
+        let collection = read_zip_entry(
+            &mut archive,
+            EXPORT_COLLECTION_FILE,
+            &mut ZipBudget::default(),
+        )
+        .unwrap()
+        .unwrap();
+        let path = std::env::temp_dir().join(format!("test-{}.anki2", rand::random::<u64>()));
+        tokio::fs::write(&path, collection).await.unwrap();
+
+        let mut connection: SqliteConnection = SqliteConnectOptions::new()
+            .filename(&path)
+            .connect()
+            .await
+            .unwrap();
+        let notes: Vec<(String, String, i64)> =
+            sqlx::query_as("SELECT flds, sfld, csum FROM notes ORDER BY id")
+                .fetch_all(&mut connection)
+                .await
+                .unwrap();
+        let num_cards: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM cards")
+            .fetch_one(&mut connection)
+            .await
+            .unwrap();
+        connection.close().await.unwrap();
+        let _ = tokio::fs::remove_file(&path).await;
+
+        let (fields, sort_field, checksum) = &notes[0];
+
+        assert!(fields.starts_with("Hund &amp; &lt;Katze&gt; [sound:"));
+        assert!(sort_field.starts_with("Hund & <Katze> [sound:"));
+        assert_eq!(*checksum, field_checksum(sort_field));
+        assert_eq!(notes[1].1, "Maus");
+        assert_eq!(num_cards.0, 4);
+
+        // a reversed note has two cards, it is imported once
+        let pending_import = parse_apkg(data, 1).await.unwrap();
+
+        assert_eq!(pending_import.decks.len(), 1);
+
+        let deck = &pending_import.decks[0];
+
+        assert_eq!(deck.name, "Deutsch - English");
+        assert_eq!(deck.from_language, "Front");
+        assert_eq!(deck.to_language_primary, "Back");
+        assert_eq!(deck.cards.len(), 2);
+
+        let card = &deck.cards[0];
+
+        assert_eq!(card.from_text, "Hund & <Katze>");
+        assert_eq!(card.to_text_primary, "dog & cat");
+        assert_eq!(card.tags, vec!["a1", "two_words"]);
+
+        let audio_file = card.audio_file.as_ref().unwrap();
+
+        assert_eq!(pending_import.media.get(audio_file), Some(&audio_data));
+        assert_eq!(deck.cards[1].from_text, "Maus");
+        assert_eq!(deck.cards[1].audio_file, None);
+        assert!(deck.cards[1].tags.is_empty());
+    }
+}
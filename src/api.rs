@@ -1,17 +1,60 @@
+use crate::anki::{parse_apkg, write_apkg};
+use crate::bundles::{read_bundle, save_bundle_media, write_bundle};
+use crate::duplicates::{find_duplicates, fold_text, normalize_text, DUPLICATE_POLICY_ALLOW};
+use crate::events::{publish_event, reset_sse_event, EventType};
+use crate::exports::{stream_export, ExportFormat};
+use crate::imports::{read_import_preview, save_import_media, store_import, take_import};
+use crate::kindle::parse_kindle;
+use crate::paste::parse_paste;
+use crate::printing::split_list;
 use crate::queries::{
-    create_card_query, create_deck_query, create_user_query, delete_card_query, delete_deck_query,
-    delete_user_query, read_card_query, read_cards_query, read_deck, read_decks_query, read_user,
-    read_users_query, update_card_query, update_deck_query, update_user_query,
+    clone_deck_query, copy_cards_query, create_card_query, create_card_relation_query,
+    create_deck_query, create_user_query, delete_card_query, delete_card_relation_query,
+    delete_deck_query, delete_user_query, move_cards_query, read_card_query,
+    read_card_relations_query, read_cards_query, read_deck, read_decks_query, read_user,
+    read_user_card_query, read_users_query, update_card_query, update_deck_query,
+    update_user_query, DatabaseQueryResult,
 };
-use crate::{AppState, CardForm, DeckForm, UserForm};
-use axum::extract::{Path, Query, State};
-use axum::http::StatusCode;
+use crate::queries::{create_bundle_query, read_bundle_records_query};
+use crate::queries::{
+    create_deck_import_query, create_import_query, create_reviews_query, read_card_revisions_query,
+    read_deck_card_tags_query, read_deck_overviews_query, read_deck_texts_query,
+    read_sync_changes_query, restore_card_revision_query, undo_last_review_query,
+};
+use crate::queries::{
+    create_tag_query, delete_tag_query, read_card_tags_query, read_tag_cards_query, read_tag_query,
+    read_tags_query, split_tag_names, update_tag_query,
+};
+use crate::queries::{
+    create_webhook_query, delete_webhook_query, read_webhook_deliveries_query, read_webhook_query,
+    read_webhooks_query, update_webhook_query,
+};
+use crate::spreadsheet::parse_csv;
+use crate::study::{
+    advance_study_session, end_study_session, read_study_session, read_study_session_card,
+    rewind_study_session, start_study_session,
+};
+use crate::subtitles::parse_subtitles;
+use crate::sync::apply_sync_form;
+use crate::validation::{Validate, ValidationErrors};
+use crate::{
+    AppState, Card, CardForm, CardRelationForm, CardRevisionRestoreForm, CardTransferForm,
+    CreatedWebhook, CsvImportForm, DeckCloneForm, DeckForm, ImportCommitForm, PasteImportForm,
+    ReviewBatchForm, StudyRatingForm, StudySessionForm, SyncForm, TagForm, UserForm, WebhookForm,
+};
+use axum::extract::{Multipart, Path, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
 use axum::{Form, Json};
 use serde::Serialize;
 use serde_json::{json, Value};
 use sqlx::Error;
 use std::collections::HashMap;
+use std::convert::Infallible;
 use std::sync::Arc;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
 
 // TODO: make mutually exclusive enum
 
@@ -24,6 +67,8 @@ struct ApiResponse<T: Serialize> {
 #[derive(serde::Serialize)]
 struct ApiResponseError {
     message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fields: Option<ValidationErrors>,
 }
 
 // helpers
@@ -38,6 +83,7 @@ fn db_result_to_json_response<T: Serialize>(result: Result<T, Error>) -> Json<Va
             data: None,
             error: Some(ApiResponseError {
                 message: format!("{}", err),
+                fields: None,
             }),
         },
     };
@@ -45,6 +91,52 @@ fn db_result_to_json_response<T: Serialize>(result: Result<T, Error>) -> Json<Va
     Json(json!(response))
 }
 
+fn validation_errors_to_json_response(errors: ValidationErrors) -> Json<Value> {
+    let response: ApiResponse<()> = ApiResponse {
+        data: None,
+        error: Some(ApiResponseError {
+            message: String::from("Validation failed"),
+            fields: Some(errors),
+        }),
+    };
+
+    Json(json!(response))
+}
+
+// reads ?include=cards,stats
+fn parse_include(query: &HashMap<String, String>) -> Result<(bool, bool), StatusCode> {
+    let mut include_cards = false;
+    let mut include_stats = false;
+
+    if let Some(include) = query.get("include") {
+        for part in include.split(',').map(str::trim) {
+            match part {
+                "cards" => include_cards = true,
+                "stats" => include_stats = true,
+                "" => {}
+                _ => return Err(StatusCode::BAD_REQUEST),
+            }
+        }
+    }
+
+    Ok((include_cards, include_stats))
+}
+
+fn db_row_result_to_json_response<T: Serialize>(
+    result: Result<Option<T>, Error>,
+) -> Result<Json<Value>, StatusCode> {
+    match result {
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Ok(Some(data)) => Ok(db_result_to_json_response(Ok(data))),
+        Err(err) => Ok(db_result_to_json_response::<T>(Err(err))),
+    }
+}
+
+// create endpoints answer with the affected rows as before, the created row only goes to the events
+fn created_result_to_json_response<T>(result: Result<T, Error>) -> Json<Value> {
+    db_result_to_json_response(result.map(|_| DatabaseQueryResult { rows_affected: 1 }))
+}
+
 // api route handlers
 
 pub async fn get_users(
@@ -73,7 +165,7 @@ pub async fn get_user(
 
     let result = read_user(&app_state.pool, user_id).await;
 
-    Ok(db_result_to_json_response(result))
+    db_row_result_to_json_response(result)
 }
 
 pub async fn post_user(
@@ -86,9 +178,14 @@ pub async fn post_user(
         return Err(StatusCode::UNAUTHORIZED);
     }
 
+    let user_form = match user_form.validate(true) {
+        Ok(user_form) => user_form,
+        Err(errors) => return Ok(validation_errors_to_json_response(errors)),
+    };
+
     let result = create_user_query(&app_state.pool, user_form).await;
 
-    Ok(db_result_to_json_response(result))
+    Ok(created_result_to_json_response(result))
 }
 
 pub async fn put_user(
@@ -102,6 +199,11 @@ pub async fn put_user(
         return Err(StatusCode::UNAUTHORIZED);
     }
 
+    let user_form = match user_form.validate(false) {
+        Ok(user_form) => user_form,
+        Err(errors) => return Ok(validation_errors_to_json_response(errors)),
+    };
+
     let result = update_user_query(&app_state.pool, user_id, user_form).await;
 
     Ok(db_result_to_json_response(result))
@@ -131,6 +233,21 @@ pub async fn get_decks(
         return Err(StatusCode::UNAUTHORIZED);
     }
 
+    let (include_cards, include_stats) = parse_include(&query)?;
+
+    if include_cards || include_stats {
+        let result = read_deck_overviews_query(
+            &app_state.pool,
+            app_state.user.as_ref().unwrap().id,
+            None,
+            include_cards,
+            include_stats,
+        )
+        .await;
+
+        return Ok(db_result_to_json_response(result));
+    }
+
     let result = read_decks_query(&app_state.pool, app_state.user.as_ref().unwrap().id).await;
 
     Ok(db_result_to_json_response(result))
@@ -146,6 +263,22 @@ pub async fn get_deck(
         return Err(StatusCode::UNAUTHORIZED);
     }
 
+    let (include_cards, include_stats) = parse_include(&query)?;
+
+    if include_cards || include_stats {
+        let result = read_deck_overviews_query(
+            &app_state.pool,
+            app_state.user.as_ref().unwrap().id,
+            Some(deck_id),
+            include_cards,
+            include_stats,
+        )
+        .await
+        .map(|overviews| overviews.into_iter().next());
+
+        return db_row_result_to_json_response(result);
+    }
+
     let result = read_deck(
         &app_state.pool,
         deck_id,
@@ -153,7 +286,7 @@ pub async fn get_deck(
     )
     .await;
 
-    Ok(db_result_to_json_response(result))
+    db_row_result_to_json_response(result)
 }
 
 pub async fn post_deck(
@@ -166,14 +299,20 @@ pub async fn post_deck(
         return Err(StatusCode::UNAUTHORIZED);
     }
 
-    let result = create_deck_query(
-        &app_state.pool,
-        deck_form,
-        app_state.user.as_ref().unwrap().id,
-    )
-    .await;
+    let deck_form = match deck_form.validate(true) {
+        Ok(deck_form) => deck_form,
+        Err(errors) => return Ok(validation_errors_to_json_response(errors)),
+    };
 
-    Ok(db_result_to_json_response(result))
+    let user_id = app_state.user.as_ref().unwrap().id;
+
+    let result = create_deck_query(&app_state.pool, deck_form, user_id).await;
+
+    if let Ok(deck) = &result {
+        publish_event(&app_state, user_id, EventType::DeckCreated, deck).await;
+    }
+
+    Ok(created_result_to_json_response(result))
 }
 
 pub async fn put_deck(
@@ -187,18 +326,273 @@ pub async fn put_deck(
         return Err(StatusCode::UNAUTHORIZED);
     }
 
-    let result = update_deck_query(
+    let deck_form = match deck_form.validate(false) {
+        Ok(deck_form) => deck_form,
+        Err(errors) => return Ok(validation_errors_to_json_response(errors)),
+    };
+
+    let user_id = app_state.user.as_ref().unwrap().id;
+
+    let result = update_deck_query(&app_state.pool, deck_id, deck_form, user_id).await;
+
+    if matches!(&result, Ok(query_result) if query_result.rows_affected > 0) {
+        if let Ok(Some(deck)) = read_deck(&app_state.pool, deck_id, user_id).await {
+            publish_event(&app_state, user_id, EventType::DeckUpdated, &deck).await;
+        }
+    }
+
+    Ok(db_result_to_json_response(result))
+}
+
+pub async fn delete_deck(
+    State(app_state): State<Arc<AppState>>,
+    Path(deck_id): Path<i32>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let user_id = app_state.user.as_ref().unwrap().id;
+
+    let deck = read_deck(&app_state.pool, deck_id, user_id).await;
+
+    let result = delete_deck_query(&app_state.pool, deck_id, user_id).await;
+
+    if let (Ok(Some(deck)), Ok(query_result)) = (&deck, &result) {
+        if query_result.rows_affected > 0 {
+            publish_event(&app_state, user_id, EventType::DeckDeleted, deck).await;
+        }
+    }
+
+    Ok(db_result_to_json_response(result))
+}
+
+pub async fn post_deck_clone(
+    State(app_state): State<Arc<AppState>>,
+    Path(deck_id): Path<i32>,
+    Query(query): Query<HashMap<String, String>>,
+    Form(deck_clone_form): Form<DeckCloneForm>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let user_id = app_state.user.as_ref().unwrap().id;
+
+    let result = clone_deck_query(&app_state.pool, deck_id, deck_clone_form, user_id).await;
+
+    if let Ok(Some(deck)) = &result {
+        publish_event(&app_state, user_id, EventType::DeckCreated, deck).await;
+    }
+
+    db_row_result_to_json_response(result)
+}
+
+pub async fn get_deck_apkg_export(
+    State(app_state): State<Arc<AppState>>,
+    Path(deck_id): Path<i32>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Response, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let reversed = match query.get("reversed").map(String::as_str) {
+        None | Some("false") => false,
+        Some("true") => true,
+        Some(_) => return Err(StatusCode::BAD_REQUEST),
+    };
+
+    let deck = read_deck(
         &app_state.pool,
         deck_id,
-        deck_form,
         app_state.user.as_ref().unwrap().id,
     )
-    .await;
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .ok_or(StatusCode::NOT_FOUND)?;
 
-    Ok(db_result_to_json_response(result))
+    let cards = read_cards_query(&app_state.pool, deck_id, &[])
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let card_tags = read_deck_card_tags_query(&app_state.pool, deck_id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let package = write_apkg(&deck, &cards, &card_tags, reversed)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let headers = [
+        (
+            header::CONTENT_TYPE,
+            String::from("application/octet-stream"),
+        ),
+        (
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"deck-{}.apkg\"", deck.id),
+        ),
+    ];
+
+    Ok((headers, package).into_response())
 }
 
-pub async fn delete_deck(
+// shared by the csv and json exports of a deck or of all decks
+async fn export_response(
+    app_state: &AppState,
+    deck_id: Option<i32>,
+    format: ExportFormat,
+) -> Result<Response, StatusCode> {
+    let user_id = app_state.user.as_ref().unwrap().id;
+
+    let decks = match deck_id {
+        Some(deck_id) => read_deck(&app_state.pool, deck_id, user_id)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+            .map(|deck| vec![deck])
+            .ok_or(StatusCode::NOT_FOUND)?,
+        None => {
+            let mut decks = read_decks_query(&app_state.pool, user_id)
+                .await
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+            decks.sort_by_key(|deck| deck.id);
+            decks
+        }
+    };
+
+    let file_name = match deck_id {
+        Some(deck_id) => format!("deck-{}.{}", deck_id, format.extension()),
+        None => format!("decks.{}", format.extension()),
+    };
+
+    let headers = [
+        (header::CONTENT_TYPE, String::from(format.content_type())),
+        (
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", file_name),
+        ),
+    ];
+
+    let body = stream_export(&app_state.pool, user_id, deck_id, decks, format);
+
+    Ok((headers, body).into_response())
+}
+
+pub async fn get_deck_csv_export(
+    State(app_state): State<Arc<AppState>>,
+    Path(deck_id): Path<i32>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Response, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    export_response(&app_state, Some(deck_id), ExportFormat::Csv).await
+}
+
+pub async fn get_deck_json_export(
+    State(app_state): State<Arc<AppState>>,
+    Path(deck_id): Path<i32>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Response, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    export_response(&app_state, Some(deck_id), ExportFormat::Json).await
+}
+
+pub async fn get_csv_export(
+    State(app_state): State<Arc<AppState>>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Response, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    export_response(&app_state, None, ExportFormat::Csv).await
+}
+
+pub async fn get_json_export(
+    State(app_state): State<Arc<AppState>>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Response, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    export_response(&app_state, None, ExportFormat::Json).await
+}
+
+// shared by the bundles of a deck and of all decks
+async fn bundle_response(
+    app_state: &AppState,
+    deck_id: Option<i32>,
+) -> Result<Response, StatusCode> {
+    let records = read_bundle_records_query(
+        &app_state.pool,
+        app_state.user.as_ref().unwrap().id,
+        deck_id,
+    )
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    let bundle = write_bundle(records)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let file_name = match deck_id {
+        Some(deck_id) => format!("deck-{}.bundle", deck_id),
+        None => String::from("decks.bundle"),
+    };
+
+    let headers = [
+        (header::CONTENT_TYPE, String::from("application/zip")),
+        (
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", file_name),
+        ),
+    ];
+
+    Ok((headers, bundle).into_response())
+}
+
+pub async fn get_deck_bundle_export(
+    State(app_state): State<Arc<AppState>>,
+    Path(deck_id): Path<i32>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Response, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    bundle_response(&app_state, Some(deck_id)).await
+}
+
+pub async fn get_bundle_export(
+    State(app_state): State<Arc<AppState>>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Response, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    bundle_response(&app_state, None).await
+}
+
+pub async fn get_deck_duplicates(
     State(app_state): State<Arc<AppState>>,
     Path(deck_id): Path<i32>,
     Query(query): Query<HashMap<String, String>>,
@@ -208,12 +602,20 @@ pub async fn delete_deck(
         return Err(StatusCode::UNAUTHORIZED);
     }
 
-    let result = delete_deck_query(
+    let ignore_articles = ignore_articles_query(&query)?;
+
+    let deck = read_deck(
         &app_state.pool,
         deck_id,
         app_state.user.as_ref().unwrap().id,
     )
-    .await;
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    let result = read_cards_query(&app_state.pool, deck_id, &[])
+        .await
+        .map(|cards| find_duplicates(cards, &deck.from_language, ignore_articles));
 
     Ok(db_result_to_json_response(result))
 }
@@ -228,7 +630,64 @@ pub async fn get_cards(
         return Err(StatusCode::UNAUTHORIZED);
     }
 
-    let result = read_cards_query(&app_state.pool, deck_id).await;
+    let tags = split_tag_names(query.get("tags").map_or("", String::as_str));
+
+    let result = read_cards_query(&app_state.pool, deck_id, &tags).await;
+
+    Ok(db_result_to_json_response(result))
+}
+
+// ignore_articles is true unless it is given as false
+fn ignore_articles_query(query: &HashMap<String, String>) -> Result<bool, StatusCode> {
+    match query.get("ignore_articles").map(String::as_str) {
+        None | Some("true") => Ok(true),
+        Some("false") => Ok(false),
+        Some(_) => Err(StatusCode::BAD_REQUEST),
+    }
+}
+
+// cards of the deck whose front text equals from_text once normalised, to warn before adding it
+pub async fn get_card_duplicates(
+    State(app_state): State<Arc<AppState>>,
+    Path(deck_id): Path<i32>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let ignore_articles = ignore_articles_query(&query)?;
+
+    let deck = read_deck(
+        &app_state.pool,
+        deck_id,
+        app_state.user.as_ref().unwrap().id,
+    )
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    let key = normalize_text(
+        query.get("from_text").map_or("", String::as_str),
+        &deck.from_language,
+        ignore_articles,
+    );
+
+    if key.is_empty() {
+        return Ok(db_result_to_json_response(Ok(Vec::<Card>::new())));
+    }
+
+    let result = read_cards_query(&app_state.pool, deck_id, &[])
+        .await
+        .map(|cards| {
+            cards
+                .into_iter()
+                .filter(|card| {
+                    normalize_text(&card.from_text, &deck.from_language, ignore_articles) == key
+                })
+                .collect::<Vec<Card>>()
+        });
 
     Ok(db_result_to_json_response(result))
 }
@@ -245,7 +704,7 @@ pub async fn get_card(
 
     let result = read_card_query(&app_state.pool, ids.0, ids.1).await;
 
-    Ok(db_result_to_json_response(result))
+    db_row_result_to_json_response(result)
 }
 
 pub async fn post_card(
@@ -259,9 +718,19 @@ pub async fn post_card(
         return Err(StatusCode::UNAUTHORIZED);
     }
 
+    let card_form = match card_form.validate(true) {
+        Ok(card_form) => card_form,
+        Err(errors) => return Ok(validation_errors_to_json_response(errors)),
+    };
+
     let result = create_card_query(&app_state.pool, deck_id, card_form).await;
 
-    Ok(db_result_to_json_response(result))
+    if let Ok(card) = &result {
+        let user_id = app_state.user.as_ref().unwrap().id;
+        publish_event(&app_state, user_id, EventType::CardCreated, card).await;
+    }
+
+    Ok(created_result_to_json_response(result))
 }
 
 pub async fn put_card(
@@ -275,8 +744,26 @@ pub async fn put_card(
         return Err(StatusCode::UNAUTHORIZED);
     }
 
+    let card_form = match card_form.validate(false) {
+        Ok(card_form) => card_form,
+        Err(errors) => return Ok(validation_errors_to_json_response(errors)),
+    };
+
+    let event_type = if card_form.rating.is_some() {
+        EventType::CardRated
+    } else {
+        EventType::CardUpdated
+    };
+
     let result = update_card_query(&app_state.pool, ids.0, ids.1, card_form).await;
 
+    if matches!(&result, Ok(query_result) if query_result.rows_affected > 0) {
+        if let Ok(Some(card)) = read_card_query(&app_state.pool, ids.0, ids.1).await {
+            let user_id = app_state.user.as_ref().unwrap().id;
+            publish_event(&app_state, user_id, event_type, &card).await;
+        }
+    }
+
     Ok(db_result_to_json_response(result))
 }
 
@@ -290,7 +777,1440 @@ pub async fn delete_card(
         return Err(StatusCode::UNAUTHORIZED);
     }
 
+    let card = read_card_query(&app_state.pool, ids.0, ids.1).await;
+
     let result = delete_card_query(&app_state.pool, ids.0, ids.1).await;
 
+    if let (Ok(Some(card)), Ok(query_result)) = (&card, &result) {
+        if query_result.rows_affected > 0 {
+            let user_id = app_state.user.as_ref().unwrap().id;
+            publish_event(&app_state, user_id, EventType::CardDeleted, card).await;
+        }
+    }
+
     Ok(db_result_to_json_response(result))
 }
+
+async fn user_owns_decks(app_state: &AppState, deck_ids: &[i32]) -> Result<bool, Error> {
+    for deck_id in deck_ids {
+        let deck = read_deck(
+            &app_state.pool,
+            *deck_id,
+            app_state.user.as_ref().unwrap().id,
+        )
+        .await?;
+
+        if deck.is_none() {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+pub async fn post_cards_move(
+    State(app_state): State<Arc<AppState>>,
+    Path(deck_id): Path<i32>,
+    Query(query): Query<HashMap<String, String>>,
+    Form(card_transfer_form): Form<CardTransferForm>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let card_transfer_form = match card_transfer_form.validate(true) {
+        Ok(card_transfer_form) => card_transfer_form,
+        Err(errors) => return Ok(validation_errors_to_json_response(errors)),
+    };
+
+    match user_owns_decks(&app_state, &[deck_id, card_transfer_form.target_deck_id]).await {
+        Ok(true) => {}
+        Ok(false) => return Err(StatusCode::NOT_FOUND),
+        Err(err) => return Ok(db_result_to_json_response::<()>(Err(err))),
+    }
+
+    let result = move_cards_query(
+        &app_state.pool,
+        deck_id,
+        card_transfer_form.target_deck_id,
+        card_transfer_form.card_ids,
+    )
+    .await;
+
+    if let Ok(Some(cards)) = &result {
+        let user_id = app_state.user.as_ref().unwrap().id;
+        for card in cards {
+            publish_event(&app_state, user_id, EventType::CardUpdated, card).await;
+        }
+    }
+
+    db_row_result_to_json_response(result)
+}
+
+pub async fn post_cards_undo(
+    State(app_state): State<Arc<AppState>>,
+    Path(deck_id): Path<i32>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    match user_owns_decks(&app_state, &[deck_id]).await {
+        Ok(true) => {}
+        Ok(false) => return Err(StatusCode::NOT_FOUND),
+        Err(err) => return Ok(db_result_to_json_response::<()>(Err(err))),
+    }
+
+    let result = undo_last_review_query(&app_state.pool, deck_id, None).await;
+
+    if let Ok(Some(card)) = &result {
+        let user_id = app_state.user.as_ref().unwrap().id;
+        publish_event(&app_state, user_id, EventType::CardRated, card).await;
+    }
+
+    db_row_result_to_json_response(result)
+}
+
+pub async fn post_cards_copy(
+    State(app_state): State<Arc<AppState>>,
+    Path(deck_id): Path<i32>,
+    Query(query): Query<HashMap<String, String>>,
+    Form(card_transfer_form): Form<CardTransferForm>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let card_transfer_form = match card_transfer_form.validate(true) {
+        Ok(card_transfer_form) => card_transfer_form,
+        Err(errors) => return Ok(validation_errors_to_json_response(errors)),
+    };
+
+    match user_owns_decks(&app_state, &[deck_id, card_transfer_form.target_deck_id]).await {
+        Ok(true) => {}
+        Ok(false) => return Err(StatusCode::NOT_FOUND),
+        Err(err) => return Ok(db_result_to_json_response::<()>(Err(err))),
+    }
+
+    let result = copy_cards_query(
+        &app_state.pool,
+        deck_id,
+        card_transfer_form.target_deck_id,
+        card_transfer_form.card_ids,
+    )
+    .await;
+
+    if let Ok(Some(cards)) = &result {
+        let user_id = app_state.user.as_ref().unwrap().id;
+        for card in cards {
+            publish_event(&app_state, user_id, EventType::CardCreated, card).await;
+        }
+    }
+
+    db_row_result_to_json_response(result)
+}
+
+async fn user_owns_card(app_state: &AppState, deck_id: i32, card_id: i32) -> Result<bool, Error> {
+    if !user_owns_decks(app_state, &[deck_id]).await? {
+        return Ok(false);
+    }
+
+    let card = read_card_query(&app_state.pool, deck_id, card_id).await?;
+
+    Ok(card.is_some())
+}
+
+pub async fn get_card_relations(
+    State(app_state): State<Arc<AppState>>,
+    Path(ids): Path<(i32, i32)>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    match user_owns_card(&app_state, ids.0, ids.1).await {
+        Ok(true) => {}
+        Ok(false) => return Err(StatusCode::NOT_FOUND),
+        Err(err) => return Ok(db_result_to_json_response::<()>(Err(err))),
+    }
+
+    let result = read_card_relations_query(&app_state.pool, ids.1).await;
+
+    Ok(db_result_to_json_response(result))
+}
+
+pub async fn post_card_relation(
+    State(app_state): State<Arc<AppState>>,
+    Path(ids): Path<(i32, i32)>,
+    Query(query): Query<HashMap<String, String>>,
+    Form(card_relation_form): Form<CardRelationForm>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let card_relation_form = match card_relation_form.validate(true) {
+        Ok(card_relation_form) => card_relation_form,
+        Err(errors) => return Ok(validation_errors_to_json_response(errors)),
+    };
+
+    if card_relation_form.related_card_id == ids.1 {
+        let mut errors = ValidationErrors::default();
+        errors.add(
+            "related_card_id",
+            String::from("must not be the card itself"),
+        );
+        return Ok(validation_errors_to_json_response(errors));
+    }
+
+    match user_owns_card(&app_state, ids.0, ids.1).await {
+        Ok(true) => {}
+        Ok(false) => return Err(StatusCode::NOT_FOUND),
+        Err(err) => return Ok(db_result_to_json_response::<()>(Err(err))),
+    }
+
+    let related_card = read_user_card_query(
+        &app_state.pool,
+        card_relation_form.related_card_id,
+        app_state.user.as_ref().unwrap().id,
+    )
+    .await;
+
+    match related_card {
+        Ok(Some(_)) => {}
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(err) => return Ok(db_result_to_json_response::<()>(Err(err))),
+    }
+
+    let result = create_card_relation_query(&app_state.pool, ids.1, card_relation_form).await;
+
+    Ok(db_result_to_json_response(result))
+}
+
+pub async fn delete_card_relation(
+    State(app_state): State<Arc<AppState>>,
+    Path(ids): Path<(i32, i32, i32)>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    match user_owns_card(&app_state, ids.0, ids.1).await {
+        Ok(true) => {}
+        Ok(false) => return Err(StatusCode::NOT_FOUND),
+        Err(err) => return Ok(db_result_to_json_response::<()>(Err(err))),
+    }
+
+    let result = delete_card_relation_query(&app_state.pool, ids.1, ids.2).await;
+
+    Ok(db_result_to_json_response(result))
+}
+
+pub async fn get_card_revisions(
+    State(app_state): State<Arc<AppState>>,
+    Path(ids): Path<(i32, i32)>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    match user_owns_card(&app_state, ids.0, ids.1).await {
+        Ok(true) => {}
+        Ok(false) => return Err(StatusCode::NOT_FOUND),
+        Err(err) => return Ok(db_result_to_json_response::<()>(Err(err))),
+    }
+
+    let result = read_card_revisions_query(&app_state.pool, ids.1).await;
+
+    Ok(db_result_to_json_response(result))
+}
+
+pub async fn post_card_revision_restore(
+    State(app_state): State<Arc<AppState>>,
+    Path(ids): Path<(i32, i32, i32)>,
+    Query(query): Query<HashMap<String, String>>,
+    Form(card_revision_restore_form): Form<CardRevisionRestoreForm>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let card_revision_restore_form = match card_revision_restore_form.validate(false) {
+        Ok(card_revision_restore_form) => card_revision_restore_form,
+        Err(errors) => return Ok(validation_errors_to_json_response(errors)),
+    };
+
+    match user_owns_card(&app_state, ids.0, ids.1).await {
+        Ok(true) => {}
+        Ok(false) => return Err(StatusCode::NOT_FOUND),
+        Err(err) => return Ok(db_result_to_json_response::<()>(Err(err))),
+    }
+
+    let fields = card_revision_restore_form
+        .fields
+        .unwrap_or_default()
+        .split(',')
+        .map(String::from)
+        .collect();
+
+    let result = restore_card_revision_query(&app_state.pool, ids.0, ids.1, ids.2, fields).await;
+
+    if let Ok(Some(card)) = &result {
+        let user_id = app_state.user.as_ref().unwrap().id;
+        publish_event(&app_state, user_id, EventType::CardUpdated, card).await;
+    }
+
+    db_row_result_to_json_response(result)
+}
+
+pub async fn get_card_tags(
+    State(app_state): State<Arc<AppState>>,
+    Path(ids): Path<(i32, i32)>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    match user_owns_card(&app_state, ids.0, ids.1).await {
+        Ok(true) => {}
+        Ok(false) => return Err(StatusCode::NOT_FOUND),
+        Err(err) => return Ok(db_result_to_json_response::<()>(Err(err))),
+    }
+
+    let result = read_card_tags_query(&app_state.pool, ids.1).await;
+
+    Ok(db_result_to_json_response(result))
+}
+
+pub async fn get_tags(
+    State(app_state): State<Arc<AppState>>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let result = read_tags_query(&app_state.pool, app_state.user.as_ref().unwrap().id).await;
+
+    Ok(db_result_to_json_response(result))
+}
+
+pub async fn get_tag(
+    State(app_state): State<Arc<AppState>>,
+    Path(tag_id): Path<i32>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let result = read_tag_query(&app_state.pool, tag_id, app_state.user.as_ref().unwrap().id).await;
+
+    db_row_result_to_json_response(result)
+}
+
+pub async fn post_tag(
+    State(app_state): State<Arc<AppState>>,
+    Query(query): Query<HashMap<String, String>>,
+    Form(tag_form): Form<TagForm>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let tag_form = match tag_form.validate(true) {
+        Ok(tag_form) => tag_form,
+        Err(errors) => return Ok(validation_errors_to_json_response(errors)),
+    };
+
+    let result = create_tag_query(
+        &app_state.pool,
+        tag_form,
+        app_state.user.as_ref().unwrap().id,
+    )
+    .await;
+
+    Ok(db_result_to_json_response(result))
+}
+
+pub async fn put_tag(
+    State(app_state): State<Arc<AppState>>,
+    Path(tag_id): Path<i32>,
+    Query(query): Query<HashMap<String, String>>,
+    Form(tag_form): Form<TagForm>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let tag_form = match tag_form.validate(false) {
+        Ok(tag_form) => tag_form,
+        Err(errors) => return Ok(validation_errors_to_json_response(errors)),
+    };
+
+    let user_id = app_state.user.as_ref().unwrap().id;
+
+    let result = update_tag_query(&app_state.pool, tag_id, tag_form, user_id).await;
+
+    // the tag names of its cards changed
+    if matches!(&result, Ok(query_result) if query_result.rows_affected > 0) {
+        if let Ok(cards) = read_tag_cards_query(&app_state.pool, tag_id, user_id).await {
+            for card in cards {
+                publish_event(&app_state, user_id, EventType::CardUpdated, &card).await;
+            }
+        }
+    }
+
+    Ok(db_result_to_json_response(result))
+}
+
+pub async fn delete_tag(
+    State(app_state): State<Arc<AppState>>,
+    Path(tag_id): Path<i32>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let user_id = app_state.user.as_ref().unwrap().id;
+
+    let cards = read_tag_cards_query(&app_state.pool, tag_id, user_id).await;
+
+    let result = delete_tag_query(&app_state.pool, tag_id, user_id).await;
+
+    if let (Ok(cards), Ok(query_result)) = (cards, &result) {
+        if query_result.rows_affected > 0 {
+            for card in cards {
+                publish_event(&app_state, user_id, EventType::CardUpdated, &card).await;
+            }
+        }
+    }
+
+    Ok(db_result_to_json_response(result))
+}
+
+pub async fn get_webhooks(
+    State(app_state): State<Arc<AppState>>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let result = read_webhooks_query(&app_state.pool, app_state.user.as_ref().unwrap().id).await;
+
+    Ok(db_result_to_json_response(result))
+}
+
+pub async fn get_webhook(
+    State(app_state): State<Arc<AppState>>,
+    Path(webhook_id): Path<i32>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let result = read_webhook_query(
+        &app_state.pool,
+        webhook_id,
+        app_state.user.as_ref().unwrap().id,
+    )
+    .await;
+
+    db_row_result_to_json_response(result)
+}
+
+pub async fn post_webhook(
+    State(app_state): State<Arc<AppState>>,
+    Query(query): Query<HashMap<String, String>>,
+    Form(webhook_form): Form<WebhookForm>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let webhook_form = match webhook_form.validate(true) {
+        Ok(webhook_form) => webhook_form,
+        Err(errors) => return Ok(validation_errors_to_json_response(errors)),
+    };
+
+    let result = create_webhook_query(
+        &app_state.pool,
+        webhook_form,
+        app_state.user.as_ref().unwrap().id,
+    )
+    .await
+    .map(|webhook| CreatedWebhook {
+        secret: webhook.secret.clone(),
+        webhook,
+    });
+
+    Ok(db_result_to_json_response(result))
+}
+
+pub async fn put_webhook(
+    State(app_state): State<Arc<AppState>>,
+    Path(webhook_id): Path<i32>,
+    Query(query): Query<HashMap<String, String>>,
+    Form(webhook_form): Form<WebhookForm>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let webhook_form = match webhook_form.validate(false) {
+        Ok(webhook_form) => webhook_form,
+        Err(errors) => return Ok(validation_errors_to_json_response(errors)),
+    };
+
+    let result = update_webhook_query(
+        &app_state.pool,
+        webhook_id,
+        webhook_form,
+        app_state.user.as_ref().unwrap().id,
+    )
+    .await;
+
+    Ok(db_result_to_json_response(result))
+}
+
+pub async fn delete_webhook(
+    State(app_state): State<Arc<AppState>>,
+    Path(webhook_id): Path<i32>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let result = delete_webhook_query(
+        &app_state.pool,
+        webhook_id,
+        app_state.user.as_ref().unwrap().id,
+    )
+    .await;
+
+    Ok(db_result_to_json_response(result))
+}
+
+pub async fn get_webhook_deliveries(
+    State(app_state): State<Arc<AppState>>,
+    Path(webhook_id): Path<i32>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let webhook = read_webhook_query(
+        &app_state.pool,
+        webhook_id,
+        app_state.user.as_ref().unwrap().id,
+    )
+    .await;
+
+    match webhook {
+        Ok(Some(_)) => {}
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(err) => return Ok(db_result_to_json_response::<()>(Err(err))),
+    }
+
+    let result = read_webhook_deliveries_query(&app_state.pool, webhook_id).await;
+
+    Ok(db_result_to_json_response(result))
+}
+
+pub async fn get_events(
+    State(app_state): State<Arc<AppState>>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let user_id = app_state.user.as_ref().unwrap().id;
+
+    let last_event_id = headers
+        .get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+
+    let (missed_events, receiver) = app_state.events.subscribe_after(last_event_id);
+
+    let replayed_events: Vec<Event> = match missed_events {
+        Some(missed_events) => missed_events
+            .iter()
+            .filter(|event| event.user_id == user_id)
+            .map(|event| event.to_sse_event())
+            .collect(),
+        None => vec![reset_sse_event()],
+    };
+
+    let live_events = BroadcastStream::new(receiver).filter_map(move |result| match result {
+        Ok(event) if event.user_id == user_id => Some(event.to_sse_event()),
+        Ok(_) => None,
+        // the client fell behind the channel and has to reload
+        Err(_) => Some(reset_sse_event()),
+    });
+
+    let stream = tokio_stream::iter(replayed_events)
+        .chain(live_events)
+        .map(Ok);
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+pub async fn get_sync(
+    State(app_state): State<Arc<AppState>>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let since = match query.get("since") {
+        Some(since) => since.parse::<i64>().map_err(|_| StatusCode::BAD_REQUEST)?,
+        None => 0,
+    };
+
+    let result =
+        read_sync_changes_query(&app_state.pool, app_state.user.as_ref().unwrap().id, since).await;
+
+    Ok(db_result_to_json_response(result))
+}
+
+pub async fn post_sync(
+    State(app_state): State<Arc<AppState>>,
+    Query(query): Query<HashMap<String, String>>,
+    Json(sync_form): Json<SyncForm>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let sync_form = match sync_form.validate(false) {
+        Ok(sync_form) => sync_form,
+        Err(errors) => return Ok(validation_errors_to_json_response(errors)),
+    };
+
+    let user_id = app_state.user.as_ref().unwrap().id;
+
+    let result = apply_sync_form(&app_state.pool, user_id, sync_form).await;
+
+    if let Ok(sync_result) = &result {
+        for (event_type, data) in &sync_result.events {
+            publish_event(&app_state, user_id, *event_type, data).await;
+        }
+    }
+
+    Ok(db_result_to_json_response(result))
+}
+
+pub async fn post_reviews(
+    State(app_state): State<Arc<AppState>>,
+    Query(query): Query<HashMap<String, String>>,
+    Json(review_batch_form): Json<ReviewBatchForm>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let review_batch_form = match review_batch_form.validate(true) {
+        Ok(review_batch_form) => review_batch_form,
+        Err(errors) => return Ok(validation_errors_to_json_response(errors)),
+    };
+
+    let user_id = app_state.user.as_ref().unwrap().id;
+
+    let result = create_reviews_query(&app_state.pool, user_id, review_batch_form.reviews).await;
+
+    if let Ok(review_batch_result) = &result {
+        for card in &review_batch_result.cards {
+            publish_event(&app_state, user_id, EventType::CardRated, card).await;
+        }
+    }
+
+    Ok(db_result_to_json_response(result))
+}
+
+pub async fn post_study_session(
+    State(app_state): State<Arc<AppState>>,
+    Query(query): Query<HashMap<String, String>>,
+    Form(study_session_form): Form<StudySessionForm>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let study_session_form = match study_session_form.validate(true) {
+        Ok(study_session_form) => study_session_form,
+        Err(errors) => return Ok(validation_errors_to_json_response(errors)),
+    };
+
+    let tags = split_tag_names(&study_session_form.tags.unwrap_or_default());
+
+    let result = start_study_session(
+        &app_state,
+        app_state.user.as_ref().unwrap().id,
+        study_session_form.deck_id,
+        tags,
+    )
+    .await;
+
+    db_row_result_to_json_response(result)
+}
+
+pub async fn get_study_session(
+    State(app_state): State<Arc<AppState>>,
+    Path(session_id): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let session = read_study_session(&app_state, &session_id, app_state.user.as_ref().unwrap().id);
+
+    db_row_result_to_json_response(Ok(session))
+}
+
+pub async fn get_study_session_next(
+    State(app_state): State<Arc<AppState>>,
+    Path(session_id): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let session = read_study_session(&app_state, &session_id, app_state.user.as_ref().unwrap().id)
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let result = read_study_session_card(&app_state, session).await;
+
+    Ok(db_result_to_json_response(result))
+}
+
+pub async fn post_study_session_rating(
+    State(app_state): State<Arc<AppState>>,
+    Path(session_id): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+    Form(study_rating_form): Form<StudyRatingForm>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let study_rating_form = match study_rating_form.validate(false) {
+        Ok(study_rating_form) => study_rating_form,
+        Err(errors) => return Ok(validation_errors_to_json_response(errors)),
+    };
+
+    let user_id = app_state.user.as_ref().unwrap().id;
+
+    let session =
+        read_study_session(&app_state, &session_id, user_id).ok_or(StatusCode::NOT_FOUND)?;
+
+    let current = match read_study_session_card(&app_state, session).await {
+        Ok(current) => current,
+        Err(err) => return Ok(db_result_to_json_response::<()>(Err(err))),
+    };
+
+    // only the current card can be rated, so a repeated submit does not rate the next one
+    if current.card.as_ref().map(|card| card.id) != Some(study_rating_form.card_id) {
+        let mut errors = ValidationErrors::default();
+        errors.add("card_id", String::from("is not the current card"));
+        return Ok(validation_errors_to_json_response(errors));
+    }
+
+    let card_form = CardForm {
+        from_text: None,
+        to_text_primary: None,
+        to_text_secondary: None,
+        example_text: None,
+        audio_url: None,
+        seen_at: None,
+        seen_for: study_rating_form.seen_for,
+        rating: study_rating_form.rating,
+        tags: None,
+    };
+
+    let deck_id = current.session.deck_id;
+    let card_id = study_rating_form.card_id;
+
+    if let Err(err) = update_card_query(&app_state.pool, deck_id, card_id, card_form).await {
+        return Ok(db_result_to_json_response::<()>(Err(err)));
+    }
+
+    if let Ok(Some(card)) = read_card_query(&app_state.pool, deck_id, card_id).await {
+        publish_event(&app_state, user_id, EventType::CardRated, &card).await;
+    }
+
+    let session =
+        advance_study_session(&app_state, &session_id, card_id).ok_or(StatusCode::NOT_FOUND)?;
+
+    let result = read_study_session_card(&app_state, session).await;
+
+    Ok(db_result_to_json_response(result))
+}
+
+pub async fn post_study_session_undo(
+    State(app_state): State<Arc<AppState>>,
+    Path(session_id): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let user_id = app_state.user.as_ref().unwrap().id;
+
+    let session =
+        read_study_session(&app_state, &session_id, user_id).ok_or(StatusCode::NOT_FOUND)?;
+
+    // only reviews given during the session can be undone from it
+    let card =
+        match undo_last_review_query(&app_state.pool, session.deck_id, Some(session.started_at))
+            .await
+        {
+            Ok(Some(card)) => card,
+            Ok(None) => return Err(StatusCode::NOT_FOUND),
+            Err(err) => return Ok(db_result_to_json_response::<()>(Err(err))),
+        };
+
+    publish_event(&app_state, user_id, EventType::CardRated, &card).await;
+
+    let session =
+        rewind_study_session(&app_state, &session_id, card.id).ok_or(StatusCode::NOT_FOUND)?;
+
+    let result = read_study_session_card(&app_state, session).await;
+
+    Ok(db_result_to_json_response(result))
+}
+
+pub async fn delete_study_session(
+    State(app_state): State<Arc<AppState>>,
+    Path(session_id): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let session = end_study_session(&app_state, &session_id, app_state.user.as_ref().unwrap().id);
+
+    db_row_result_to_json_response(Ok(session))
+}
+
+pub async fn post_apkg_import(
+    State(app_state): State<Arc<AppState>>,
+    Query(query): Query<HashMap<String, String>>,
+    mut multipart: Multipart,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let mut data = None;
+
+    while let Ok(Some(field)) = multipart.next_field().await {
+        if field.name() == Some("file") {
+            data = field.bytes().await.ok();
+        }
+    }
+
+    let mut errors = ValidationErrors::default();
+
+    let data = match data {
+        Some(data) if !data.is_empty() => data,
+        _ => {
+            errors.add("file", String::from("is required"));
+            return Ok(validation_errors_to_json_response(errors));
+        }
+    };
+
+    let user_id = app_state.user.as_ref().unwrap().id;
+
+    let pending_import = match parse_apkg(data.to_vec(), user_id).await {
+        Ok(pending_import) => pending_import,
+        Err(message) => {
+            errors.add("file", message);
+            return Ok(validation_errors_to_json_response(errors));
+        }
+    };
+
+    let preview = pending_import.preview();
+
+    store_import(&app_state, pending_import);
+
+    Ok(db_result_to_json_response(Ok(preview)))
+}
+
+pub async fn post_kindle_import(
+    State(app_state): State<Arc<AppState>>,
+    Query(query): Query<HashMap<String, String>>,
+    mut multipart: Multipart,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let mut data = None;
+    let mut languages = Vec::new();
+
+    while let Ok(Some(field)) = multipart.next_field().await {
+        match field.name() {
+            Some("file") => data = field.bytes().await.ok(),
+            Some("languages") => {
+                if let Ok(value) = field.text().await {
+                    languages = split_list(&value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut errors = ValidationErrors::default();
+
+    let data = match data {
+        Some(data) if !data.is_empty() => data,
+        _ => {
+            errors.add("file", String::from("is required"));
+            return Ok(validation_errors_to_json_response(errors));
+        }
+    };
+
+    let user_id = app_state.user.as_ref().unwrap().id;
+
+    // words of a book are skipped when its deck already has them
+    let decks = match read_decks_query(&app_state.pool, user_id).await {
+        Ok(decks) => decks,
+        Err(err) => return Ok(db_result_to_json_response::<()>(Err(err))),
+    };
+
+    let mut deck_texts = Vec::new();
+
+    for deck in decks {
+        let texts = match read_deck_texts_query(&app_state.pool, &deck, fold_text).await {
+            Ok(texts) => texts,
+            Err(err) => return Ok(db_result_to_json_response::<()>(Err(err))),
+        };
+
+        deck_texts.push((deck, texts));
+    }
+
+    let pending_import = match parse_kindle(data.to_vec(), user_id, &deck_texts, &languages).await {
+        Ok(pending_import) => pending_import,
+        Err(message) => {
+            errors.add("file", message);
+            return Ok(validation_errors_to_json_response(errors));
+        }
+    };
+
+    let preview = pending_import.preview();
+
+    store_import(&app_state, pending_import);
+
+    Ok(db_result_to_json_response(Ok(preview)))
+}
+
+pub async fn post_csv_import(
+    State(app_state): State<Arc<AppState>>,
+    Path(deck_id): Path<i32>,
+    Query(query): Query<HashMap<String, String>>,
+    mut multipart: Multipart,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let user_id = app_state.user.as_ref().unwrap().id;
+
+    let deck = match read_deck(&app_state.pool, deck_id, user_id).await {
+        Ok(Some(deck)) => deck,
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(err) => return Ok(db_result_to_json_response::<()>(Err(err))),
+    };
+
+    let mut data = None;
+    let mut fields: HashMap<String, String> = HashMap::new();
+
+    while let Ok(Some(field)) = multipart.next_field().await {
+        match field.name().map(String::from) {
+            Some(name) if name == "file" => data = field.bytes().await.ok(),
+            Some(name) => {
+                if let Ok(value) = field.text().await {
+                    fields.insert(name, value);
+                }
+            }
+            None => {}
+        }
+    }
+
+    let csv_import_form = CsvImportForm {
+        delimiter: fields.remove("delimiter"),
+        header: fields.remove("header"),
+        from_text: fields.remove("from_text"),
+        to_text_primary: fields.remove("to_text_primary"),
+        to_text_secondary: fields.remove("to_text_secondary"),
+        example_text: fields.remove("example_text"),
+        audio_url: fields.remove("audio_url"),
+    };
+
+    let csv_import_form = match csv_import_form.validate(true) {
+        Ok(csv_import_form) => csv_import_form,
+        Err(errors) => return Ok(validation_errors_to_json_response(errors)),
+    };
+
+    let data = match data {
+        Some(data) if !data.is_empty() => data,
+        _ => {
+            let mut errors = ValidationErrors::default();
+            errors.add("file", String::from("is required"));
+            return Ok(validation_errors_to_json_response(errors));
+        }
+    };
+
+    // rows repeating a card of the deck are flagged in the preview
+    let deck_texts = match read_deck_texts_query(&app_state.pool, &deck, normalize_text).await {
+        Ok(deck_texts) => deck_texts,
+        Err(err) => return Ok(db_result_to_json_response::<()>(Err(err))),
+    };
+
+    let pending_import = match parse_csv(&data, user_id, &deck, &deck_texts, csv_import_form) {
+        Ok(pending_import) => pending_import,
+        Err(errors) => return Ok(validation_errors_to_json_response(errors)),
+    };
+
+    let preview = pending_import.preview();
+
+    store_import(&app_state, pending_import);
+
+    Ok(db_result_to_json_response(Ok(preview)))
+}
+
+pub async fn post_subtitles_import(
+    State(app_state): State<Arc<AppState>>,
+    Path(deck_id): Path<i32>,
+    Query(query): Query<HashMap<String, String>>,
+    mut multipart: Multipart,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let user_id = app_state.user.as_ref().unwrap().id;
+
+    let deck = match read_deck(&app_state.pool, deck_id, user_id).await {
+        Ok(Some(deck)) => deck,
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(err) => return Ok(db_result_to_json_response::<()>(Err(err))),
+    };
+
+    let mut data = None;
+    let mut file_name = None;
+
+    while let Ok(Some(field)) = multipart.next_field().await {
+        if field.name() == Some("file") {
+            file_name = field.file_name().map(String::from);
+            data = field.bytes().await.ok();
+        }
+    }
+
+    let mut errors = ValidationErrors::default();
+
+    let data = match data {
+        Some(data) if !data.is_empty() => data,
+        _ => {
+            errors.add("file", String::from("is required"));
+            return Ok(validation_errors_to_json_response(errors));
+        }
+    };
+
+    // only words that are not in the deck yet are listed
+    let deck_texts = match read_deck_texts_query(&app_state.pool, &deck, fold_text).await {
+        Ok(deck_texts) => deck_texts,
+        Err(err) => return Ok(db_result_to_json_response::<()>(Err(err))),
+    };
+
+    let file_name = file_name.unwrap_or_else(|| String::from("subtitles"));
+
+    let pending_import = match parse_subtitles(&data, &file_name, user_id, &deck, &deck_texts) {
+        Ok(pending_import) => pending_import,
+        Err(message) => {
+            errors.add("file", message);
+            return Ok(validation_errors_to_json_response(errors));
+        }
+    };
+
+    let preview = pending_import.preview();
+
+    store_import(&app_state, pending_import);
+
+    Ok(db_result_to_json_response(Ok(preview)))
+}
+
+pub async fn post_paste_import(
+    State(app_state): State<Arc<AppState>>,
+    Path(deck_id): Path<i32>,
+    Query(query): Query<HashMap<String, String>>,
+    Form(paste_import_form): Form<PasteImportForm>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let user_id = app_state.user.as_ref().unwrap().id;
+
+    let deck = match read_deck(&app_state.pool, deck_id, user_id).await {
+        Ok(Some(deck)) => deck,
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(err) => return Ok(db_result_to_json_response::<()>(Err(err))),
+    };
+
+    let paste_import_form = match paste_import_form.validate(true) {
+        Ok(paste_import_form) => paste_import_form,
+        Err(errors) => return Ok(validation_errors_to_json_response(errors)),
+    };
+
+    // lines repeating a card of the deck are flagged or skipped
+    let deck_texts = match read_deck_texts_query(&app_state.pool, &deck, normalize_text).await {
+        Ok(deck_texts) => deck_texts,
+        Err(err) => return Ok(db_result_to_json_response::<()>(Err(err))),
+    };
+
+    let pending_import = match parse_paste(user_id, &deck, &deck_texts, paste_import_form) {
+        Ok(pending_import) => pending_import,
+        Err(errors) => return Ok(validation_errors_to_json_response(errors)),
+    };
+
+    let preview = pending_import.preview();
+
+    store_import(&app_state, pending_import);
+
+    Ok(db_result_to_json_response(Ok(preview)))
+}
+
+// a bundle is a backup of the app itself, so it is restored right away without a preview
+pub async fn post_bundle_import(
+    State(app_state): State<Arc<AppState>>,
+    Query(query): Query<HashMap<String, String>>,
+    mut multipart: Multipart,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let mut data = None;
+
+    while let Ok(Some(field)) = multipart.next_field().await {
+        if field.name() == Some("file") {
+            data = field.bytes().await.ok();
+        }
+    }
+
+    let mut errors = ValidationErrors::default();
+
+    let data = match data {
+        Some(data) if !data.is_empty() => data,
+        _ => {
+            errors.add("file", String::from("is required"));
+            return Ok(validation_errors_to_json_response(errors));
+        }
+    };
+
+    let (bundle, media) = match read_bundle(data.to_vec()) {
+        Ok(bundle) => bundle,
+        Err(message) => {
+            errors.add("file", message);
+            return Ok(validation_errors_to_json_response(errors));
+        }
+    };
+
+    let audio_urls = match save_bundle_media(&media).await {
+        Ok(audio_urls) => audio_urls,
+        Err(err) => {
+            errors.add("file", format!("media could not be saved: {}", err));
+            return Ok(validation_errors_to_json_response(errors));
+        }
+    };
+
+    let user_id = app_state.user.as_ref().unwrap().id;
+
+    let result = create_bundle_query(&app_state.pool, user_id, &bundle, &audio_urls).await;
+
+    if let Ok(deck_overviews) = &result {
+        for deck_overview in deck_overviews {
+            publish_event(
+                &app_state,
+                user_id,
+                EventType::DeckCreated,
+                &deck_overview.deck,
+            )
+            .await;
+
+            for card in deck_overview.cards.iter().flatten() {
+                publish_event(&app_state, user_id, EventType::CardCreated, card).await;
+            }
+        }
+    }
+
+    Ok(db_result_to_json_response(result))
+}
+
+pub async fn get_import(
+    State(app_state): State<Arc<AppState>>,
+    Path(import_id): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let preview = read_import_preview(&app_state, &import_id, app_state.user.as_ref().unwrap().id);
+
+    db_row_result_to_json_response(Ok(preview))
+}
+
+pub async fn post_import_commit(
+    State(app_state): State<Arc<AppState>>,
+    Path(import_id): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+    Form(import_commit_form): Form<ImportCommitForm>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let user_id = app_state.user.as_ref().unwrap().id;
+
+    let import_commit_form = match import_commit_form.validate(true) {
+        Ok(import_commit_form) => import_commit_form,
+        Err(errors) => return Ok(validation_errors_to_json_response(errors)),
+    };
+
+    let duplicates = import_commit_form
+        .duplicates
+        .clone()
+        .unwrap_or_else(|| String::from(DUPLICATE_POLICY_ALLOW));
+    let ignore_articles = import_commit_form.ignore_articles.unwrap_or(true);
+
+    let preview =
+        read_import_preview(&app_state, &import_id, user_id).ok_or(StatusCode::NOT_FOUND)?;
+
+    // only the chosen decks and cards of the preview are imported, all of them when none are chosen
+    let mut errors = ValidationErrors::default();
+
+    if let Some(index) = import_commit_form
+        .decks
+        .iter()
+        .find(|index| **index < 0 || **index as usize >= preview.decks.len())
+    {
+        errors.add(
+            "decks",
+            format!("must be indexes of the decks of the preview, not {}", index),
+        );
+    }
+
+    if let Some(index) = import_commit_form
+        .cards
+        .iter()
+        .find(|index| **index < 0 || **index as usize >= preview.num_cards)
+    {
+        errors.add(
+            "cards",
+            format!("must be indexes of the cards of the import, not {}", index),
+        );
+    }
+
+    if !errors.is_empty() {
+        return Ok(validation_errors_to_json_response(errors));
+    }
+
+    let is_chosen = |index: usize| {
+        import_commit_form.decks.is_empty() || import_commit_form.decks.contains(&(index as i32))
+    };
+
+    // imports into existing decks keep their languages
+    if preview.goes_into_existing_decks() {
+        let mut pending_import =
+            take_import(&app_state, &import_id, user_id).ok_or(StatusCode::NOT_FOUND)?;
+
+        pending_import.keep_chosen(&import_commit_form.decks, &import_commit_form.cards);
+
+        let import_decks = pending_import
+            .decks
+            .into_iter()
+            .filter_map(|import_deck| import_deck.deck_id.map(|deck_id| (deck_id, import_deck)))
+            .collect();
+
+        let result = create_deck_import_query(
+            &app_state.pool,
+            user_id,
+            import_decks,
+            &HashMap::new(),
+            &duplicates,
+            ignore_articles,
+        )
+        .await;
+
+        if let Ok((deck_overviews, merged_cards)) = &result {
+            for card in deck_overviews
+                .iter()
+                .flat_map(|deck_overview| deck_overview.cards.iter().flatten())
+            {
+                publish_event(&app_state, user_id, EventType::CardCreated, card).await;
+            }
+
+            for card in merged_cards {
+                publish_event(&app_state, user_id, EventType::CardUpdated, card).await;
+            }
+        }
+
+        let result = result.map(|(deck_overviews, _)| deck_overviews);
+
+        return Ok(db_result_to_json_response(result));
+    }
+
+    // blank fields keep the languages of the preview. they are checked before the import is
+    // taken, so that a fixed form can be sent again
+    let mut deck_forms = Vec::new();
+
+    for (_, deck) in preview
+        .decks
+        .into_iter()
+        .enumerate()
+        .filter(|(index, _)| is_chosen(*index))
+    {
+        let deck_form = DeckForm {
+            from_language: import_commit_form
+                .from_language
+                .clone()
+                .filter(|from_language| !from_language.trim().is_empty())
+                .or(Some(deck.from_language)),
+            to_language_primary: import_commit_form
+                .to_language_primary
+                .clone()
+                .filter(|to_language_primary| !to_language_primary.trim().is_empty())
+                .or(Some(deck.to_language_primary)),
+            to_language_secondary: import_commit_form
+                .to_language_secondary
+                .clone()
+                .filter(|to_language_secondary| !to_language_secondary.trim().is_empty())
+                .or(deck.to_language_secondary),
+            design_key: None,
+            seen_at: None,
+        };
+
+        match deck_form.validate(true) {
+            Ok(deck_form) => deck_forms.push(deck_form),
+            Err(errors) => return Ok(validation_errors_to_json_response(errors)),
+        }
+    }
+
+    let mut pending_import =
+        take_import(&app_state, &import_id, user_id).ok_or(StatusCode::NOT_FOUND)?;
+
+    pending_import.keep_chosen(&import_commit_form.decks, &import_commit_form.cards);
+
+    let audio_urls = match save_import_media(&pending_import).await {
+        Ok(audio_urls) => audio_urls,
+        Err(err) => {
+            let mut errors = ValidationErrors::default();
+            errors.add("file", format!("media could not be saved: {}", err));
+            return Ok(validation_errors_to_json_response(errors));
+        }
+    };
+
+    let import_decks = deck_forms.into_iter().zip(pending_import.decks).collect();
+
+    let result = create_import_query(
+        &app_state.pool,
+        user_id,
+        import_decks,
+        &audio_urls,
+        &duplicates,
+        ignore_articles,
+    )
+    .await;
+
+    if let Ok(deck_overviews) = &result {
+        for deck_overview in deck_overviews {
+            publish_event(
+                &app_state,
+                user_id,
+                EventType::DeckCreated,
+                &deck_overview.deck,
+            )
+            .await;
+
+            for card in deck_overview.cards.iter().flatten() {
+                publish_event(&app_state, user_id, EventType::CardCreated, card).await;
+            }
+        }
+    }
+
+    Ok(db_result_to_json_response(result))
+}
+
+pub async fn delete_import(
+    State(app_state): State<Arc<AppState>>,
+    Path(import_id): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Json<Value>, StatusCode> {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let preview = take_import(&app_state, &import_id, app_state.user.as_ref().unwrap().id)
+        .map(|pending_import| pending_import.preview());
+
+    db_row_result_to_json_response(Ok(preview))
+}
@@ -0,0 +1,689 @@
+use crate::imports::{is_audio_file, local_media_path, read_zip_entry, save_media_file, ZipBudget};
+use crate::queries::BundleRecords;
+use crate::validation::Validate;
+use crate::{CardForm, CardRelationForm, DeckForm};
+use chrono::NaiveDateTime;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::io::{Cursor, Write};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+pub const BUNDLE_FORMAT: &str = "square-cards-bundle";
+
+// raise with every change to the manifest and add a step to upgrade_manifest
+pub const BUNDLE_VERSION: u64 = 1;
+
+const MANIFEST_FILE: &str = "manifest.json";
+const MEDIA_DIRECTORY: &str = "media";
+
+// bundle model
+
+// ids are local to the bundle and numbered in the order of the database ids, so that a bundle
+// that is restored and exported again comes out the same
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct BundleDeck {
+    pub id: i32,
+    pub from_language: String,
+    pub to_language_primary: String,
+    pub to_language_secondary: Option<String>,
+    pub design_key: Option<String>,
+    pub seen_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct BundleCard {
+    pub id: i32,
+    pub deck_id: i32,
+    // follows from the relations, kept for readers of the manifest
+    pub related_card_ids: Vec<i32>,
+    pub from_text: String,
+    pub to_text_primary: String,
+    pub to_text_secondary: Option<String>,
+    pub example_text: Option<String>,
+    // links that do not point at a file of the bundle
+    pub audio_url: Option<String>,
+    // name of a file in the media directory of the bundle
+    pub audio_file: Option<String>,
+    pub tags: Vec<String>,
+    pub seen_at: NaiveDateTime,
+    pub seen_for: Option<i32>,
+    pub rating: i32,
+    pub prev_rating: i32,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct BundleRelation {
+    pub card_id: i32,
+    pub related_card_id: i32,
+    pub relation_type: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct BundleReview {
+    pub card_id: i32,
+    pub rating: i32,
+    pub prev_rating: i32,
+    pub seen_at: NaiveDateTime,
+    pub seen_for: Option<i32>,
+    pub reviewed_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct Bundle {
+    pub format: String,
+    pub version: u64,
+    pub decks: Vec<BundleDeck>,
+    pub cards: Vec<BundleCard>,
+    pub relations: Vec<BundleRelation>,
+    pub reviews: Vec<BundleReview>,
+    pub media: Vec<String>,
+}
+
+// helpers
+
+// media is stored under the hash of its content, so the same file always gets the same name
+fn media_name(data: &[u8], extension: &str) -> String {
+    let hash: String = Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    format!("{}.{}", hash, extension.to_lowercase())
+}
+
+fn is_media_name(name: &str) -> bool {
+    match name.split_once('.') {
+        Some((hash, extension)) => {
+            hash.len() == 64
+                && hash
+                    .bytes()
+                    .all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'))
+                && is_audio_file(name)
+                && extension.bytes().all(|byte| byte.is_ascii_alphanumeric())
+        }
+        None => false,
+    }
+}
+
+fn local_ids(ids: impl Iterator<Item = i32>) -> HashMap<i32, i32> {
+    ids.enumerate()
+        .map(|(index, id)| (id, index as i32 + 1))
+        .collect()
+}
+
+// brings the manifest of an older bundle to the current version, newer bundles are rejected
+fn upgrade_manifest(manifest: Value) -> Result<Value, String> {
+    if manifest["format"].as_str() != Some(BUNDLE_FORMAT) {
+        return Err(String::from("is not a Square Cards bundle"));
+    }
+
+    let version = manifest["version"]
+        .as_u64()
+        .ok_or_else(|| String::from("has no version"))?;
+
+    if version > BUNDLE_VERSION {
+        return Err(format!(
+            "was written by a newer version of the app (bundle version {}, supported up to {})",
+            version, BUNDLE_VERSION
+        ));
+    }
+
+    if version < 1 {
+        return Err(format!("has the unknown bundle version {}", version));
+    }
+
+    // version 1 is the first one, steps from version n to n + 1 go here in order
+    Ok(manifest)
+}
+
+// every reference points into the bundle and every text fits its column, a bundle is restored
+// completely or not at all
+fn check_bundle(bundle: &Bundle, media: &HashMap<String, Vec<u8>>) -> Result<(), String> {
+    let mut deck_ids = HashSet::new();
+
+    for deck in &bundle.decks {
+        if !deck_ids.insert(deck.id) {
+            return Err(format!("has deck {} twice", deck.id));
+        }
+
+        let deck_form = DeckForm {
+            from_language: Some(deck.from_language.clone()),
+            to_language_primary: Some(deck.to_language_primary.clone()),
+            to_language_secondary: deck.to_language_secondary.clone(),
+            design_key: deck.design_key.clone(),
+            seen_at: None,
+        };
+
+        if let Err(errors) = deck_form.validate(true) {
+            return Err(format!(
+                "has an invalid deck {}: {}",
+                deck.id,
+                errors.to_message()
+            ));
+        }
+    }
+
+    let mut card_ids = HashSet::new();
+
+    for card in &bundle.cards {
+        if !card_ids.insert(card.id) {
+            return Err(format!("has card {} twice", card.id));
+        }
+
+        if !deck_ids.contains(&card.deck_id) {
+            return Err(format!("has card {} in a missing deck", card.id));
+        }
+
+        if let Some(audio_file) = &card.audio_file {
+            if !media.contains_key(audio_file) {
+                return Err(format!("is missing the audio of card {}", card.id));
+            }
+        }
+
+        let card_form = CardForm {
+            from_text: Some(card.from_text.clone()),
+            to_text_primary: Some(card.to_text_primary.clone()),
+            to_text_secondary: card.to_text_secondary.clone(),
+            example_text: card.example_text.clone(),
+            audio_url: card.audio_url.clone(),
+            seen_at: Some(card.seen_at),
+            seen_for: card.seen_for,
+            rating: Some(card.rating),
+            tags: Some(card.tags.join(",")),
+        };
+
+        if let Err(errors) = card_form.validate(true) {
+            return Err(format!(
+                "has an invalid card {}: {}",
+                card.id,
+                errors.to_message()
+            ));
+        }
+    }
+
+    for relation in &bundle.relations {
+        if !card_ids.contains(&relation.card_id) || !card_ids.contains(&relation.related_card_id) {
+            return Err(String::from("has a relation to a missing card"));
+        }
+
+        if relation.card_id == relation.related_card_id {
+            return Err(format!("relates card {} to itself", relation.card_id));
+        }
+
+        let card_relation_form = CardRelationForm {
+            related_card_id: relation.related_card_id,
+            relation_type: relation.relation_type.clone(),
+        };
+
+        if let Err(errors) = card_relation_form.validate(true) {
+            return Err(format!(
+                "has an invalid relation of card {}: {}",
+                relation.card_id,
+                errors.to_message()
+            ));
+        }
+    }
+
+    for review in &bundle.reviews {
+        if !card_ids.contains(&review.card_id) {
+            return Err(String::from("has a review of a missing card"));
+        }
+    }
+
+    Ok(())
+}
+
+// export
+
+// writes the decks with their cards, relations, reviews and the audio stored in the assets
+pub async fn write_bundle(records: BundleRecords) -> Result<Vec<u8>, String> {
+    let deck_ids = local_ids(records.decks.iter().map(|deck| deck.id));
+    let card_ids = local_ids(records.cards.iter().map(|card| card.id));
+
+    let mut media: Vec<(String, Vec<u8>)> = Vec::new();
+    let mut media_names: HashMap<String, String> = HashMap::new();
+    let mut cards = Vec::with_capacity(records.cards.len());
+
+    for card in records.cards {
+        let mut audio_url = card.audio_url;
+        let mut audio_file = None;
+
+        if let Some(url) = &audio_url {
+            if !media_names.contains_key(url) {
+                if let Some(path) = local_media_path(url) {
+                    if let Ok(data) = tokio::fs::read(&path).await {
+                        let extension = path
+                            .extension()
+                            .map(|extension| extension.to_string_lossy().into_owned())
+                            .unwrap_or_default();
+                        let name = media_name(&data, &extension);
+
+                        media_names.insert(url.clone(), name.clone());
+
+                        if !media.iter().any(|(media_name, _)| *media_name == name) {
+                            media.push((name, data));
+                        }
+                    }
+                }
+            }
+
+            if let Some(name) = media_names.get(url) {
+                audio_file = Some(name.clone());
+                audio_url = None;
+            }
+        }
+
+        let mut related_card_ids: Vec<i32> = card
+            .related_card_ids
+            .iter()
+            .filter_map(|id| card_ids.get(id).copied())
+            .collect();
+        related_card_ids.sort();
+
+        cards.push(BundleCard {
+            id: card_ids[&card.id],
+            deck_id: deck_ids[&card.deck_id],
+            related_card_ids,
+            from_text: card.from_text,
+            to_text_primary: card.to_text_primary,
+            to_text_secondary: card.to_text_secondary,
+            example_text: card.example_text,
+            audio_url,
+            audio_file,
+            tags: records.card_tags.get(&card.id).cloned().unwrap_or_default(),
+            seen_at: card.seen_at,
+            seen_for: card.seen_for,
+            rating: card.rating,
+            prev_rating: card.prev_rating,
+            created_at: card.created_at,
+            updated_at: card.updated_at,
+        });
+    }
+
+    media.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let bundle = Bundle {
+        format: String::from(BUNDLE_FORMAT),
+        version: BUNDLE_VERSION,
+        decks: records
+            .decks
+            .into_iter()
+            .map(|deck| BundleDeck {
+                id: deck_ids[&deck.id],
+                from_language: deck.from_language,
+                to_language_primary: deck.to_language_primary,
+                to_language_secondary: deck.to_language_secondary,
+                design_key: deck.design_key,
+                seen_at: deck.seen_at,
+                created_at: deck.created_at,
+                updated_at: deck.updated_at,
+            })
+            .collect(),
+        cards,
+        relations: records
+            .relations
+            .into_iter()
+            .map(|relation| BundleRelation {
+                card_id: card_ids[&relation.card_id],
+                related_card_id: card_ids[&relation.related_card_id],
+                relation_type: relation.relation_type,
+                created_at: relation.created_at,
+            })
+            .collect(),
+        reviews: records
+            .reviews
+            .into_iter()
+            .map(|review| BundleReview {
+                card_id: card_ids[&review.card_id],
+                rating: review.rating,
+                prev_rating: review.prev_rating,
+                seen_at: review.seen_at,
+                seen_for: review.seen_for,
+                reviewed_at: review.reviewed_at,
+                created_at: review.created_at,
+            })
+            .collect(),
+        media: media.iter().map(|(name, _)| name.clone()).collect(),
+    };
+
+    let manifest = serde_json::to_vec_pretty(&bundle).map_err(|err| err.to_string())?;
+
+    // the entries keep the default timestamp, so equal contents give equal files
+    let mut archive = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    let files = std::iter::once((String::from(MANIFEST_FILE), manifest)).chain(
+        media
+            .into_iter()
+            .map(|(name, data)| (format!("{}/{}", MEDIA_DIRECTORY, name), data)),
+    );
+
+    for (name, data) in files {
+        archive
+            .start_file(name, options)
+            .and_then(|_| archive.write_all(&data).map_err(Into::into))
+            .map_err(|err| err.to_string())?;
+    }
+
+    let archive = archive.finish().map_err(|err| err.to_string())?;
+
+    Ok(archive.into_inner())
+}
+
+// import
+
+// reads and checks an uploaded bundle, its media is returned by file name
+pub fn read_bundle(data: Vec<u8>) -> Result<(Bundle, HashMap<String, Vec<u8>>), String> {
+    let mut archive =
+        ZipArchive::new(Cursor::new(data)).map_err(|_| String::from("is not a bundle (.zip)"))?;
+
+    let mut budget = ZipBudget::default();
+
+    let manifest = read_zip_entry(&mut archive, MANIFEST_FILE, &mut budget)?
+        .ok_or_else(|| String::from("does not contain a manifest"))?;
+
+    let manifest: Value = serde_json::from_slice(&manifest)
+        .map_err(|err| format!("has an invalid manifest: {}", err))?;
+
+    let bundle: Bundle = serde_json::from_value(upgrade_manifest(manifest)?)
+        .map_err(|err| format!("has an invalid manifest: {}", err))?;
+
+    let mut media = HashMap::new();
+
+    for name in &bundle.media {
+        if !is_media_name(name) {
+            return Err(format!("has an invalid media file name {}", name));
+        }
+
+        let data = read_zip_entry(
+            &mut archive,
+            &format!("{}/{}", MEDIA_DIRECTORY, name),
+            &mut budget,
+        )?
+        .ok_or_else(|| format!("is missing the media file {}", name))?;
+
+        let extension = name.rsplit_once('.').map_or("", |(_, extension)| extension);
+
+        if media_name(&data, extension) != *name {
+            return Err(format!("has a damaged media file {}", name));
+        }
+
+        media.insert(name.clone(), data);
+    }
+
+    check_bundle(&bundle, &media)?;
+
+    Ok((bundle, media))
+}
+
+// writes the media of a bundle to the assets and returns the urls by file name, files that are
+// already there are shared since their names follow from their content
+pub async fn save_bundle_media(
+    media: &HashMap<String, Vec<u8>>,
+) -> Result<HashMap<String, String>, std::io::Error> {
+    let mut urls = HashMap::new();
+
+    for (name, data) in media {
+        urls.insert(name.clone(), save_media_file(name, data).await?);
+    }
+
+    Ok(urls)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queries::{
+        create_bundle_query, create_user_query, read_bundle_records_query, CardRelationRecord,
+    };
+    use crate::{Card, Deck, Review};
+    use serde_json::json;
+    use sqlx::{Pool, Postgres};
+
+    fn timestamp(minute: u32) -> NaiveDateTime {
+        chrono::NaiveDate::from_ymd_opt(2026, 10, 18)
+            .unwrap()
+            .and_hms_opt(9, minute, 0)
+            .unwrap()
+    }
+
+    fn deck(id: i32, design_key: Option<&str>) -> Deck {
+        Deck {
+            id,
+            user_id: 1,
+            from_language: String::from("Deutsch"),
+            to_language_primary: String::from("English"),
+            to_language_secondary: Some(String::from("Français")),
+            design_key: design_key.map(String::from),
+            seen_at: timestamp(1),
+            created_at: timestamp(2),
+            updated_at: timestamp(3),
+        }
+    }
+
+    fn card(id: i32, deck_id: i32, from_text: &str, audio_url: Option<String>) -> Card {
+        Card {
+            id,
+            deck_id,
+            related_card_ids: Vec::new(),
+            from_text: String::from(from_text),
+            to_text_primary: format!("{} (en)", from_text),
+            to_text_secondary: None,
+            example_text: Some(format!("Ein Satz mit {}.", from_text)),
+            audio_url,
+            seen_at: timestamp(10),
+            seen_for: Some(1200),
+            rating: 3,
+            prev_rating: 2,
+            created_at: timestamp(4),
+            updated_at: timestamp(5),
+        }
+    }
+
+    fn review(id: i32, card_id: i32) -> Review {
+        Review {
+            id,
+            card_id,
+            rating: 3,
+            prev_rating: 2,
+            seen_at: timestamp(10),
+            seen_for: Some(1200),
+            reviewed_at: timestamp(11),
+            created_at: timestamp(11),
+            undo_rating: None,
+            undo_prev_rating: None,
+            undo_seen_at: None,
+            undo_seen_for: None,
+            undo_updated_at: None,
+        }
+    }
+
+    fn relation(card_id: i32, related_card_id: i32, relation_type: &str) -> CardRelationRecord {
+        CardRelationRecord {
+            card_id,
+            related_card_id,
+            relation_type: Some(String::from(relation_type)),
+            created_at: timestamp(6),
+        }
+    }
+
+    // the related ids of each card, as the cards query fills them from the relations
+    fn fill_related_card_ids(cards: &mut [Card], relations: &[CardRelationRecord]) {
+        for card in cards.iter_mut() {
+            let mut related_card_ids: Vec<i32> = relations
+                .iter()
+                .filter_map(|relation| {
+                    if relation.card_id == card.id {
+                        Some(relation.related_card_id)
+                    } else if relation.related_card_id == card.id {
+                        Some(relation.card_id)
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+            related_card_ids.sort();
+            card.related_card_ids = related_card_ids;
+        }
+    }
+
+    async fn create_user(pool: &Pool<Postgres>, email: &str) -> i32 {
+        let user_form = serde_json::from_value(json!({ "name": "Anna", "email": email }));
+
+        create_user_query(pool, user_form.unwrap())
+            .await
+            .unwrap()
+            .id
+    }
+
+    // restores a bundle for the user and exports what is read back
+    async fn restore_and_export(pool: &Pool<Postgres>, user_id: i32, data: Vec<u8>) -> Vec<u8> {
+        let (bundle, media) = read_bundle(data).unwrap();
+        let audio_urls = save_bundle_media(&media).await.unwrap();
+
+        create_bundle_query(pool, user_id, &bundle, &audio_urls)
+            .await
+            .unwrap();
+
+        let records = read_bundle_records_query(pool, user_id, None)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let data = write_bundle(records).await.unwrap();
+
+        for url in audio_urls.values() {
+            let _ = tokio::fs::remove_file(local_media_path(url).unwrap()).await;
+        }
+
+        data
+    }
+
+    fn manifest(version: u64) -> Value {
+        serde_json::json!({
+            "format": BUNDLE_FORMAT,
+            "version": version,
+            "decks": [],
+            "cards": [],
+            "relations": [],
+            "reviews": [],
+            "media": [],
+        })
+    }
+
+    fn bundle_file(manifest: &Value) -> Vec<u8> {
+        let mut archive = ZipWriter::new(Cursor::new(Vec::new()));
+
+        archive
+            .start_file(MANIFEST_FILE, FileOptions::default())
+            .unwrap();
+        archive
+            .write_all(&serde_json::to_vec(manifest).unwrap())
+            .unwrap();
+
+        archive.finish().unwrap().into_inner()
+    }
+
+    #[sqlx::test]
+    async fn bundle_round_trip_is_identical(pool: Pool<Postgres>) {
+        let audio_data = b"bundle round trip audio".to_vec();
+        let audio_url = save_media_file("bundle-round-trip-test.mp3", &audio_data)
+            .await
+            .unwrap();
+
+        let mut cards = vec![
+            card(7, 3, "Hund", Some(audio_url.clone())),
+            card(
+                9,
+                3,
+                "Katze",
+                Some(String::from("https://example.com/katze.mp3")),
+            ),
+            card(12, 5, "Maus", None),
+            card(15, 5, "Haus", Some(audio_url.clone())),
+        ];
+        let relations = vec![
+            relation(7, 9, "synonym"),
+            relation(7, 15, "false_friend"),
+            relation(12, 15, "antonym"),
+        ];
+        fill_related_card_ids(&mut cards, &relations);
+
+        let records = BundleRecords {
+            decks: vec![deck(3, Some("purple")), deck(5, None)],
+            cards,
+            card_tags: HashMap::from([
+                (7, vec![String::from("a1"), String::from("animals")]),
+                (12, vec![String::from("animals")]),
+            ]),
+            relations,
+            reviews: vec![review(20, 7), review(21, 12), review(25, 7)],
+        };
+
+        let exported = write_bundle(records).await.unwrap();
+
+        let (bundle, media) = read_bundle(exported.clone()).unwrap();
+
+        assert_eq!(bundle.decks.len(), 2);
+        assert_eq!(bundle.cards.len(), 4);
+        assert_eq!(bundle.relations.len(), 3);
+        assert_eq!(bundle.reviews.len(), 3);
+        assert_eq!(bundle.media.len(), 1);
+        assert_eq!(bundle.decks[0].design_key.as_deref(), Some("purple"));
+        assert_eq!(bundle.cards[0].audio_file, Some(bundle.media[0].clone()));
+        assert_eq!(media.get(&bundle.media[0]), Some(&audio_data));
+        assert_eq!(
+            bundle.cards[1].audio_url.as_deref(),
+            Some("https://example.com/katze.mp3")
+        );
+
+        let _ = tokio::fs::remove_file(local_media_path(&audio_url).unwrap()).await;
+
+        // restored for one user and again from its export for another, through the queries
+        let anna = create_user(&pool, "anna@example.com").await;
+        let ben = create_user(&pool, "ben@example.com").await;
+
+        let restored = restore_and_export(&pool, anna, exported.clone()).await;
+        let restored_again = restore_and_export(&pool, ben, restored.clone()).await;
+
+        assert!(exported == restored);
+        assert!(restored == restored_again);
+    }
+
+    #[test]
+    fn upgrade_manifest_accepts_supported_versions() {
+        // version 1 is the oldest version, there are no upgrade steps yet
+        let upgraded = upgrade_manifest(manifest(1)).unwrap();
+
+        assert_eq!(upgraded, manifest(BUNDLE_VERSION));
+        assert!(read_bundle(bundle_file(&manifest(1))).is_ok());
+    }
+
+    #[test]
+    fn upgrade_manifest_rejects_newer_and_unknown_versions() {
+        let newer = manifest(BUNDLE_VERSION + 1);
+
+        assert!(upgrade_manifest(newer.clone())
+            .unwrap_err()
+            .contains("newer version"));
+        assert!(read_bundle(bundle_file(&newer))
+            .err()
+            .unwrap()
+            .contains("newer version"));
+
+        assert!(upgrade_manifest(manifest(0)).is_err());
+
+        let mut other_format = manifest(1);
+        other_format["format"] = Value::from("other");
+
+        assert!(upgrade_manifest(other_format).is_err());
+    }
+}
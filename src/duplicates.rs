@@ -0,0 +1,475 @@
+use crate::imports::ImportCard;
+use crate::Card;
+use std::collections::HashMap;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+pub const DUPLICATE_POLICY_ALLOW: &str = "allow";
+pub const DUPLICATE_POLICY_SKIP: &str = "skip";
+pub const DUPLICATE_POLICY_MERGE: &str = "merge";
+
+pub const DUPLICATE_POLICIES: [&str; 3] = [
+    DUPLICATE_POLICY_ALLOW,
+    DUPLICATE_POLICY_SKIP,
+    DUPLICATE_POLICY_MERGE,
+];
+
+// leading articles by language, decks name their languages freely, so a language is matched by
+// its german or english name or its code
+const LANGUAGE_ARTICLES: [(&[&str], &[&str]); 7] = [
+    (
+        &["deutsch", "german", "de"],
+        &[
+            "der", "die", "das", "den", "dem", "des", "ein", "eine", "einen", "einem", "einer",
+            "eines",
+        ],
+    ),
+    (&["englisch", "english", "en"], &["the", "a", "an"]),
+    (
+        &["französisch", "french", "français", "fr"],
+        &["le", "la", "les", "l'", "un", "une", "des"],
+    ),
+    (
+        &["spanisch", "spanish", "español", "es"],
+        &["el", "la", "los", "las", "un", "una", "unos", "unas"],
+    ),
+    (
+        &["italienisch", "italian", "italiano", "it"],
+        &[
+            "il", "lo", "la", "i", "gli", "le", "l'", "un", "uno", "una", "un'",
+        ],
+    ),
+    (
+        &["niederländisch", "dutch", "nederlands", "nl"],
+        &["de", "het", "een", "'t"],
+    ),
+    (
+        &["portugiesisch", "portuguese", "português", "pt"],
+        &["o", "a", "os", "as", "um", "uma", "uns", "umas"],
+    ),
+];
+
+// cards that share the normalised front text, in the order of their ids
+#[derive(serde::Serialize)]
+pub struct DuplicateGroup {
+    pub key: String,
+    pub cards: Vec<Card>,
+}
+
+// what happens to an imported card whose front text is already taken
+pub enum DuplicateResolution {
+    Insert(ImportCard),
+    // the card fills the blank fields of the card of the deck with this id
+    Merge(i32, ImportCard),
+}
+
+// helpers
+
+fn language_articles(language: &str) -> &'static [&'static str] {
+    let language = language.trim().to_lowercase();
+
+    LANGUAGE_ARTICLES
+        .iter()
+        .find(|(names, _)| names.contains(&language.as_str()))
+        .map_or(&[], |(_, articles)| articles)
+}
+
+fn strip_diacritics(text: &str) -> String {
+    text.nfd()
+        .filter(|char| !is_combining_mark(*char))
+        .flat_map(|char| match char {
+            'ß' => vec!['s', 's'],
+            'æ' => vec!['a', 'e'],
+            'œ' => vec!['o', 'e'],
+            'ø' => vec!['o'],
+            'ł' => vec!['l'],
+            char => vec![char],
+        })
+        .collect()
+}
+
+fn strip_article<'a>(text: &'a str, articles: &[&str]) -> &'a str {
+    for article in articles {
+        // elided articles like l'eau are attached to the word
+        let rest = if article.ends_with('\'') {
+            text.strip_prefix(article)
+        } else {
+            text.strip_prefix(article)
+                .and_then(|rest| rest.strip_prefix(' '))
+        };
+
+        match rest {
+            Some(rest) if !rest.trim().is_empty() => return rest.trim_start(),
+            _ => {}
+        }
+    }
+
+    text
+}
+
+fn merge_text(target: &mut Option<String>, source: Option<String>) {
+    if target.is_none() {
+        *target = source;
+    }
+}
+
+fn merge_import_card(target: &mut ImportCard, source: ImportCard) {
+    merge_text(&mut target.to_text_secondary, source.to_text_secondary);
+    merge_text(&mut target.example_text, source.example_text);
+
+    if target.audio_file.is_none() && target.audio_url.is_none() {
+        target.audio_file = source.audio_file;
+        target.audio_url = source.audio_url;
+    }
+
+    for tag in source.tags {
+        if !target.tags.contains(&tag) {
+            target.tags.push(tag);
+        }
+    }
+}
+
+// normalise
+
+// the text as words are compared against a deck: without case, repeated whitespace and a leading
+// article of the language when asked to, but with its diacritics, which keep schon and schön apart
+pub fn fold_text(text: &str, language: &str, ignore_articles: bool) -> String {
+    let text = text
+        .nfc()
+        .collect::<String>()
+        .to_lowercase()
+        .replace('’', "'")
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ");
+
+    if ignore_articles {
+        String::from(strip_article(&text, language_articles(language)))
+    } else {
+        text
+    }
+}
+
+// the text as it is compared for duplicates: folded and without diacritics
+pub fn normalize_text(text: &str, language: &str, ignore_articles: bool) -> String {
+    strip_diacritics(&fold_text(text, language, ignore_articles))
+}
+
+// groups of two or more cards whose front texts are the same once normalised
+pub fn find_duplicates(
+    cards: Vec<Card>,
+    language: &str,
+    ignore_articles: bool,
+) -> Vec<DuplicateGroup> {
+    let mut groups: Vec<DuplicateGroup> = Vec::new();
+    let mut group_indexes: HashMap<String, usize> = HashMap::new();
+
+    for card in cards {
+        let key = normalize_text(&card.from_text, language, ignore_articles);
+
+        match group_indexes.get(&key) {
+            Some(index) => groups[*index].cards.push(card),
+            None => {
+                group_indexes.insert(key.clone(), groups.len());
+                groups.push(DuplicateGroup {
+                    key,
+                    cards: vec![card],
+                });
+            }
+        }
+    }
+
+    groups.retain(|group| group.cards.len() > 1);
+
+    for group in &mut groups {
+        group.cards.sort_by_key(|card| card.id);
+    }
+
+    groups
+}
+
+// applies the policy to the cards of an import against the cards already in the deck and against
+// each other. skipped cards are dropped and merged cards fill the blank fields of the first card
+// with the same text, the rest is inserted
+pub fn resolve_duplicates(
+    import_cards: Vec<ImportCard>,
+    deck_cards: &[Card],
+    language: &str,
+    policy: &str,
+    ignore_articles: bool,
+) -> Vec<DuplicateResolution> {
+    if policy == DUPLICATE_POLICY_ALLOW {
+        return import_cards
+            .into_iter()
+            .map(DuplicateResolution::Insert)
+            .collect();
+    }
+
+    let mut deck_card_ids: HashMap<String, i32> = HashMap::new();
+
+    for card in deck_cards {
+        deck_card_ids
+            .entry(normalize_text(&card.from_text, language, ignore_articles))
+            .or_insert(card.id);
+    }
+
+    let mut resolutions: Vec<DuplicateResolution> = Vec::new();
+    let mut resolution_indexes: HashMap<String, usize> = HashMap::new();
+
+    for import_card in import_cards {
+        let key = normalize_text(&import_card.from_text, language, ignore_articles);
+
+        if let Some(index) = resolution_indexes.get(&key) {
+            if policy == DUPLICATE_POLICY_MERGE {
+                let (DuplicateResolution::Insert(target) | DuplicateResolution::Merge(_, target)) =
+                    &mut resolutions[*index];
+                merge_import_card(target, import_card);
+            }
+
+            continue;
+        }
+
+        let resolution = match deck_card_ids.get(&key) {
+            Some(_) if policy == DUPLICATE_POLICY_SKIP => continue,
+            Some(card_id) => DuplicateResolution::Merge(*card_id, import_card),
+            None => DuplicateResolution::Insert(import_card),
+        };
+
+        resolution_indexes.insert(key, resolutions.len());
+        resolutions.push(resolution);
+    }
+
+    resolutions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn card(id: i32, from_text: &str) -> Card {
+        let now = chrono::Utc::now().naive_utc();
+
+        Card {
+            id,
+            deck_id: 1,
+            related_card_ids: Vec::new(),
+            from_text: String::from(from_text),
+            to_text_primary: String::from("back"),
+            to_text_secondary: None,
+            example_text: None,
+            audio_url: None,
+            seen_at: now,
+            seen_for: None,
+            rating: 0,
+            prev_rating: 0,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    fn import_card(from_text: &str, example_text: Option<&str>, tags: &[&str]) -> ImportCard {
+        ImportCard {
+            from_text: String::from(from_text),
+            to_text_primary: String::from("back"),
+            to_text_secondary: None,
+            example_text: example_text.map(String::from),
+            audio_file: None,
+            audio_url: None,
+            tags: tags.iter().map(|tag| String::from(*tag)).collect(),
+        }
+    }
+
+    // merge target, front text, example text and tags of a resolution
+    type Described<'a> = (Option<i32>, &'a str, Option<&'a str>, Vec<&'a str>);
+
+    fn describe(resolutions: &[DuplicateResolution]) -> Vec<Described<'_>> {
+        resolutions
+            .iter()
+            .map(|resolution| {
+                let (card_id, card) = match resolution {
+                    DuplicateResolution::Insert(card) => (None, card),
+                    DuplicateResolution::Merge(card_id, card) => (Some(*card_id), card),
+                };
+
+                (
+                    card_id,
+                    card.from_text.as_str(),
+                    card.example_text.as_deref(),
+                    card.tags.iter().map(String::as_str).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn normalize_text_ignores_case_and_whitespace() {
+        assert_eq!(
+            normalize_text("  Guten   MORGEN\t", "Deutsch", false),
+            "guten morgen"
+        );
+        assert_eq!(
+            normalize_text("Hund", "Deutsch", false),
+            normalize_text("HUND", "de", false)
+        );
+    }
+
+    #[test]
+    fn normalize_text_strips_diacritics() {
+        assert_eq!(normalize_text("Müde", "Deutsch", false), "mude");
+        assert_eq!(normalize_text("Straße", "Deutsch", false), "strasse");
+        assert_eq!(normalize_text("STRASSE", "Deutsch", false), "strasse");
+        assert_eq!(normalize_text("Ægir", "Deutsch", false), "aegir");
+        assert_eq!(normalize_text("cœur", "French", false), "coeur");
+        assert_eq!(normalize_text("Søren", "Dansk", false), "soren");
+        assert_eq!(normalize_text("café", "French", false), "cafe");
+        // composed and decomposed forms are the same text
+        assert_eq!(
+            normalize_text("e\u{301}te\u{301}", "French", false),
+            normalize_text("\u{e9}t\u{e9}", "French", false)
+        );
+    }
+
+    #[test]
+    fn fold_text_keeps_diacritics() {
+        assert_eq!(fold_text("  Schön ", "Deutsch", false), "schön");
+        assert_eq!(fold_text("die Bär", "Deutsch", true), "bär");
+        assert_ne!(
+            fold_text("schon", "Deutsch", false),
+            fold_text("schön", "Deutsch", false)
+        );
+        assert_eq!(
+            fold_text("e\u{301}te\u{301}", "French", false),
+            fold_text("\u{e9}t\u{e9}", "French", false)
+        );
+    }
+
+    #[test]
+    fn normalize_text_strips_articles_of_the_language() {
+        assert_eq!(normalize_text("der Hund", "Deutsch", true), "hund");
+        assert_eq!(normalize_text("Die  Katze", "german", true), "katze");
+        assert_eq!(normalize_text("the dog", "English", true), "dog");
+        assert_eq!(normalize_text("les enfants", "fr", true), "enfants");
+        assert_eq!(normalize_text("l'eau", "Französisch", true), "eau");
+        assert_eq!(normalize_text("L’eau", "Französisch", true), "eau");
+        assert_eq!(normalize_text("un'amica", "Italiano", true), "amica");
+        assert_eq!(normalize_text("'t huis", "Dutch", true), "huis");
+
+        // only leading whole words of the deck language are articles
+        assert_eq!(normalize_text("der Hund", "Deutsch", false), "der hund");
+        assert_eq!(normalize_text("the dog", "Deutsch", true), "the dog");
+        assert_eq!(normalize_text("Dieb", "Deutsch", true), "dieb");
+        assert_eq!(normalize_text("die", "Deutsch", true), "die");
+        assert_eq!(normalize_text("le", "French", true), "le");
+    }
+
+    #[test]
+    fn find_duplicates_groups_normalised_texts() {
+        let cards = vec![
+            card(4, "die Straße"),
+            card(1, "Hund"),
+            card(2, "Strasse"),
+            card(3, "hund "),
+            card(5, "Katze"),
+        ];
+
+        let groups = find_duplicates(cards, "Deutsch", true);
+
+        let groups: Vec<(&str, Vec<i32>)> = groups
+            .iter()
+            .map(|group| {
+                (
+                    group.key.as_str(),
+                    group.cards.iter().map(|card| card.id).collect(),
+                )
+            })
+            .collect();
+
+        assert_eq!(groups, vec![("strasse", vec![2, 4]), ("hund", vec![1, 3])]);
+    }
+
+    #[test]
+    fn resolve_duplicates_allows_everything() {
+        let resolutions = resolve_duplicates(
+            vec![
+                import_card("Hund", None, &[]),
+                import_card("hund", None, &[]),
+            ],
+            &[card(1, "Hund")],
+            "Deutsch",
+            DUPLICATE_POLICY_ALLOW,
+            true,
+        );
+
+        assert_eq!(
+            describe(&resolutions),
+            vec![(None, "Hund", None, vec![]), (None, "hund", None, vec![]),]
+        );
+    }
+
+    #[test]
+    fn resolve_duplicates_skips_cards_of_the_deck_and_repeats() {
+        let resolutions = resolve_duplicates(
+            vec![
+                import_card("der Hund", None, &[]),
+                import_card("Katze", None, &[]),
+                import_card("KATZE", Some("Die Katze schläft."), &[]),
+                import_card("Maus", None, &[]),
+            ],
+            &[card(1, "Hund")],
+            "Deutsch",
+            DUPLICATE_POLICY_SKIP,
+            true,
+        );
+
+        assert_eq!(
+            describe(&resolutions),
+            vec![(None, "Katze", None, vec![]), (None, "Maus", None, vec![])]
+        );
+    }
+
+    #[test]
+    fn resolve_duplicates_merges_into_the_first_card() {
+        let resolutions = resolve_duplicates(
+            vec![
+                import_card("der Hund", None, &["a1"]),
+                import_card("Hund", Some("Der Hund bellt."), &["a1", "tiere"]),
+                import_card("Katze", None, &[]),
+                import_card("katze", Some("Die Katze schläft."), &["tiere"]),
+                import_card("Katze", Some("Noch ein Satz."), &[]),
+            ],
+            &[card(7, "Hund"), card(8, "hund")],
+            "Deutsch",
+            DUPLICATE_POLICY_MERGE,
+            true,
+        );
+
+        assert_eq!(
+            describe(&resolutions),
+            vec![
+                (
+                    Some(7),
+                    "der Hund",
+                    Some("Der Hund bellt."),
+                    vec!["a1", "tiere"]
+                ),
+                (None, "Katze", Some("Die Katze schläft."), vec!["tiere"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn resolve_duplicates_keeps_articles_apart_when_asked_to() {
+        let resolutions = resolve_duplicates(
+            vec![import_card("der Hund", None, &[])],
+            &[card(1, "Hund")],
+            "Deutsch",
+            DUPLICATE_POLICY_SKIP,
+            false,
+        );
+
+        assert_eq!(
+            describe(&resolutions),
+            vec![(None, "der Hund", None, vec![])]
+        );
+    }
+}
@@ -0,0 +1,222 @@
+use crate::webhooks::dispatch_event;
+use crate::AppState;
+use axum::response::sse::Event;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+
+const CHANNEL_CAPACITY: usize = 256;
+const HISTORY_CAPACITY: usize = 1000;
+
+// event model
+
+#[derive(Clone, Copy)]
+pub enum EventType {
+    CardCreated,
+    CardUpdated,
+    CardRated,
+    CardDeleted,
+    DeckCreated,
+    DeckUpdated,
+    DeckDeleted,
+}
+
+impl EventType {
+    pub const ALL: [EventType; 7] = [
+        EventType::CardCreated,
+        EventType::CardUpdated,
+        EventType::CardRated,
+        EventType::CardDeleted,
+        EventType::DeckCreated,
+        EventType::DeckUpdated,
+        EventType::DeckDeleted,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventType::CardCreated => "card.created",
+            EventType::CardUpdated => "card.updated",
+            EventType::CardRated => "card.rated",
+            EventType::CardDeleted => "card.deleted",
+            EventType::DeckCreated => "deck.created",
+            EventType::DeckUpdated => "deck.updated",
+            EventType::DeckDeleted => "deck.deleted",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ChangeEvent {
+    pub id: u64,
+    pub user_id: i32,
+    pub event_type: EventType,
+    pub payload: Value,
+}
+
+impl ChangeEvent {
+    pub fn to_sse_event(&self) -> Event {
+        Event::default()
+            .id(self.id.to_string())
+            .event(self.event_type.as_str())
+            .data(self.payload.to_string())
+    }
+}
+
+// tells a client that it missed events and has to reload its data
+pub fn reset_sse_event() -> Event {
+    Event::default().event("reset").data("{}")
+}
+
+// in-process event bus
+
+struct EventLog {
+    // recent events, kept so that reconnecting clients can catch up
+    events: VecDeque<ChangeEvent>,
+    next_id: u64,
+}
+
+pub struct EventBus {
+    sender: broadcast::Sender<ChangeEvent>,
+    log: Mutex<EventLog>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+
+        EventBus {
+            sender,
+            log: Mutex::new(EventLog {
+                events: VecDeque::with_capacity(HISTORY_CAPACITY),
+                // ids start at the boot time so they keep increasing across restarts
+                next_id: chrono::Utc::now().timestamp_millis() as u64,
+            }),
+        }
+    }
+
+    fn publish(&self, user_id: i32, event_type: EventType, payload: Value) {
+        let mut log = self.log.lock().unwrap();
+
+        let event = ChangeEvent {
+            id: log.next_id,
+            user_id,
+            event_type,
+            payload,
+        };
+
+        log.next_id += 1;
+
+        if log.events.len() == HISTORY_CAPACITY {
+            log.events.pop_front();
+        }
+        log.events.push_back(event.clone());
+
+        // sending only fails when nobody is listening
+        let _ = self.sender.send(event);
+    }
+
+    // returns the buffered events after the given id, or None if some of them were already dropped
+    pub fn subscribe_after(
+        &self,
+        last_event_id: Option<u64>,
+    ) -> (Option<Vec<ChangeEvent>>, broadcast::Receiver<ChangeEvent>) {
+        let log = self.log.lock().unwrap();
+
+        // subscribing while holding the lock means no event is missed or sent twice
+        let receiver = self.sender.subscribe();
+
+        let last_event_id = match last_event_id {
+            Some(last_event_id) => last_event_id,
+            None => return (Some(Vec::new()), receiver),
+        };
+
+        let first_event_id = log.events.front().map_or(log.next_id, |event| event.id);
+
+        // the id is client supplied, so one that this log never handed out also means a reset
+        if last_event_id.saturating_add(1) < first_event_id || last_event_id >= log.next_id {
+            return (None, receiver);
+        }
+
+        let missed = log
+            .events
+            .iter()
+            .filter(|event| event.id > last_event_id)
+            .cloned()
+            .collect();
+
+        (Some(missed), receiver)
+    }
+}
+
+// publish
+
+pub async fn publish_event<T: Serialize>(
+    app_state: &AppState,
+    user_id: i32,
+    event_type: EventType,
+    data: &T,
+) {
+    let payload = json!({
+        "event": event_type.as_str(),
+        "occurred_at": chrono::Utc::now().naive_utc(),
+        "data": data,
+    });
+
+    app_state
+        .events
+        .publish(user_id, event_type, payload.clone());
+
+    dispatch_event(&app_state.pool, user_id, event_type, payload).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn replayed_ids(event_bus: &EventBus, last_event_id: Option<u64>) -> Option<Vec<u64>> {
+        event_bus
+            .subscribe_after(last_event_id)
+            .0
+            .map(|events| events.iter().map(|event| event.id).collect())
+    }
+
+    #[test]
+    fn subscribe_after_replays_missed_events() {
+        let event_bus = EventBus::new();
+
+        for _ in 0..3 {
+            event_bus.publish(1, EventType::CardCreated, json!({}));
+        }
+
+        let first_event_id = event_bus.log.lock().unwrap().events[0].id;
+
+        assert_eq!(replayed_ids(&event_bus, None), Some(Vec::new()));
+        assert_eq!(
+            replayed_ids(&event_bus, Some(first_event_id)),
+            Some(vec![first_event_id + 1, first_event_id + 2])
+        );
+        assert_eq!(
+            replayed_ids(&event_bus, Some(first_event_id + 2)),
+            Some(Vec::new())
+        );
+        assert_eq!(
+            replayed_ids(&event_bus, Some(first_event_id - 1)),
+            Some(vec![first_event_id, first_event_id + 1, first_event_id + 2])
+        );
+    }
+
+    #[test]
+    fn subscribe_after_resets_on_unknown_ids() {
+        let event_bus = EventBus::new();
+
+        event_bus.publish(1, EventType::CardCreated, json!({}));
+
+        let first_event_id = event_bus.log.lock().unwrap().events[0].id;
+
+        assert_eq!(replayed_ids(&event_bus, Some(first_event_id - 2)), None);
+        assert_eq!(replayed_ids(&event_bus, Some(first_event_id + 1)), None);
+        assert_eq!(replayed_ids(&event_bus, Some(u64::MAX)), None);
+    }
+}
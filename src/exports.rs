@@ -0,0 +1,199 @@
+use crate::queries::stream_card_exports_query;
+use crate::{CardExport, Deck};
+use axum::body::Body;
+use chrono::NaiveDateTime;
+use sqlx::{Pool, Postgres};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+
+// rows are sent in chunks of about this size, with at most a few chunks waiting for the client
+const EXPORT_CHUNK_SIZE: usize = 64 * 1024;
+const EXPORT_CHANNEL_SIZE: usize = 4;
+
+// the format serde uses for the timestamps in json
+const DATE_TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.f";
+
+const CSV_EXPORT_COLUMNS: [&str; 18] = [
+    "deck_id",
+    "from_language",
+    "to_language_primary",
+    "to_language_secondary",
+    "id",
+    "from_text",
+    "to_text_primary",
+    "to_text_secondary",
+    "example_text",
+    "audio_url",
+    "related_card_ids",
+    "tags",
+    "rating",
+    "prev_rating",
+    "seen_at",
+    "seen_for",
+    "created_at",
+    "updated_at",
+];
+
+#[derive(Clone, Copy)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Json => "application/json",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+        }
+    }
+}
+
+// helpers
+
+fn format_date_time(date_time: NaiveDateTime) -> String {
+    date_time.format(DATE_TIME_FORMAT).to_string()
+}
+
+fn csv_record(card_export: &CardExport) -> Vec<String> {
+    let card = &card_export.card;
+
+    let related_card_ids: Vec<String> = card
+        .related_card_ids
+        .iter()
+        .map(|id| id.to_string())
+        .collect();
+
+    vec![
+        card.deck_id.to_string(),
+        card_export.from_language.clone(),
+        card_export.to_language_primary.clone(),
+        card_export
+            .to_language_secondary
+            .clone()
+            .unwrap_or_default(),
+        card.id.to_string(),
+        card.from_text.clone(),
+        card.to_text_primary.clone(),
+        card.to_text_secondary.clone().unwrap_or_default(),
+        card.example_text.clone().unwrap_or_default(),
+        card.audio_url.clone().unwrap_or_default(),
+        related_card_ids.join(","),
+        card_export.tags.join(","),
+        card.rating.to_string(),
+        card.prev_rating.to_string(),
+        format_date_time(card.seen_at),
+        card.seen_for
+            .map(|seen_for| seen_for.to_string())
+            .unwrap_or_default(),
+        format_date_time(card.created_at),
+        format_date_time(card.updated_at),
+    ]
+}
+
+fn write_csv_record(chunk: &mut Vec<u8>, record: &[String]) -> Result<(), csv::Error> {
+    let mut writer = csv::Writer::from_writer(chunk);
+    writer.write_record(record)?;
+    writer.flush()?;
+    Ok(())
+}
+
+// export
+
+async fn write_export(
+    pool: Pool<Postgres>,
+    user_id: i32,
+    deck_id: Option<i32>,
+    decks: Vec<Deck>,
+    format: ExportFormat,
+    sender: &mpsc::Sender<Result<Vec<u8>, std::io::Error>>,
+) -> Result<(), String> {
+    let mut chunk = Vec::with_capacity(EXPORT_CHUNK_SIZE);
+
+    // the decks are few and written up front, the cards follow as they are read
+    match format {
+        ExportFormat::Csv => write_csv_record(&mut chunk, &CSV_EXPORT_COLUMNS.map(String::from))
+            .map_err(|err| err.to_string())?,
+        ExportFormat::Json => {
+            let exported_at = serde_json::to_string(&chrono::Utc::now().naive_utc())
+                .map_err(|err| err.to_string())?;
+            let decks = serde_json::to_string(&decks).map_err(|err| err.to_string())?;
+
+            chunk.extend_from_slice(
+                format!(
+                    "{{\"exported_at\":{},\"decks\":{},\"cards\":[",
+                    exported_at, decks
+                )
+                .as_bytes(),
+            );
+        }
+    }
+
+    let mut card_exports = stream_card_exports_query(&pool, user_id, deck_id);
+    let mut is_first = true;
+
+    while let Some(card_export) = card_exports.next().await {
+        let card_export = card_export.map_err(|err| err.to_string())?;
+
+        match format {
+            ExportFormat::Csv => write_csv_record(&mut chunk, &csv_record(&card_export))
+                .map_err(|err| err.to_string())?,
+            ExportFormat::Json => {
+                if !is_first {
+                    chunk.push(b',');
+                }
+
+                serde_json::to_writer(&mut chunk, &card_export).map_err(|err| err.to_string())?;
+            }
+        }
+
+        is_first = false;
+
+        if chunk.len() >= EXPORT_CHUNK_SIZE {
+            let full_chunk = std::mem::replace(&mut chunk, Vec::with_capacity(EXPORT_CHUNK_SIZE));
+
+            // the client went away
+            if sender.send(Ok(full_chunk)).await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+
+    if let ExportFormat::Json = format {
+        chunk.extend_from_slice(b"]}");
+    }
+
+    let _ = sender.send(Ok(chunk)).await;
+
+    Ok(())
+}
+
+// streams the cards of the decks with their scheduling fields, a failed read ends the body with
+// an error, so that the client does not take a cut off export for a complete one
+pub fn stream_export(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+    deck_id: Option<i32>,
+    decks: Vec<Deck>,
+    format: ExportFormat,
+) -> Body {
+    let (sender, receiver) = mpsc::channel(EXPORT_CHANNEL_SIZE);
+    let pool = pool.clone();
+
+    tokio::spawn(async move {
+        if let Err(err) = write_export(pool, user_id, deck_id, decks, format, &sender).await {
+            eprintln!("Failed to export cards of user {}. Error: {}", user_id, err);
+            let _ = sender.send(Err(std::io::Error::other(err))).await;
+        }
+    });
+
+    Body::from_stream(ReceiverStream::new(receiver))
+}
@@ -0,0 +1,182 @@
+use crate::imports::IMPORT_MAX_SIZE;
+use crate::queries::{
+    claim_idempotency_key_query, complete_idempotency_key_query, delete_idempotency_key_query,
+    StoredIdempotencyKey,
+};
+use crate::AppState;
+use axum::body::{to_bytes, Body};
+use axum::extract::{Query, Request, State};
+use axum::http::{header, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+const IDEMPOTENCY_KEY_MAX_LENGTH: usize = 255;
+// the largest body any route accepts, the route limits still apply when the handler reads it
+const MAX_BODY_SIZE: usize = IMPORT_MAX_SIZE;
+// a claim this old without a response is left over from a dropped request
+const CLAIM_TIMEOUT_SECONDS: f64 = 300.0;
+
+// helpers
+
+fn error_response(status: StatusCode, message: &str) -> Response {
+    let body = json!({
+        "data": null,
+        "error": { "message": message },
+    });
+
+    (status, Json(body)).into_response()
+}
+
+fn hash_request(method: &Method, path: &str, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+
+    hasher.update(method.as_str().as_bytes());
+    hasher.update(b" ");
+    hasher.update(path.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(body);
+
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn replay_response(stored: StoredIdempotencyKey, request_hash: &str) -> Response {
+    if stored.request_hash != request_hash {
+        return error_response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Idempotency-Key was already used for a different request",
+        );
+    }
+
+    match (stored.response_status, stored.response_body) {
+        (Some(status), Some(body)) => (
+            StatusCode::from_u16(status as u16).unwrap_or(StatusCode::OK),
+            [
+                (header::CONTENT_TYPE, "application/json"),
+                (
+                    header::HeaderName::from_static("idempotent-replayed"),
+                    "true",
+                ),
+            ],
+            body,
+        )
+            .into_response(),
+        _ => error_response(
+            StatusCode::CONFLICT,
+            "A request with this Idempotency-Key is still being processed",
+        ),
+    }
+}
+
+// middleware
+
+// runs a POST carrying an Idempotency-Key once and answers retries within 24 hours with the stored
+// response, failed requests release their key so that they can be retried, and so do requests that
+// were dropped before finishing once their claim times out
+pub async fn idempotency(
+    State(app_state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if request.method() != Method::POST {
+        return next.run(request).await;
+    }
+
+    let idempotency_key = match request.headers().get(IDEMPOTENCY_KEY_HEADER) {
+        None => return next.run(request).await,
+        Some(value) => match value.to_str().map(str::trim) {
+            Ok(key) if !key.is_empty() && key.len() <= IDEMPOTENCY_KEY_MAX_LENGTH => {
+                String::from(key)
+            }
+            _ => {
+                return error_response(
+                    StatusCode::BAD_REQUEST,
+                    "Idempotency-Key must be between 1 and 255 characters",
+                )
+            }
+        },
+    };
+
+    // unauthorized requests are left to the handler to reject
+    let uuid = Query::<HashMap<String, String>>::try_from_uri(request.uri())
+        .ok()
+        .and_then(|Query(query)| query.get("uuid").cloned());
+
+    let user_id = match (&app_state.user, uuid) {
+        (Some(user), Some(uuid)) if uuid == app_state.uuid => user.id,
+        _ => return next.run(request).await,
+    };
+
+    let (parts, body) = request.into_parts();
+
+    let request_body = match to_bytes(body, MAX_BODY_SIZE).await {
+        Ok(request_body) => request_body,
+        Err(_) => {
+            return error_response(StatusCode::PAYLOAD_TOO_LARGE, "Request body is too large")
+        }
+    };
+
+    let request_hash = hash_request(&parts.method, parts.uri.path(), &request_body);
+
+    let claim = claim_idempotency_key_query(
+        &app_state.pool,
+        user_id,
+        &idempotency_key,
+        &request_hash,
+        CLAIM_TIMEOUT_SECONDS,
+    )
+    .await;
+
+    match claim {
+        Ok(None) => {}
+        Ok(Some(stored)) => return replay_response(stored, &request_hash),
+        Err(err) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, &err.to_string()),
+    }
+
+    let response = next
+        .run(Request::from_parts(parts, Body::from(request_body)))
+        .await;
+
+    let (parts, body) = response.into_parts();
+
+    let response_body = match to_bytes(body, usize::MAX).await {
+        Ok(response_body) => response_body,
+        Err(err) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, &err.to_string()),
+    };
+
+    // errors come back as 200 with an error envelope, those are released as well
+    let succeeded = parts.status.is_success()
+        && serde_json::from_slice::<Value>(&response_body)
+            .map_or(true, |body| body.get("error").is_none_or(Value::is_null));
+
+    let result = if succeeded {
+        complete_idempotency_key_query(
+            &app_state.pool,
+            user_id,
+            &idempotency_key,
+            i32::from(parts.status.as_u16()),
+            String::from_utf8_lossy(&response_body).into_owned(),
+        )
+        .await
+    } else {
+        delete_idempotency_key_query(&app_state.pool, user_id, &idempotency_key).await
+    };
+
+    if let Err(err) = result {
+        eprintln!(
+            "Failed to store idempotency key {}. Error: {}",
+            idempotency_key, err
+        );
+    }
+
+    Response::from_parts(parts, Body::from(response_body))
+}
@@ -0,0 +1,579 @@
+use crate::paste::PasteImportDetails;
+use crate::spreadsheet::CsvImportDetails;
+use crate::validation::Validate;
+use crate::{AppState, CardForm};
+use chrono::NaiveDateTime;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use std::path::PathBuf;
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+const IMPORT_MAX_AGE_HOURS: i64 = 1;
+const PREVIEW_CARD_COUNT: usize = 10;
+
+pub const IMPORT_MAX_SIZE: usize = 256 * 1024 * 1024;
+
+// how much a zip upload may unpack to, per file and in total
+const ZIP_ENTRY_MAX_SIZE: u64 = IMPORT_MAX_SIZE as u64;
+const ZIP_TOTAL_MAX_SIZE: u64 = 2 * IMPORT_MAX_SIZE as u64;
+
+// the back of cards from imports that only know the word, like kindle lookups or subtitles. it is
+// filled in later on the card
+pub const TRANSLATION_PLACEHOLDER: &str = "…";
+
+// audio is the only media a card can hold
+const AUDIO_EXTENSIONS: [&str; 6] = ["mp3", "ogg", "oga", "wav", "m4a", "opus"];
+const MEDIA_DIRECTORY: &str = "assets/media";
+
+// import model
+
+#[derive(Clone, serde::Serialize)]
+pub struct ImportCard {
+    pub from_text: String,
+    pub to_text_primary: String,
+    pub to_text_secondary: Option<String>,
+    pub example_text: Option<String>,
+    // name of a file in the import's media, saved when the import is committed
+    pub audio_file: Option<String>,
+    // kept as is, for imports that link their audio
+    pub audio_url: Option<String>,
+    pub tags: Vec<String>,
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct ImportDeck {
+    // the existing deck the cards go into, None for a new deck
+    pub deck_id: Option<i32>,
+    pub name: String,
+    pub from_language: String,
+    pub to_language_primary: String,
+    pub to_language_secondary: Option<String>,
+    pub cards: Vec<ImportCard>,
+}
+
+// a note or row that is skipped, or imported but worth a second look
+#[derive(Clone, serde::Serialize)]
+pub struct ImportNoteReport {
+    pub deck_name: String,
+    // line of the uploaded file, for formats that have lines
+    pub line: Option<u64>,
+    pub text: String,
+    pub message: String,
+}
+
+// a parsed upload that waits for the user to look at the preview and commit it
+pub struct PendingImport {
+    pub id: String,
+    pub user_id: i32,
+    pub source: String,
+    pub decks: Vec<ImportDeck>,
+    pub skipped: Vec<ImportNoteReport>,
+    pub flagged: Vec<ImportNoteReport>,
+    pub media: HashMap<String, Vec<u8>>,
+    pub unsupported_media: Vec<String>,
+    pub csv: Option<CsvImportDetails>,
+    pub paste: Option<PasteImportDetails>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(serde::Serialize)]
+pub struct ImportDeckPreview {
+    pub deck_id: Option<i32>,
+    pub name: String,
+    pub from_language: String,
+    pub to_language_primary: String,
+    pub to_language_secondary: Option<String>,
+    pub num_cards: usize,
+    pub cards: Vec<ImportCard>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ImportPreview {
+    pub id: String,
+    pub source: String,
+    // the deck the cards go into, for imports that hold a single existing deck
+    pub deck_id: Option<i32>,
+    pub num_cards: usize,
+    pub decks: Vec<ImportDeckPreview>,
+    pub skipped: Vec<ImportNoteReport>,
+    pub flagged: Vec<ImportNoteReport>,
+    pub num_audio_files: usize,
+    pub unsupported_media: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub csv: Option<CsvImportDetails>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub paste: Option<PasteImportDetails>,
+}
+
+impl ImportPreview {
+    // such imports keep the languages of their decks
+    pub fn goes_into_existing_decks(&self) -> bool {
+        !self.decks.is_empty() && self.decks.iter().all(|deck| deck.deck_id.is_some())
+    }
+}
+
+impl PendingImport {
+    pub fn new(user_id: i32, source: &str) -> PendingImport {
+        PendingImport {
+            id: generate_import_id(),
+            user_id,
+            source: String::from(source),
+            decks: Vec::new(),
+            skipped: Vec::new(),
+            flagged: Vec::new(),
+            media: HashMap::new(),
+            unsupported_media: Vec::new(),
+            csv: None,
+            paste: None,
+            created_at: chrono::Utc::now().naive_utc(),
+        }
+    }
+
+    pub fn preview(&self) -> ImportPreview {
+        let decks = self
+            .decks
+            .iter()
+            .map(|deck| ImportDeckPreview {
+                deck_id: deck.deck_id,
+                name: deck.name.clone(),
+                from_language: deck.from_language.clone(),
+                to_language_primary: deck.to_language_primary.clone(),
+                to_language_secondary: deck.to_language_secondary.clone(),
+                num_cards: deck.cards.len(),
+                cards: deck
+                    .cards
+                    .iter()
+                    .take(PREVIEW_CARD_COUNT)
+                    .cloned()
+                    .collect(),
+            })
+            .collect();
+
+        let num_audio_files = self
+            .decks
+            .iter()
+            .flat_map(|deck| &deck.cards)
+            .filter(|card| card.audio_file.is_some() || card.audio_url.is_some())
+            .count();
+
+        ImportPreview {
+            id: self.id.clone(),
+            source: self.source.clone(),
+            deck_id: match self.decks.as_slice() {
+                [deck] => deck.deck_id,
+                _ => None,
+            },
+            num_cards: self.decks.iter().map(|deck| deck.cards.len()).sum(),
+            decks,
+            skipped: self.skipped.clone(),
+            flagged: self.flagged.clone(),
+            num_audio_files,
+            unsupported_media: self.unsupported_media.clone(),
+            csv: self.csv.clone(),
+            paste: self.paste.clone(),
+        }
+    }
+
+    // checks the texts against the card limits, cards that do not fit are reported instead
+    pub fn add_card(&mut self, deck_index: usize, card: ImportCard, line: Option<u64>) -> bool {
+        let card_form = CardForm {
+            from_text: Some(card.from_text.clone()),
+            to_text_primary: Some(card.to_text_primary.clone()),
+            to_text_secondary: card.to_text_secondary.clone(),
+            example_text: card.example_text.clone(),
+            audio_url: card.audio_url.clone(),
+            seen_at: None,
+            seen_for: None,
+            rating: None,
+            tags: Some(card.tags.join(",")),
+        };
+
+        match card_form.validate(true) {
+            Ok(card_form) => {
+                self.decks[deck_index].cards.push(ImportCard {
+                    from_text: card_form.from_text.unwrap_or_default(),
+                    to_text_primary: card_form.to_text_primary.unwrap_or_default(),
+                    to_text_secondary: card_form.to_text_secondary,
+                    example_text: card_form.example_text,
+                    audio_file: card.audio_file,
+                    audio_url: card_form.audio_url,
+                    tags: card.tags,
+                });
+
+                true
+            }
+            Err(errors) => {
+                self.skip_card(deck_index, &card.from_text, errors.to_message(), line);
+
+                false
+            }
+        }
+    }
+
+    // drops the decks and cards that were not chosen by their indexes, cards are counted across
+    // the decks. no indexes keep all of them
+    pub fn keep_chosen(&mut self, deck_indexes: &[i32], card_indexes: &[i32]) {
+        let mut card_index: i32 = 0;
+
+        for deck in &mut self.decks {
+            deck.cards.retain(|_| {
+                let is_chosen = card_indexes.is_empty() || card_indexes.contains(&card_index);
+                card_index += 1;
+                is_chosen
+            });
+        }
+
+        let mut deck_index: i32 = 0;
+
+        self.decks.retain(|_| {
+            let is_chosen = deck_indexes.is_empty() || deck_indexes.contains(&deck_index);
+            deck_index += 1;
+            is_chosen
+        });
+    }
+
+    pub fn skip_card(&mut self, deck_index: usize, text: &str, message: String, line: Option<u64>) {
+        self.skipped.push(ImportNoteReport {
+            deck_name: self.decks[deck_index].name.clone(),
+            line,
+            text: String::from(text),
+            message,
+        });
+    }
+
+    pub fn flag_card(&mut self, deck_index: usize, text: &str, message: String, line: Option<u64>) {
+        self.flagged.push(ImportNoteReport {
+            deck_name: self.decks[deck_index].name.clone(),
+            line,
+            text: String::from(text),
+            message,
+        });
+    }
+}
+
+// helpers
+
+fn generate_import_id() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+pub fn is_audio_file(name: &str) -> bool {
+    name.rsplit_once('.')
+        .is_some_and(|(_, extension)| AUDIO_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+}
+
+// turns the html of a note field into plain text
+pub fn strip_html(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+
+    for char in html.chars() {
+        match char {
+            '<' => {
+                in_tag = true;
+                text.push(' ');
+            }
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => text.push(char),
+            _ => {}
+        }
+    }
+
+    let text = text
+        .replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&");
+
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+// counts what is unpacked from one zip upload, so that a small upload cannot expand into gigabytes
+pub struct ZipBudget {
+    entry_max_size: u64,
+    remaining: u64,
+}
+
+impl Default for ZipBudget {
+    fn default() -> Self {
+        ZipBudget {
+            entry_max_size: ZIP_ENTRY_MAX_SIZE,
+            remaining: ZIP_TOTAL_MAX_SIZE,
+        }
+    }
+}
+
+// reads a file of an uploaded zip, None if there is no such file
+pub fn read_zip_entry(
+    archive: &mut ZipArchive<Cursor<Vec<u8>>>,
+    name: &str,
+    budget: &mut ZipBudget,
+) -> Result<Option<Vec<u8>>, String> {
+    let file = match archive.by_name(name) {
+        Ok(file) => file,
+        Err(ZipError::FileNotFound) => return Ok(None),
+        Err(err) => return Err(format!("has an unreadable file {}: {}", name, err)),
+    };
+
+    let limit = budget.entry_max_size.min(budget.remaining);
+    let too_large = || format!("unpacks to more than {} MB", limit / 1024 / 1024);
+
+    // the declared size is checked first, the read is capped since the declaration can lie
+    if file.size() > limit {
+        return Err(too_large());
+    }
+
+    let mut data = Vec::with_capacity(file.size() as usize);
+
+    file.take(limit + 1)
+        .read_to_end(&mut data)
+        .map_err(|err| format!("has an unreadable file {}: {}", name, err))?;
+
+    if data.len() as u64 > limit {
+        return Err(too_large());
+    }
+
+    budget.remaining -= data.len() as u64;
+
+    Ok(Some(data))
+}
+
+// store
+
+pub fn store_import(app_state: &AppState, pending_import: PendingImport) {
+    let now = chrono::Utc::now().naive_utc();
+
+    let mut imports = app_state.imports.write().unwrap();
+
+    // uploads that were neither committed nor discarded are dropped after an hour
+    imports.retain(|_, pending_import| {
+        (now - pending_import.created_at).num_hours() < IMPORT_MAX_AGE_HOURS
+    });
+
+    imports.insert(pending_import.id.clone(), pending_import);
+}
+
+pub fn read_import_preview(
+    app_state: &AppState,
+    import_id: &str,
+    user_id: i32,
+) -> Option<ImportPreview> {
+    app_state
+        .imports
+        .read()
+        .unwrap()
+        .get(import_id)
+        .filter(|pending_import| pending_import.user_id == user_id)
+        .map(PendingImport::preview)
+}
+
+// all cards of the import across its decks, for pages that let the user choose among them
+pub fn read_import_cards(
+    app_state: &AppState,
+    import_id: &str,
+    user_id: i32,
+) -> Option<Vec<ImportCard>> {
+    app_state
+        .imports
+        .read()
+        .unwrap()
+        .get(import_id)
+        .filter(|pending_import| pending_import.user_id == user_id)
+        .map(|pending_import| {
+            pending_import
+                .decks
+                .iter()
+                .flat_map(|deck| deck.cards.iter().cloned())
+                .collect()
+        })
+}
+
+// removes the import, so that committing it twice does not create the cards twice
+pub fn take_import(app_state: &AppState, import_id: &str, user_id: i32) -> Option<PendingImport> {
+    let mut imports = app_state.imports.write().unwrap();
+
+    match imports.get(import_id) {
+        Some(pending_import) if pending_import.user_id == user_id => imports.remove(import_id),
+        _ => None,
+    }
+}
+
+// media
+
+// writes the audio of the imported cards to the assets and returns the urls by media name
+pub async fn save_import_media(
+    pending_import: &PendingImport,
+) -> Result<HashMap<String, String>, std::io::Error> {
+    let mut urls = HashMap::new();
+
+    let audio_files = pending_import
+        .decks
+        .iter()
+        .flat_map(|deck| &deck.cards)
+        .filter_map(|card| card.audio_file.as_ref());
+
+    for audio_file in audio_files {
+        if urls.contains_key(audio_file) {
+            continue;
+        }
+
+        let data = match pending_import.media.get(audio_file) {
+            Some(data) => data,
+            None => continue,
+        };
+
+        let extension = audio_file
+            .rsplit_once('.')
+            .map(|(_, extension)| extension.to_lowercase())
+            .unwrap_or_default();
+
+        let file_name = format!("{}.{}", generate_import_id(), extension);
+
+        urls.insert(audio_file.clone(), save_media_file(&file_name, data).await?);
+    }
+
+    Ok(urls)
+}
+
+// where the media behind the urls is kept, tests keep theirs out of the assets
+fn media_directory() -> PathBuf {
+    if cfg!(test) {
+        std::env::temp_dir().join("square-cards-media")
+    } else {
+        PathBuf::from(MEDIA_DIRECTORY)
+    }
+}
+
+// writes a file to the assets and returns its url
+
+pub async fn save_media_file(file_name: &str, data: &[u8]) -> Result<String, std::io::Error> {
+    let directory = media_directory();
+    tokio::fs::create_dir_all(&directory).await?;
+    tokio::fs::write(directory.join(file_name), data).await?;
+
+    Ok(format!("/{}/{}", MEDIA_DIRECTORY, file_name))
+}
+
+// the file behind an audio_url that points into the imported media, None for other urls
+pub fn local_media_path(audio_url: &str) -> Option<PathBuf> {
+    let file_name = audio_url
+        .strip_prefix('/')?
+        .strip_prefix(MEDIA_DIRECTORY)?
+        .strip_prefix('/')?;
+
+    if file_name.is_empty() || file_name.contains(['/', '\\']) || file_name.starts_with('.') {
+        return None;
+    }
+
+    Some(media_directory().join(file_name))
+}
+
+// a deck of user 1 for the import tests
+#[cfg(test)]
+pub fn deck_fixture(from_language: &str) -> crate::Deck {
+    let now = chrono::Utc::now().naive_utc();
+
+    crate::Deck {
+        id: 1,
+        user_id: 1,
+        from_language: String::from(from_language),
+        to_language_primary: String::from("English"),
+        to_language_secondary: None,
+        design_key: None,
+        seen_at: now,
+        created_at: now,
+        updated_at: now,
+    }
+}
+
+// a new card of the deck fixture
+#[cfg(test)]
+pub fn card_fixture(id: i32, from_text: &str, to_text_primary: &str) -> crate::Card {
+    let now = chrono::Utc::now().naive_utc();
+
+    crate::Card {
+        id,
+        deck_id: 1,
+        related_card_ids: Vec::new(),
+        from_text: String::from(from_text),
+        to_text_primary: String::from(to_text_primary),
+        to_text_secondary: None,
+        example_text: None,
+        audio_url: None,
+        seen_at: now,
+        seen_for: None,
+        rating: 0,
+        prev_rating: 0,
+        created_at: now,
+        updated_at: now,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::FileOptions;
+    use zip::{CompressionMethod, ZipWriter};
+
+    fn zip_archive(files: &[(&str, Vec<u8>)]) -> ZipArchive<Cursor<Vec<u8>>> {
+        let mut archive = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+        for (name, data) in files {
+            archive.start_file(*name, options).unwrap();
+            archive.write_all(data).unwrap();
+        }
+
+        ZipArchive::new(Cursor::new(archive.finish().unwrap().into_inner())).unwrap()
+    }
+
+    #[test]
+    fn read_zip_entry_reads_files_within_the_budget() {
+        let mut archive = zip_archive(&[("a", vec![1; 10]), ("b", vec![2; 10])]);
+        let mut budget = ZipBudget::default();
+
+        assert_eq!(
+            read_zip_entry(&mut archive, "a", &mut budget),
+            Ok(Some(vec![1; 10]))
+        );
+        assert_eq!(
+            read_zip_entry(&mut archive, "missing", &mut budget),
+            Ok(None)
+        );
+        assert_eq!(budget.remaining, ZIP_TOTAL_MAX_SIZE - 10);
+    }
+
+    #[test]
+    fn read_zip_entry_rejects_large_files() {
+        let mut archive = zip_archive(&[("a", vec![0; 4096])]);
+        let mut budget = ZipBudget {
+            entry_max_size: 4095,
+            remaining: 1_000_000,
+        };
+
+        assert!(read_zip_entry(&mut archive, "a", &mut budget).is_err());
+    }
+
+    #[test]
+    fn read_zip_entry_caps_the_total_size() {
+        let mut archive = zip_archive(&[("a", vec![0; 3000]), ("b", vec![0; 3000])]);
+        let mut budget = ZipBudget {
+            entry_max_size: 4096,
+            remaining: 5000,
+        };
+
+        assert!(read_zip_entry(&mut archive, "a", &mut budget).is_ok());
+        assert!(read_zip_entry(&mut archive, "b", &mut budget).is_err());
+    }
+}
@@ -0,0 +1,407 @@
+use crate::duplicates::fold_text;
+use crate::imports::{
+    ImportCard, ImportDeck, ImportNoteReport, PendingImport, TRANSLATION_PLACEHOLDER,
+};
+use crate::Deck;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteConnection};
+use sqlx::{ConnectOptions, FromRow};
+use std::collections::{HashMap, HashSet};
+
+const KINDLE_TAG: &str = "kindle";
+
+// words the reader marked as learned on the kindle
+const MASTERED_CATEGORY: i64 = 100;
+
+const LOOKUPS_QUERY: &str = "
+SELECT WORDS.word, WORDS.lang AS word_language, WORDS.category, LOOKUPS.usage, LOOKUPS.book_key,
+    BOOK_INFO.title, BOOK_INFO.lang AS book_language
+FROM LOOKUPS
+JOIN WORDS ON WORDS.id = LOOKUPS.word_key
+LEFT JOIN BOOK_INFO ON BOOK_INFO.id = LOOKUPS.book_key
+ORDER BY LOOKUPS.timestamp, LOOKUPS.id
+";
+
+// iso 639-1 codes, as kindles tag books with them, and the english, german and own names of the
+// languages
+const LANGUAGE_NAMES: [(&str, &[&str]); 30] = [
+    ("ar", &["arabic", "arabisch", "العربية"]),
+    ("ca", &["catalan", "katalanisch", "català"]),
+    ("cs", &["czech", "tschechisch", "čeština"]),
+    ("da", &["danish", "dänisch", "dansk"]),
+    ("de", &["german", "deutsch"]),
+    ("el", &["greek", "griechisch", "ελληνικά"]),
+    ("en", &["english", "englisch"]),
+    ("es", &["spanish", "spanisch", "español"]),
+    ("fi", &["finnish", "finnisch", "suomi"]),
+    ("fr", &["french", "französisch", "français"]),
+    ("he", &["hebrew", "hebräisch", "עברית"]),
+    ("hi", &["hindi", "हिन्दी"]),
+    ("hu", &["hungarian", "ungarisch", "magyar"]),
+    ("id", &["indonesian", "indonesisch", "bahasa indonesia"]),
+    ("is", &["icelandic", "isländisch", "íslenska"]),
+    ("it", &["italian", "italienisch", "italiano"]),
+    ("ja", &["japanese", "japanisch", "日本語"]),
+    ("ko", &["korean", "koreanisch", "한국어"]),
+    ("nb", &["norwegian", "norwegisch", "norsk", "norsk bokmål"]),
+    ("nl", &["dutch", "niederländisch", "nederlands"]),
+    ("pl", &["polish", "polnisch", "polski"]),
+    ("pt", &["portuguese", "portugiesisch", "português"]),
+    ("ro", &["romanian", "rumänisch", "română"]),
+    ("ru", &["russian", "russisch", "русский"]),
+    ("sv", &["swedish", "schwedisch", "svenska"]),
+    ("th", &["thai", "thailändisch", "ไทย"]),
+    ("tr", &["turkish", "türkisch", "türkçe"]),
+    ("uk", &["ukrainian", "ukrainisch", "українська"]),
+    ("vi", &["vietnamese", "vietnamesisch", "tiếng việt"]),
+    ("zh", &["chinese", "chinesisch", "中文"]),
+];
+
+// a word looked up in a book, with the sentence it was found in
+#[derive(FromRow)]
+struct KindleLookup {
+    word: Option<String>,
+    word_language: Option<String>,
+    category: Option<i64>,
+    usage: Option<String>,
+    book_key: Option<String>,
+    title: Option<String>,
+    book_language: Option<String>,
+}
+
+// helpers
+
+async fn read_lookups(path: &std::path::Path) -> Result<Vec<KindleLookup>, sqlx::Error> {
+    let mut connection: SqliteConnection = SqliteConnectOptions::new()
+        .filename(path)
+        .read_only(true)
+        .connect()
+        .await?;
+
+    sqlx::query_as(LOOKUPS_QUERY)
+        .fetch_all(&mut connection)
+        .await
+}
+
+// kindles tag languages like "en" or "en-GB", the region is left out
+fn kindle_language(language: &str) -> String {
+    language
+        .split(['-', '_'])
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase()
+}
+
+// the code of a language given by its code or one of its names, like the decks name their front
+// language. unknown names are kept and match no book
+fn language_code(language: &str) -> String {
+    let language = language.trim().to_lowercase();
+
+    LANGUAGE_NAMES
+        .iter()
+        .find(|(_, names)| names.contains(&language.as_str()))
+        .map_or_else(
+            || kindle_language(&language),
+            |(code, _)| String::from(*code),
+        )
+}
+
+// import
+
+// reads a kindle vocab.db into a pending import with a deck per book, its cards go into the first
+// deck whose front language is the language of the book. only books in one of the languages are
+// read, all of them when there are none. words already in the deck or looked up before are skipped
+pub async fn parse_kindle(
+    data: Vec<u8>,
+    user_id: i32,
+    decks: &[(Deck, HashSet<String>)],
+    languages: &[String],
+) -> Result<PendingImport, String> {
+    // sqlite needs a file to open
+    let path = std::env::temp_dir().join(format!("import-{}.db", rand::random::<u64>()));
+
+    tokio::fs::write(&path, data)
+        .await
+        .map_err(|err| err.to_string())?;
+
+    let lookups = read_lookups(&path).await;
+
+    let _ = tokio::fs::remove_file(&path).await;
+
+    let lookups = lookups.map_err(|err| {
+        format!(
+            "could not be read as a Kindle vocabulary builder (vocab.db): {}",
+            err
+        )
+    })?;
+
+    let languages: Vec<String> = languages
+        .iter()
+        .map(|language| language_code(language))
+        .collect();
+
+    let mut pending_import = PendingImport::new(user_id, "kindle");
+    let mut deck_indexes: HashMap<Option<String>, usize> = HashMap::new();
+    let mut unmatched_books: HashSet<Option<String>> = HashSet::new();
+    // front texts of the lookups read so far per deck
+    let mut seen_texts: HashMap<i32, HashSet<String>> = HashMap::new();
+
+    for lookup in lookups {
+        let word = match lookup.word.as_deref().map(str::trim) {
+            Some(word) if !word.is_empty() => String::from(word),
+            _ => continue,
+        };
+
+        // books without a language are read in the language of the word
+        let language = kindle_language(
+            lookup
+                .book_language
+                .as_deref()
+                .or(lookup.word_language.as_deref())
+                .unwrap_or_default(),
+        );
+
+        if !languages.is_empty() && !languages.contains(&language) {
+            continue;
+        }
+
+        let title = lookup
+            .title
+            .clone()
+            .unwrap_or_else(|| String::from("Kindle"));
+
+        let deck = decks
+            .iter()
+            .find(|(deck, _)| language_code(&deck.from_language) == language);
+
+        let (deck, deck_texts) = match deck {
+            Some(deck) => deck,
+            None => {
+                if unmatched_books.insert(lookup.book_key.clone()) {
+                    pending_import.skipped.push(ImportNoteReport {
+                        deck_name: title.clone(),
+                        line: None,
+                        text: title,
+                        message: format!("has no deck for the language {}", language),
+                    });
+                }
+
+                continue;
+            }
+        };
+
+        let deck_index = *deck_indexes
+            .entry(lookup.book_key.clone())
+            .or_insert_with(|| {
+                pending_import.decks.push(ImportDeck {
+                    deck_id: Some(deck.id),
+                    name: title,
+                    from_language: deck.from_language.clone(),
+                    to_language_primary: deck.to_language_primary.clone(),
+                    to_language_secondary: deck.to_language_secondary.clone(),
+                    cards: Vec::new(),
+                });
+
+                pending_import.decks.len() - 1
+            });
+
+        // a word looked up again keeps the sentence of its first lookup
+        let key = fold_text(&word, &deck.from_language, true);
+
+        if !seen_texts.entry(deck.id).or_default().insert(key.clone()) {
+            continue;
+        }
+
+        if lookup.category == Some(MASTERED_CATEGORY) {
+            pending_import.skip_card(
+                deck_index,
+                &word,
+                String::from("is marked as learned on the Kindle"),
+                None,
+            );
+            continue;
+        }
+
+        if deck_texts.contains(&key) {
+            pending_import.skip_card(
+                deck_index,
+                &word,
+                String::from("already exists in the deck"),
+                None,
+            );
+            continue;
+        }
+
+        let card = ImportCard {
+            from_text: word,
+            to_text_primary: String::from(TRANSLATION_PLACEHOLDER),
+            to_text_secondary: None,
+            example_text: lookup
+                .usage
+                .map(|usage| usage.trim().to_string())
+                .filter(|usage| !usage.is_empty()),
+            audio_file: None,
+            audio_url: None,
+            tags: vec![String::from(KINDLE_TAG)],
+        };
+
+        pending_import.add_card(deck_index, card, None);
+    }
+
+    Ok(pending_import)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imports::deck_fixture;
+    use sqlx::{Connection, Executor};
+
+    // a vocab.db with the tables the kindle writes, filled by the given statements
+    async fn vocab_file(statements: &str) -> Vec<u8> {
+        let path = std::env::temp_dir().join(format!("test-{}.db", rand::random::<u64>()));
+
+        let mut connection: SqliteConnection = SqliteConnectOptions::new()
+            .filename(&path)
+            .create_if_missing(true)
+            .connect()
+            .await
+            .unwrap();
+
+        connection
+            .execute(
+                "CREATE TABLE WORDS (id TEXT PRIMARY KEY, word TEXT, stem TEXT, lang TEXT, category INTEGER DEFAULT 0, timestamp INTEGER DEFAULT 0);
+                CREATE TABLE LOOKUPS (id TEXT PRIMARY KEY, word_key TEXT, book_key TEXT, dict_key TEXT, pos TEXT, usage TEXT, timestamp INTEGER DEFAULT 0);
+                CREATE TABLE BOOK_INFO (id TEXT PRIMARY KEY, asin TEXT, guid TEXT, lang TEXT, title TEXT, authors TEXT);",
+            )
+            .await
+            .unwrap();
+        connection.execute(statements).await.unwrap();
+        connection.close().await.unwrap();
+
+        let data = tokio::fs::read(&path).await.unwrap();
+        let _ = tokio::fs::remove_file(&path).await;
+
+        data
+    }
+
+    fn decks() -> Vec<(Deck, HashSet<String>)> {
+        vec![
+            (
+                deck_fixture("Deutsch"),
+                HashSet::from([fold_text("die Katze", "Deutsch", true)]),
+            ),
+            (
+                Deck {
+                    id: 2,
+                    ..deck_fixture("English")
+                },
+                HashSet::new(),
+            ),
+        ]
+    }
+
+    async fn lookups() -> Vec<u8> {
+        vocab_file(
+            "INSERT INTO BOOK_INFO (id, lang, title) VALUES
+                ('a', 'de', 'Die Verwandlung'), ('b', 'en-GB', 'Emma'), ('c', 'fr', 'Candide');
+            INSERT INTO WORDS (id, word, lang, category) VALUES
+                ('de:Hund', 'Hund', 'de', 0), ('de:Katze', 'Katze', 'de', 0), ('de:schon', 'schon', 'de', 100),
+                ('de:schön', 'schön', 'de', 0), ('en:house', 'house', 'en', 0), ('fr:chien', 'chien', 'fr', 0);
+            INSERT INTO LOOKUPS (id, word_key, book_key, usage, timestamp) VALUES
+                ('1', 'de:Hund', 'a', ' Der Hund bellt. ', 1),
+                ('2', 'de:Hund', 'a', 'Noch ein Hund.', 2),
+                ('3', 'de:Katze', 'a', 'Die Katze schläft.', 3),
+                ('4', 'de:schon', 'a', 'Schon wieder.', 4),
+                ('5', 'de:schön', 'a', 'Wie schön.', 5),
+                ('6', 'en:house', 'b', 'A house.', 6),
+                ('7', 'fr:chien', 'c', 'Le chien.', 7),
+                ('8', 'fr:chien', 'c', 'Un chien.', 8);",
+        )
+        .await
+    }
+
+    fn words(deck: &ImportDeck) -> Vec<&str> {
+        deck.cards
+            .iter()
+            .map(|card| card.from_text.as_str())
+            .collect()
+    }
+
+    fn skipped(pending_import: &PendingImport) -> Vec<(&str, &str)> {
+        pending_import
+            .skipped
+            .iter()
+            .map(|report| (report.text.as_str(), report.message.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn language_code_reads_codes_and_names() {
+        assert_eq!(language_code("Deutsch"), "de");
+        assert_eq!(language_code(" German "), "de");
+        assert_eq!(language_code("FR"), "fr");
+        assert_eq!(language_code("en-GB"), "en");
+        assert_eq!(language_code("Japanisch"), "ja");
+        assert_eq!(language_code("日本語"), "ja");
+        assert_eq!(language_code("Svenska"), "sv");
+        assert_eq!(language_code("Klingonisch"), "klingonisch");
+    }
+
+    #[tokio::test]
+    async fn parse_kindle_makes_a_deck_per_book() {
+        let pending_import = parse_kindle(lookups().await, 1, &decks(), &[])
+            .await
+            .unwrap();
+
+        let names: Vec<&str> = pending_import
+            .decks
+            .iter()
+            .map(|deck| deck.name.as_str())
+            .collect();
+
+        assert_eq!(names, vec!["Die Verwandlung", "Emma"]);
+        assert_eq!(pending_import.decks[0].deck_id, Some(1));
+        assert_eq!(pending_import.decks[1].deck_id, Some(2));
+
+        // a repeated lookup keeps the sentence of the first one
+        let card = &pending_import.decks[0].cards[0];
+
+        assert_eq!(words(&pending_import.decks[0]), vec!["Hund", "schön"]);
+        assert_eq!(card.example_text.as_deref(), Some("Der Hund bellt."));
+        assert_eq!(card.tags, vec!["kindle"]);
+        assert_eq!(words(&pending_import.decks[1]), vec!["house"]);
+
+        assert_eq!(
+            skipped(&pending_import),
+            vec![
+                ("Katze", "already exists in the deck"),
+                ("schon", "is marked as learned on the Kindle"),
+                ("Candide", "has no deck for the language fr"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn parse_kindle_reads_books_in_the_languages() {
+        let languages = vec![String::from("Englisch")];
+
+        let pending_import = parse_kindle(lookups().await, 1, &decks(), &languages)
+            .await
+            .unwrap();
+
+        assert_eq!(pending_import.decks.len(), 1);
+        assert_eq!(words(&pending_import.decks[0]), vec!["house"]);
+        assert!(pending_import.skipped.is_empty());
+    }
+
+    #[tokio::test]
+    async fn parse_kindle_needs_a_vocabulary_builder() {
+        let err = parse_kindle(b"not a database".to_vec(), 1, &decks(), &[])
+            .await
+            .err()
+            .unwrap();
+
+        assert!(err.starts_with("could not be read as a Kindle vocabulary builder"));
+    }
+}
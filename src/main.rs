@@ -1,13 +1,52 @@
+mod anki;
 mod api;
+mod bundles;
+mod duplicates;
+mod events;
+mod exports;
+mod idempotency;
+mod imports;
+mod kindle;
 mod pages;
+mod paste;
+mod printing;
 mod queries;
+mod spreadsheet;
+mod study;
+mod subtitles;
+mod sync;
+mod validation;
+mod webhooks;
 
 use crate::api::{
-    delete_card, delete_deck, delete_user, get_card, get_cards, get_deck, get_decks, get_user,
-    get_users, post_card, post_deck, post_user, put_card, put_deck, put_user,
+    delete_card, delete_card_relation, delete_deck, delete_import, delete_study_session,
+    delete_tag, delete_user, delete_webhook, get_bundle_export, get_card, get_card_duplicates,
+    get_card_relations, get_card_revisions, get_card_tags, get_cards, get_csv_export, get_deck,
+    get_deck_apkg_export, get_deck_bundle_export, get_deck_csv_export, get_deck_duplicates,
+    get_deck_json_export, get_decks, get_events, get_import, get_json_export, get_study_session,
+    get_study_session_next, get_sync, get_tag, get_tags, get_user, get_users, get_webhook,
+    get_webhook_deliveries, get_webhooks, post_apkg_import, post_bundle_import, post_card,
+    post_card_relation, post_card_revision_restore, post_cards_copy, post_cards_move,
+    post_cards_undo, post_csv_import, post_deck, post_deck_clone, post_import_commit,
+    post_kindle_import, post_paste_import, post_reviews, post_study_session,
+    post_study_session_rating, post_study_session_undo, post_subtitles_import, post_sync, post_tag,
+    post_user, post_webhook, put_card, put_deck, put_tag, put_user, put_webhook,
+};
+use crate::events::EventBus;
+use crate::idempotency::idempotency;
+use crate::imports::{PendingImport, IMPORT_MAX_SIZE};
+use crate::pages::{
+    page_action, page_add_card, page_duplicates, page_edit_card, page_home, page_import,
+    page_import_preview, page_paste_cards, page_print, page_subtitles,
+};
+use crate::study::StudySession;
+use crate::webhooks::run_webhook_worker;
+use axum::{
+    extract::DefaultBodyLimit,
+    middleware,
+    routing::{delete, get, post},
+    Router,
 };
-use crate::pages::{page_action, page_add_card, page_edit_card, page_home};
-use axum::{routing::get, Router};
 use chrono::NaiveDateTime;
 use sqlx::{postgres::PgPoolOptions, Error, Pool, Postgres};
 use std::sync::RwLock;
@@ -53,6 +92,35 @@ struct DeckForm {
     seen_at: Option<NaiveDateTime>,
 }
 
+#[derive(serde::Serialize)]
+struct DeckStats {
+    #[serde(skip)]
+    deck_id: i32,
+    total: i64,
+    unrated: i64,
+    rating_1: i64,
+    rating_2: i64,
+    rating_3: i64,
+    rating_4: i64,
+    due_today: i64,
+}
+
+#[derive(serde::Serialize)]
+struct DeckOverview {
+    #[serde(flatten)]
+    deck: Deck,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cards: Option<Vec<Card>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stats: Option<DeckStats>,
+}
+
+#[derive(serde::Deserialize)]
+struct DeckCloneForm {
+    reset_progress: Option<bool>,
+    swap_languages: Option<bool>,
+}
+
 #[derive(Clone, serde::Serialize)]
 struct Card {
     id: i32,
@@ -71,9 +139,22 @@ struct Card {
     updated_at: NaiveDateTime,
 }
 
+// a card with what an export needs besides it, the deck languages only go into csv rows
+#[derive(serde::Serialize)]
+struct CardExport {
+    #[serde(flatten)]
+    card: Card,
+    tags: Vec<String>,
+    #[serde(skip)]
+    from_language: String,
+    #[serde(skip)]
+    to_language_primary: String,
+    #[serde(skip)]
+    to_language_secondary: Option<String>,
+}
+
 #[derive(serde::Deserialize)]
 struct CardForm {
-    related_card_ids: Option<Vec<i32>>,
     from_text: Option<String>,
     to_text_primary: Option<String>,
     to_text_secondary: Option<String>,
@@ -82,6 +163,258 @@ struct CardForm {
     seen_at: Option<NaiveDateTime>,
     seen_for: Option<i32>,
     rating: Option<i32>,
+    // comma separated tag names, replaces the tags of the card
+    tags: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct RelatedCard {
+    relation_type: Option<String>,
+    #[serde(flatten)]
+    card: Card,
+}
+
+#[derive(serde::Deserialize)]
+struct CardRelationForm {
+    related_card_id: i32,
+    relation_type: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct Tag {
+    id: i32,
+    user_id: i32,
+    name: String,
+    created_at: NaiveDateTime,
+    updated_at: NaiveDateTime,
+}
+
+#[derive(serde::Deserialize)]
+struct TagForm {
+    name: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct CardRevision {
+    id: i32,
+    card_id: i32,
+    from_text: String,
+    to_text_primary: String,
+    to_text_secondary: Option<String>,
+    example_text: Option<String>,
+    created_at: NaiveDateTime,
+}
+
+#[derive(serde::Deserialize)]
+struct CardRevisionRestoreForm {
+    fields: Option<String>,
+}
+
+// read from the text fields of the multipart upload
+struct CsvImportForm {
+    // detected from the file when not given, one of "," ";" "tab" "|"
+    delimiter: Option<String>,
+    // "false" when the first row already holds cards
+    header: Option<String>,
+    // a column name or number per card field, guessed from the header when not given
+    from_text: Option<String>,
+    to_text_primary: Option<String>,
+    to_text_secondary: Option<String>,
+    example_text: Option<String>,
+    audio_url: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct PasteImportForm {
+    // a card per line, front and back divided by the separator
+    text: Option<String>,
+    // detected from the lines when not given, "tab" or the separator itself
+    separator: Option<String>,
+    // the part after the separator goes on the front
+    swap: Option<bool>,
+    // lines repeating a card of the list or the deck are left out instead of flagged
+    skip_duplicates: Option<bool>,
+}
+
+#[derive(serde::Deserialize)]
+struct ImportCommitForm {
+    // replace the languages guessed from the import for every deck
+    from_language: Option<String>,
+    to_language_primary: Option<String>,
+    to_language_secondary: Option<String>,
+    // what happens to cards whose front text is already taken, "allow" when not given
+    duplicates: Option<String>,
+    // leading articles are left out when texts are compared, unless this is false
+    ignore_articles: Option<bool>,
+    // indexes of the decks of the preview to import like "0,2", all of them when empty
+    #[serde(default, deserialize_with = "validation::deserialize_id_list")]
+    decks: Vec<i32>,
+    // indexes of the cards to import counted across the decks, all of them when empty
+    #[serde(default, deserialize_with = "validation::deserialize_id_list")]
+    cards: Vec<i32>,
+}
+
+#[derive(serde::Deserialize)]
+struct CardTransferForm {
+    #[serde(deserialize_with = "validation::deserialize_id_list")]
+    card_ids: Vec<i32>,
+    target_deck_id: i32,
+}
+
+#[derive(serde::Deserialize)]
+struct StudySessionForm {
+    deck_id: i32,
+    tags: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct StudyRatingForm {
+    card_id: i32,
+    rating: Option<i32>,
+    seen_for: Option<i32>,
+}
+
+#[derive(serde::Serialize)]
+struct Review {
+    id: i32,
+    card_id: i32,
+    rating: i32,
+    prev_rating: i32,
+    seen_at: NaiveDateTime,
+    seen_for: Option<i32>,
+    reviewed_at: NaiveDateTime,
+    created_at: NaiveDateTime,
+    #[serde(skip)]
+    undo_rating: Option<i32>,
+    #[serde(skip)]
+    undo_prev_rating: Option<i32>,
+    #[serde(skip)]
+    undo_seen_at: Option<NaiveDateTime>,
+    #[serde(skip)]
+    undo_seen_for: Option<i32>,
+    #[serde(skip)]
+    undo_updated_at: Option<NaiveDateTime>,
+}
+
+#[derive(serde::Deserialize)]
+struct ReviewBatchForm {
+    #[serde(default)]
+    reviews: Vec<ReviewForm>,
+}
+
+#[derive(serde::Deserialize)]
+struct ReviewForm {
+    card_id: i32,
+    rating: Option<i32>,
+    seen_for: Option<i32>,
+    reviewed_at: NaiveDateTime,
+}
+
+#[derive(serde::Serialize)]
+struct ReviewBatchResult {
+    applied: Vec<Review>,
+    skipped: Vec<SkippedReview>,
+    // latest state of every reviewed card
+    #[serde(skip)]
+    cards: Vec<Card>,
+}
+
+#[derive(serde::Serialize)]
+struct SkippedReview {
+    card_id: i32,
+    reviewed_at: NaiveDateTime,
+    message: String,
+}
+
+#[derive(serde::Deserialize)]
+struct SyncForm {
+    strategy: Option<String>,
+    #[serde(default)]
+    decks: Vec<SyncChange>,
+    #[serde(default)]
+    cards: Vec<SyncChange>,
+}
+
+#[derive(serde::Deserialize)]
+struct SyncChange {
+    id: Option<i32>,
+    client_id: Option<String>,
+    deck_id: Option<i32>,
+    deck_client_id: Option<String>,
+    updated_at: NaiveDateTime,
+    #[serde(default)]
+    deleted: bool,
+    #[serde(default)]
+    fields: serde_json::Map<String, serde_json::Value>,
+    base: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+// a synced card with its tag names, a change of its tags changes the card
+#[derive(serde::Serialize)]
+struct SyncCard {
+    #[serde(flatten)]
+    card: Card,
+    tags: Vec<String>,
+}
+
+#[derive(serde::Serialize)]
+struct SyncPull {
+    cursor: i64,
+    decks: Vec<Deck>,
+    cards: Vec<SyncCard>,
+    reviews: Vec<Review>,
+    deleted: SyncDeleted,
+}
+
+#[derive(Default, serde::Serialize)]
+struct SyncDeleted {
+    decks: Vec<i32>,
+    cards: Vec<i32>,
+    reviews: Vec<i32>,
+}
+
+#[derive(serde::Serialize)]
+struct Webhook {
+    id: i32,
+    user_id: i32,
+    url: String,
+    // only returned once, when the webhook is created
+    #[serde(skip_serializing)]
+    secret: String,
+    event_types: Vec<String>,
+    active: bool,
+    created_at: NaiveDateTime,
+    updated_at: NaiveDateTime,
+}
+
+#[derive(serde::Serialize)]
+struct CreatedWebhook {
+    #[serde(flatten)]
+    webhook: Webhook,
+    secret: String,
+}
+
+#[derive(serde::Deserialize)]
+struct WebhookForm {
+    url: Option<String>,
+    secret: Option<String>,
+    event_types: Option<String>,
+    active: Option<bool>,
+}
+
+#[derive(serde::Serialize)]
+struct WebhookDelivery {
+    id: i32,
+    webhook_id: i32,
+    event_type: String,
+    payload: serde_json::Value,
+    status: String,
+    attempts: i32,
+    next_attempt_at: NaiveDateTime,
+    response_status: Option<i32>,
+    last_error: Option<String>,
+    created_at: NaiveDateTime,
+    updated_at: NaiveDateTime,
 }
 
 // global state
@@ -91,6 +424,9 @@ struct AppState {
     user: Option<User>,
     uuid: String,
     active_decks: RwLock<HashMap<i32, Vec<Card>>>,
+    events: EventBus,
+    study_sessions: RwLock<HashMap<String, StudySession>>,
+    imports: RwLock<HashMap<String, PendingImport>>,
 }
 
 // main
@@ -109,6 +445,10 @@ async fn main() -> Result<(), Error> {
         .connect(&db_url)
         .await?;
 
+    // webhooks
+
+    tokio::spawn(run_webhook_worker(pool.clone()));
+
     // sever
 
     let app_state = Arc::new(AppState {
@@ -128,6 +468,9 @@ async fn main() -> Result<(), Error> {
         }),
         uuid,
         active_decks: RwLock::new(HashMap::new()),
+        events: EventBus::new(),
+        study_sessions: RwLock::new(HashMap::new()),
+        imports: RwLock::new(HashMap::new()),
     });
 
     let root_path = env::current_dir().unwrap();
@@ -143,21 +486,116 @@ async fn main() -> Result<(), Error> {
             "/decks/:deck_id",
             get(get_deck).put(put_deck).delete(delete_deck),
         )
+        .route("/decks/:deck_id/clone", post(post_deck_clone))
+        .route("/decks/:deck_id/duplicates", get(get_deck_duplicates))
+        .route("/decks/:deck_id/export.apkg", get(get_deck_apkg_export))
+        .route("/decks/:deck_id/export.csv", get(get_deck_csv_export))
+        .route("/decks/:deck_id/export.json", get(get_deck_json_export))
+        .route("/decks/:deck_id/export.bundle", get(get_deck_bundle_export))
+        .route("/export.csv", get(get_csv_export))
+        .route("/export.json", get(get_json_export))
+        .route("/export.bundle", get(get_bundle_export))
         .route("/cards/:deck_id", get(get_cards).post(post_card))
+        .route("/cards/:deck_id/duplicates", get(get_card_duplicates))
+        .route("/cards/:deck_id/move", post(post_cards_move))
+        .route("/cards/:deck_id/copy", post(post_cards_copy))
+        .route("/cards/:deck_id/undo", post(post_cards_undo))
         .route(
             "/cards/:deck_id/:card_id",
             get(get_card).put(put_card).delete(delete_card),
-        );
+        )
+        .route(
+            "/cards/:deck_id/:card_id/related",
+            get(get_card_relations).post(post_card_relation),
+        )
+        .route(
+            "/cards/:deck_id/:card_id/related/:related_card_id",
+            delete(delete_card_relation),
+        )
+        .route(
+            "/cards/:deck_id/:card_id/revisions",
+            get(get_card_revisions),
+        )
+        .route("/cards/:deck_id/:card_id/tags", get(get_card_tags))
+        .route(
+            "/cards/:deck_id/:card_id/revisions/:revision_id/restore",
+            post(post_card_revision_restore),
+        )
+        .route("/tags", get(get_tags).post(post_tag))
+        .route(
+            "/tags/:tag_id",
+            get(get_tag).put(put_tag).delete(delete_tag),
+        )
+        .route("/webhooks", get(get_webhooks).post(post_webhook))
+        .route(
+            "/webhooks/:webhook_id",
+            get(get_webhook).put(put_webhook).delete(delete_webhook),
+        )
+        .route(
+            "/webhooks/:webhook_id/deliveries",
+            get(get_webhook_deliveries),
+        )
+        .route("/events", get(get_events))
+        .route("/sync", get(get_sync).post(post_sync))
+        .route("/reviews", post(post_reviews))
+        .route("/sessions", post(post_study_session))
+        .route(
+            "/sessions/:session_id",
+            get(get_study_session).delete(delete_study_session),
+        )
+        .route("/sessions/:session_id/next", get(get_study_session_next))
+        .route(
+            "/sessions/:session_id/ratings",
+            post(post_study_session_rating),
+        )
+        .route("/sessions/:session_id/undo", post(post_study_session_undo))
+        .route(
+            "/imports/apkg",
+            post(post_apkg_import).layer(DefaultBodyLimit::max(IMPORT_MAX_SIZE)),
+        )
+        .route(
+            "/imports/bundle",
+            post(post_bundle_import).layer(DefaultBodyLimit::max(IMPORT_MAX_SIZE)),
+        )
+        .route(
+            "/imports/csv/:deck_id",
+            post(post_csv_import).layer(DefaultBodyLimit::max(IMPORT_MAX_SIZE)),
+        )
+        .route(
+            "/imports/kindle",
+            post(post_kindle_import).layer(DefaultBodyLimit::max(IMPORT_MAX_SIZE)),
+        )
+        .route("/imports/paste/:deck_id", post(post_paste_import))
+        .route(
+            "/imports/subtitles/:deck_id",
+            post(post_subtitles_import).layer(DefaultBodyLimit::max(IMPORT_MAX_SIZE)),
+        )
+        .route(
+            "/imports/:import_id",
+            get(get_import)
+                .post(post_import_commit)
+                .delete(delete_import),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            idempotency,
+        ));
 
     let app = Router::new()
         .nest("/api", api_router)
         .route("/", get(page_home))
         .route("/action/:deck_id/:card_index/:card_side", get(page_action))
         .route("/add_card/:deck_id/:card_index", get(page_add_card))
+        .route("/paste_cards/:deck_id/:card_index", get(page_paste_cards))
         .route(
             "/edit_card/:deck_id/:card_id/:card_index",
             get(page_edit_card),
         )
+        .route("/import", get(page_import))
+        .route("/duplicates/:deck_id", get(page_duplicates))
+        .route("/print/:deck_id", get(page_print))
+        .route("/import/:import_id", get(page_import_preview))
+        .route("/subtitles/:import_id", get(page_subtitles))
         .nest_service(
             "/assets",
             ServeDir::new(format!("{}/assets", root_path.to_str().unwrap())),
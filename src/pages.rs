@@ -1,7 +1,15 @@
+use crate::duplicates::{find_duplicates, DuplicateGroup};
+use crate::imports::{
+    read_import_cards, read_import_preview, ImportCard, ImportPreview, TRANSLATION_PLACEHOLDER,
+};
+use crate::printing::{layout_sheets, PrintOptions, PrintSheet, PRINT_FIELDS};
 use crate::queries::{
-    read_card_query, read_cards_query, read_deck, read_decks_query, update_deck_query,
+    read_card_query, read_card_revisions_query, read_card_tags_query, read_cards_query, read_deck,
+    read_decks_query, read_tags_query, split_tag_names, update_deck_query,
 };
-use crate::{AppState, Card, Deck, DeckForm};
+use crate::study::order_study_cards;
+use crate::validation::{MAX_RATING, MIN_RATING, REVISION_FIELDS};
+use crate::{AppState, Card, CardRevision, Deck, DeckForm, Tag};
 use askama::Template;
 use axum::extract::{Path, Query, State};
 use axum::http::StatusCode;
@@ -17,6 +25,7 @@ use std::sync::Arc;
 #[template(path = "home.html")]
 struct HomeTemplate {
     decks: Vec<Deck>,
+    uuid: String,
 }
 
 #[derive(Template)]
@@ -28,12 +37,24 @@ struct ActionTemplate {
     index: usize,
     side: String,
     random: String,
+    // where to go back to after undoing the last rating, None on the first card
+    undo_index: Option<usize>,
     uuid: String,
 }
 
 #[derive(Template)]
 #[template(path = "add_card.html")]
 struct AddCardTemplate {
+    deck: Deck,
+    card_index: i32,
+    tags: Vec<Tag>,
+    card_tags: String,
+    uuid: String,
+}
+
+#[derive(Template)]
+#[template(path = "paste_cards.html")]
+struct PasteCardsTemplate {
     deck: Deck,
     card_index: i32,
     uuid: String,
@@ -45,9 +66,142 @@ struct EditCardTemplate {
     deck: Deck,
     card: Card,
     card_index: i32,
+    revisions: Vec<RevisionDiff>,
+    tags: Vec<Tag>,
+    // comma separated names of the tags on the card
+    card_tags: String,
+    uuid: String,
+}
+
+// revision view model
+
+struct RevisionChange {
+    field: String,
+    label: String,
+    old: String,
+    new: String,
+}
+
+struct RevisionDiff {
+    id: i32,
+    created_at: String,
+    changes: Vec<RevisionChange>,
+}
+
+fn revision_field_texts(
+    from_text: &str,
+    to_text_primary: &str,
+    to_text_secondary: &Option<String>,
+    example_text: &Option<String>,
+) -> [String; 4] {
+    [
+        String::from(from_text),
+        String::from(to_text_primary),
+        to_text_secondary.clone().unwrap_or_default(),
+        example_text.clone().unwrap_or_default(),
+    ]
+}
+
+// a revision holds the texts before an edit, so it is compared to the next newer revision or the card
+fn revision_diffs(deck: &Deck, card: &Card, revisions: Vec<CardRevision>) -> Vec<RevisionDiff> {
+    let labels = [
+        deck.from_language.clone(),
+        deck.to_language_primary.clone(),
+        deck.to_language_secondary.clone().unwrap_or_default(),
+        String::from("Beispiel"),
+    ];
+
+    let mut newer = revision_field_texts(
+        &card.from_text,
+        &card.to_text_primary,
+        &card.to_text_secondary,
+        &card.example_text,
+    );
+
+    let mut diffs = Vec::new();
+
+    for revision in revisions {
+        let older = revision_field_texts(
+            &revision.from_text,
+            &revision.to_text_primary,
+            &revision.to_text_secondary,
+            &revision.example_text,
+        );
+
+        let changes = (0..REVISION_FIELDS.len())
+            .filter(|&index| older[index] != newer[index])
+            .map(|index| RevisionChange {
+                field: String::from(REVISION_FIELDS[index]),
+                label: labels[index].clone(),
+                old: older[index].clone(),
+                new: newer[index].clone(),
+            })
+            .collect();
+
+        diffs.push(RevisionDiff {
+            id: revision.id,
+            created_at: revision.created_at.format("%d.%m.%Y %H:%M").to_string(),
+            changes,
+        });
+
+        newer = older;
+    }
+
+    diffs
+}
+
+#[derive(Template)]
+#[template(path = "import.html")]
+struct ImportTemplate {
+    decks: Vec<Deck>,
+    uuid: String,
+}
+
+#[derive(Template)]
+#[template(path = "import_preview.html")]
+struct ImportPreviewTemplate {
+    preview: ImportPreview,
+    translation_placeholder: &'static str,
     uuid: String,
 }
 
+#[derive(Template)]
+#[template(path = "subtitles.html")]
+struct SubtitlesTemplate {
+    preview: ImportPreview,
+    cards: Vec<ImportCard>,
+    uuid: String,
+}
+
+#[derive(Template)]
+#[template(path = "duplicates.html")]
+struct DuplicatesTemplate {
+    deck: Deck,
+    groups: Vec<DuplicateGroup>,
+    ignore_articles: bool,
+    uuid: String,
+}
+
+#[derive(Template)]
+#[template(path = "print.html")]
+struct PrintTemplate {
+    deck: Deck,
+    sheets: Vec<PrintSheet>,
+    num_cards: usize,
+    options: PrintOptions,
+    // the fields with their labels for the options
+    fields: Vec<(&'static str, String)>,
+    ratings: Vec<i32>,
+    tags: Vec<Tag>,
+    uuid: String,
+}
+
+#[derive(Template)]
+#[template(path = "error.html")]
+struct ErrorTemplate {
+    message: String,
+}
+
 // html response model
 
 struct HtmlResponse<T>(T);
@@ -68,6 +222,14 @@ where
     }
 }
 
+fn error_response(status: StatusCode, message: &str) -> Response {
+    let template = ErrorTemplate {
+        message: String::from(message),
+    };
+
+    (status, HtmlResponse(template)).into_response()
+}
+
 // route handlers
 
 pub async fn page_home(
@@ -77,7 +239,10 @@ pub async fn page_home(
     let uuid = query.get("uuid");
     // TODO: add error template
     if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
-        let template = HomeTemplate { decks: Vec::new() };
+        let template = HomeTemplate {
+            decks: Vec::new(),
+            uuid: String::new(),
+        };
 
         return HtmlResponse(template);
     }
@@ -85,13 +250,19 @@ pub async fn page_home(
     let result = read_decks_query(&app_state.pool, app_state.user.as_ref().unwrap().id).await;
 
     if let Ok(mut decks) = result {
-        decks.sort_by(|a, b| a.id.cmp(&b.id));
+        decks.sort_by_key(|deck| deck.id);
 
-        let template = HomeTemplate { decks };
+        let template = HomeTemplate {
+            decks,
+            uuid: app_state.uuid.clone(),
+        };
 
         HtmlResponse(template)
     } else {
-        let template = HomeTemplate { decks: Vec::new() };
+        let template = HomeTemplate {
+            decks: Vec::new(),
+            uuid: app_state.uuid.clone(),
+        };
 
         HtmlResponse(template)
     }
@@ -101,6 +272,7 @@ pub async fn read_cards_and_set_deck_timestamp_query(
     pool: &Pool<Postgres>,
     deck_id: i32,
     user_id: i32,
+    tags: &[String],
 ) -> Result<Vec<Card>, Error> {
     update_deck_query(
         pool,
@@ -114,17 +286,25 @@ pub async fn read_cards_and_set_deck_timestamp_query(
         },
         user_id,
     )
-    .await
-    .expect("should be defined");
+    .await?;
 
-    read_cards_query(pool, deck_id).await
+    read_cards_query(pool, deck_id, tags).await
 }
 
 pub async fn page_action(
     State(app_state): State<Arc<AppState>>,
     Path(params): Path<(i32, usize, String)>,
-) -> impl IntoResponse {
-    if params.1 == 0 && params.2 == "from" {
+    Query(query): Query<HashMap<String, String>>,
+) -> Response {
+    // going back to the first card after an undo keeps the running order
+    let is_undo = query.contains_key("card_id")
+        && app_state
+            .active_decks
+            .read()
+            .unwrap()
+            .contains_key(&params.0);
+
+    if params.1 == 0 && params.2 == "from" && !is_undo {
         let deck_result = read_deck(
             &app_state.pool,
             params.0,
@@ -132,77 +312,43 @@ pub async fn page_action(
         )
         .await;
 
+        let deck = match deck_result {
+            Ok(Some(deck)) => deck,
+            Ok(None) => return error_response(StatusCode::NOT_FOUND, "Deck not found"),
+            Err(err) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, &err.to_string()),
+        };
+
+        // ?tags=a,b studies only the cards carrying one of the tags
+        let tags = split_tag_names(query.get("tags").map_or("", String::as_str));
+
         let cards_result = read_cards_and_set_deck_timestamp_query(
             &app_state.pool,
             params.0,
             app_state.user.as_ref().unwrap().id,
+            &tags,
         )
         .await;
 
-        if let Ok(mut cards) = cards_result {
-            if let Ok(decks) = deck_result {
-                let deck = decks.get(0).cloned().unwrap();
-
-                cards.sort_by(|a, b| b.updated_at.cmp(&a.updated_at));
-
-                let mut weights: HashMap<i32, i32> = HashMap::new();
-
-                for card in &cards {
-                    // 1. All set to 4 after deck last seen
-
-                    if card.rating == 4 && deck.seen_at < card.updated_at {
-                        weights.insert(card.id, 1_000_000);
-                    }
-
-                    // 2. All unrated
-
-                    if card.rating == 0 {
-                        weights.insert(card.id, 100_000);
-                    }
-                }
-
-                let span = cards[0].updated_at - cards[cards.len() - 1].updated_at; // youngest - oldest
-
-                for card in &cards {
-                    // Continue if weights already include card id
-
-                    if weights.contains_key(&card.id) {
-                        continue;
-                    }
+        if let Ok(cards) = cards_result {
+            let cards = order_study_cards(&deck, cards);
 
-                    // 3. If num < DAILY_REVIEW_COUNT, fill with youngest
+            let mut decks = app_state.active_decks.write().unwrap();
 
-                    if weights.len() < 9 {
-                        weights.insert(card.id, 100_000);
-                    }
-
-                    // 4. Weight by rating times weight by last seen - ceil((youngest - current) / span * 4)
-                    // TODO: Weight by time looked at: max(lower_limit, min(x, upper_limit))
-
-                    let current_age = cards[0].updated_at - card.updated_at;
-
-                    let span_number = span.num_milliseconds() as f32;
-                    let current_age_number = current_age.num_milliseconds() as f32;
-
-                    let weight_by_last_seen: f32 =
-                        (current_age_number / span_number * 4_f32).ceil();
-
-                    weights.insert(card.id, card.rating + weight_by_last_seen as i32);
-                }
-
-                // Sort cards by weight
-
-                cards.sort_by(|a, b| weights.get(&b.id).cmp(&weights.get(&a.id)));
-
-                let mut decks = app_state.active_decks.write().unwrap();
-
-                decks.insert(params.0, cards);
-            }
+            decks.insert(params.0, cards);
         }
     }
 
+    let undo_index = params.1.checked_sub(1);
+
     if let Some(deck) = app_state.active_decks.read().unwrap().get(&params.0) {
-        let card = deck.get(params.1).cloned();
+        // after an undo the card is looked up, the deck may have been reordered in the meantime
+        let index = query
+            .get("card_id")
+            .and_then(|card_id| card_id.parse::<i32>().ok())
+            .and_then(|card_id| deck.iter().position(|card| card.id == card_id))
+            .unwrap_or(params.1);
+
+        let card = deck.get(index).cloned();
         let random_number = rand::thread_rng().gen_range(0..=2);
 
         let random = if random_number > 0 {
@@ -216,13 +362,14 @@ pub async fn page_action(
                 card,
                 num_cards: deck.len() as i32,
                 deck_id: params.0,
-                index: params.1,
+                index,
                 side: params.2,
                 random,
+                undo_index: index.checked_sub(1),
                 uuid: app_state.uuid.clone(),
             };
 
-            return HtmlResponse(template);
+            return HtmlResponse(template).into_response();
         }
     }
 
@@ -265,16 +412,17 @@ pub async fn page_action(
         index: 0,
         side: String::from("from"),
         random: String::from("from"),
+        undo_index,
         uuid: app_state.uuid.clone(),
     };
 
-    HtmlResponse(template)
+    HtmlResponse(template).into_response()
 }
 
 pub async fn page_add_card(
     State(app_state): State<Arc<AppState>>,
     Path(params): Path<(i32, i32)>,
-) -> impl IntoResponse {
+) -> Response {
     let result = read_deck(
         &app_state.pool,
         params.0,
@@ -282,50 +430,63 @@ pub async fn page_add_card(
     )
     .await;
 
-    if let Ok(deck) = result {
-        let template = AddCardTemplate {
-            deck: deck.get(0).cloned().unwrap(),
-            card_index: params.1,
-            uuid: app_state.uuid.clone(),
-        };
+    let deck = match result {
+        Ok(Some(deck)) => deck,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, "Deck not found"),
+        Err(err) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, &err.to_string()),
+    };
 
-        HtmlResponse(template)
-    } else {
-        let template = AddCardTemplate {
-            deck: Deck {
-                id: 0,
-                user_id: 0,
-                from_language: String::from("Not found"),
-                to_language_primary: String::from("Not found"),
-                to_language_secondary: None,
-                design_key: None,
-                seen_at: chrono::NaiveDate::from_ymd_opt(2016, 7, 8)
-                    .unwrap()
-                    .and_hms_opt(9, 10, 11)
-                    .unwrap(),
-                created_at: chrono::NaiveDate::from_ymd_opt(2016, 7, 8)
-                    .unwrap()
-                    .and_hms_opt(9, 10, 11)
-                    .unwrap(),
-                updated_at: chrono::NaiveDate::from_ymd_opt(2016, 7, 8)
-                    .unwrap()
-                    .and_hms_opt(9, 10, 11)
-                    .unwrap(),
-            },
-            card_index: params.1,
-            uuid: app_state.uuid.clone(),
-        };
+    let tags = match read_tags_query(&app_state.pool, deck.user_id).await {
+        Ok(tags) => tags,
+        Err(err) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, &err.to_string()),
+    };
 
-        HtmlResponse(template)
-    }
+    let template = AddCardTemplate {
+        deck,
+        card_index: params.1,
+        tags,
+        card_tags: String::new(),
+        uuid: app_state.uuid.clone(),
+    };
+
+    HtmlResponse(template).into_response()
+}
+
+pub async fn page_paste_cards(
+    State(app_state): State<Arc<AppState>>,
+    Path(params): Path<(i32, i32)>,
+) -> Response {
+    let result = read_deck(
+        &app_state.pool,
+        params.0,
+        app_state.user.as_ref().unwrap().id,
+    )
+    .await;
+
+    let deck = match result {
+        Ok(Some(deck)) => deck,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, "Deck not found"),
+        Err(err) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, &err.to_string()),
+    };
+
+    let template = PasteCardsTemplate {
+        deck,
+        card_index: params.1,
+        uuid: app_state.uuid.clone(),
+    };
+
+    HtmlResponse(template).into_response()
 }
 
 pub async fn page_edit_card(
     State(app_state): State<Arc<AppState>>,
     Path(params): Path<(i32, i32, i32)>,
     Query(query): Query<HashMap<String, String>>,
-) -> impl IntoResponse {
+) -> Response {
     let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return error_response(StatusCode::UNAUTHORIZED, "Unauthorized");
+    }
 
     let deck_result = read_deck(
         &app_state.pool,
@@ -334,81 +495,238 @@ pub async fn page_edit_card(
     )
     .await;
 
+    let deck = match deck_result {
+        Ok(Some(deck)) => deck,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, "Deck not found"),
+        Err(err) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, &err.to_string()),
+    };
+
     let card_result = read_card_query(&app_state.pool, params.0, params.1).await;
 
-    if app_state.user.is_none()
-        || uuid.is_none()
-        || uuid.unwrap() != &app_state.uuid
-        || deck_result.is_err()
-        || card_result.is_err()
-    {
-        let template = EditCardTemplate {
-            deck: Deck {
-                id: 0,
-                user_id: 0,
-                from_language: String::from("Not found"),
-                to_language_primary: String::from("Not found"),
-                to_language_secondary: None,
-                design_key: None,
-                seen_at: chrono::NaiveDate::from_ymd_opt(2016, 7, 8)
-                    .unwrap()
-                    .and_hms_opt(9, 10, 11)
-                    .unwrap(),
-                created_at: chrono::NaiveDate::from_ymd_opt(2016, 7, 8)
-                    .unwrap()
-                    .and_hms_opt(9, 10, 11)
-                    .unwrap(),
-                updated_at: chrono::NaiveDate::from_ymd_opt(2016, 7, 8)
-                    .unwrap()
-                    .and_hms_opt(9, 10, 11)
-                    .unwrap(),
-            },
-            card: Card {
-                id: 0,
-                deck_id: 0,
-                related_card_ids: Vec::new(),
-                from_text: String::from("Not found"),
-                to_text_primary: String::from("Not found"),
-                to_text_secondary: None,
-                example_text: None,
-                audio_url: None,
-                seen_at: chrono::NaiveDate::from_ymd_opt(2016, 7, 8)
-                    .unwrap()
-                    .and_hms_opt(9, 10, 11)
-                    .unwrap(),
-                seen_for: None,
-                rating: 0,
-                prev_rating: 0,
-                created_at: chrono::NaiveDate::from_ymd_opt(2016, 7, 8)
-                    .unwrap()
-                    .and_hms_opt(9, 10, 11)
-                    .unwrap(),
-                updated_at: chrono::NaiveDate::from_ymd_opt(2016, 7, 8)
-                    .unwrap()
-                    .and_hms_opt(9, 10, 11)
-                    .unwrap(),
-            },
-            card_index: params.2,
-            uuid: app_state.uuid.clone(),
-        };
+    let card = match card_result {
+        Ok(Some(card)) => card,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, "Card not found"),
+        Err(err) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, &err.to_string()),
+    };
 
-        return HtmlResponse(template);
-    }
+    let revisions = match read_card_revisions_query(&app_state.pool, card.id).await {
+        Ok(revisions) => revision_diffs(&deck, &card, revisions),
+        Err(err) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, &err.to_string()),
+    };
+
+    let tags = match read_tags_query(&app_state.pool, deck.user_id).await {
+        Ok(tags) => tags,
+        Err(err) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, &err.to_string()),
+    };
+
+    let card_tags = match read_card_tags_query(&app_state.pool, card.id).await {
+        Ok(card_tags) => card_tags
+            .into_iter()
+            .map(|tag| tag.name)
+            .collect::<Vec<String>>()
+            .join(", "),
+        Err(err) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, &err.to_string()),
+    };
 
     let template = EditCardTemplate {
-        deck: deck_result
-            .expect("should be defined")
-            .get(0)
-            .cloned()
-            .unwrap(),
-        card: card_result
-            .expect("should be defined")
-            .get(0)
-            .cloned()
-            .unwrap(),
+        deck,
+        card,
         card_index: params.2,
+        revisions,
+        tags,
+        card_tags,
+        uuid: app_state.uuid.clone(),
+    };
+
+    HtmlResponse(template).into_response()
+}
+
+pub async fn page_import(
+    State(app_state): State<Arc<AppState>>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Response {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return error_response(StatusCode::UNAUTHORIZED, "Unauthorized");
+    }
+
+    // csv files are imported into one of these
+    let mut decks = read_decks_query(&app_state.pool, app_state.user.as_ref().unwrap().id)
+        .await
+        .unwrap_or_default();
+
+    decks.sort_by_key(|deck| deck.id);
+
+    let template = ImportTemplate {
+        decks,
+        uuid: app_state.uuid.clone(),
+    };
+
+    HtmlResponse(template).into_response()
+}
+
+pub async fn page_import_preview(
+    State(app_state): State<Arc<AppState>>,
+    Path(import_id): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Response {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return error_response(StatusCode::UNAUTHORIZED, "Unauthorized");
+    }
+
+    let preview =
+        match read_import_preview(&app_state, &import_id, app_state.user.as_ref().unwrap().id) {
+            Some(preview) => preview,
+            None => return error_response(StatusCode::NOT_FOUND, "Import not found"),
+        };
+
+    let template = ImportPreviewTemplate {
+        preview,
+        translation_placeholder: TRANSLATION_PLACEHOLDER,
+        uuid: app_state.uuid.clone(),
+    };
+
+    HtmlResponse(template).into_response()
+}
+
+pub async fn page_subtitles(
+    State(app_state): State<Arc<AppState>>,
+    Path(import_id): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Response {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return error_response(StatusCode::UNAUTHORIZED, "Unauthorized");
+    }
+
+    let user_id = app_state.user.as_ref().unwrap().id;
+
+    let (preview, cards) = match (
+        read_import_preview(&app_state, &import_id, user_id),
+        read_import_cards(&app_state, &import_id, user_id),
+    ) {
+        (Some(preview), Some(cards)) => (preview, cards),
+        _ => return error_response(StatusCode::NOT_FOUND, "Import not found"),
+    };
+
+    let template = SubtitlesTemplate {
+        preview,
+        cards,
+        uuid: app_state.uuid.clone(),
+    };
+
+    HtmlResponse(template).into_response()
+}
+
+pub async fn page_duplicates(
+    State(app_state): State<Arc<AppState>>,
+    Path(deck_id): Path<i32>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Response {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return error_response(StatusCode::UNAUTHORIZED, "Unauthorized");
+    }
+
+    let ignore_articles = query.get("ignore_articles").map(String::as_str) != Some("false");
+
+    let deck = match read_deck(
+        &app_state.pool,
+        deck_id,
+        app_state.user.as_ref().unwrap().id,
+    )
+    .await
+    {
+        Ok(Some(deck)) => deck,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, "Deck not found"),
+        Err(err) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, &err.to_string()),
+    };
+
+    let cards = match read_cards_query(&app_state.pool, deck_id, &[]).await {
+        Ok(cards) => cards,
+        Err(err) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, &err.to_string()),
+    };
+
+    let template = DuplicatesTemplate {
+        groups: find_duplicates(cards, &deck.from_language, ignore_articles),
+        deck,
+        ignore_articles,
+        uuid: app_state.uuid.clone(),
+    };
+
+    HtmlResponse(template).into_response()
+}
+
+pub async fn page_print(
+    State(app_state): State<Arc<AppState>>,
+    Path(deck_id): Path<i32>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Response {
+    let uuid = query.get("uuid");
+    if app_state.user.is_none() || uuid.is_none() || uuid.unwrap() != &app_state.uuid {
+        return error_response(StatusCode::UNAUTHORIZED, "Unauthorized");
+    }
+
+    let options = match PrintOptions::from_query(&query) {
+        Ok(options) => options,
+        Err(errors) => return error_response(StatusCode::BAD_REQUEST, &errors.to_message()),
+    };
+
+    let deck = match read_deck(
+        &app_state.pool,
+        deck_id,
+        app_state.user.as_ref().unwrap().id,
+    )
+    .await
+    {
+        Ok(Some(deck)) => deck,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, "Deck not found"),
+        Err(err) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, &err.to_string()),
+    };
+
+    let mut cards = match read_cards_query(&app_state.pool, deck_id, &options.tags).await {
+        Ok(cards) => cards,
+        Err(err) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, &err.to_string()),
+    };
+
+    if !options.ratings.is_empty() {
+        cards.retain(|card| options.ratings.contains(&card.rating));
+    }
+
+    cards.sort_by_key(|card| card.id);
+
+    let tags = match read_tags_query(&app_state.pool, deck.user_id).await {
+        Ok(tags) => tags,
+        Err(err) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, &err.to_string()),
+    };
+
+    let fields = PRINT_FIELDS
+        .into_iter()
+        .map(|field| {
+            let label = match field {
+                "to_text_primary" => deck.to_language_primary.clone(),
+                "to_text_secondary" => deck
+                    .to_language_secondary
+                    .clone()
+                    .unwrap_or_else(|| String::from("zweite Sprache")),
+                _ => String::from("Beispiel"),
+            };
+
+            (field, label)
+        })
+        .collect();
+
+    let template = PrintTemplate {
+        sheets: layout_sheets(&cards, &options),
+        num_cards: cards.len(),
+        deck,
+        options,
+        fields,
+        ratings: (MIN_RATING..=MAX_RATING).collect(),
+        tags,
         uuid: app_state.uuid.clone(),
     };
 
-    return HtmlResponse(template);
+    HtmlResponse(template).into_response()
 }
@@ -0,0 +1,294 @@
+use crate::duplicates::normalize_text;
+use crate::imports::{ImportCard, ImportDeck, PendingImport};
+use crate::validation::ValidationErrors;
+use crate::{Deck, PasteImportForm};
+use std::collections::{HashMap, HashSet};
+
+// the separators a pasted list is checked for, in the order they win a tie
+pub const PASTE_SEPARATORS: [&str; 7] = ["\t", " - ", " – ", " = ", ";", ":", ","];
+
+// how the pasted text was read, so that a wrong guess can be corrected on the paste page
+#[derive(Clone, serde::Serialize)]
+pub struct PasteImportDetails {
+    // "tab" for a tab, the separator itself otherwise, like " - "
+    pub separator: String,
+    pub swap: bool,
+    pub skip_duplicates: bool,
+}
+
+// helpers
+
+// the separator found in the most lines, chats mostly use a dash or a tab from a spreadsheet
+fn detect_separator(lines: &[(u64, &str)]) -> &'static str {
+    let mut best = PASTE_SEPARATORS[0];
+    let mut best_count = 0;
+
+    for separator in PASTE_SEPARATORS {
+        let count = lines
+            .iter()
+            .filter(|(_, line)| line.contains(separator))
+            .count();
+
+        if count > best_count {
+            best = separator;
+            best_count = count;
+        }
+    }
+
+    best
+}
+
+// spaced separators keep their spaces, so that sending the name back reads the list the same way
+fn separator_name(separator: &str) -> String {
+    match separator {
+        "\t" => String::from("tab"),
+        separator => String::from(separator),
+    }
+}
+
+// import
+
+// reads a pasted list with a card per line into a pending import for the deck, the line is split
+// at the first separator. lines that repeat a card of the list or the deck are flagged, or
+// skipped when asked to
+pub fn parse_paste(
+    user_id: i32,
+    deck: &Deck,
+    deck_texts: &HashSet<String>,
+    paste_import_form: PasteImportForm,
+) -> Result<PendingImport, ValidationErrors> {
+    let mut errors = ValidationErrors::default();
+
+    let text = paste_import_form.text.unwrap_or_default();
+
+    let lines: Vec<(u64, &str)> = text
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index as u64 + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .collect();
+
+    if lines.is_empty() {
+        errors.add("text", String::from("does not contain any lines"));
+        return Err(errors);
+    }
+
+    let separator = match &paste_import_form.separator {
+        Some(separator) => separator.as_str(),
+        None => detect_separator(&lines),
+    };
+
+    let swap = paste_import_form.swap.unwrap_or(false);
+    let skip_duplicates = paste_import_form.skip_duplicates.unwrap_or(false);
+
+    let mut pending_import = PendingImport::new(user_id, "paste");
+
+    pending_import.decks.push(ImportDeck {
+        deck_id: Some(deck.id),
+        name: format!("{} - {}", deck.from_language, deck.to_language_primary),
+        from_language: deck.from_language.clone(),
+        to_language_primary: deck.to_language_primary.clone(),
+        to_language_secondary: deck.to_language_secondary.clone(),
+        cards: Vec::new(),
+    });
+
+    // line of the first card per front text, compared like duplicates
+    let mut seen_lines: HashMap<String, u64> = HashMap::new();
+
+    for (line, text) in lines {
+        let (front, back) = match text.split_once(separator) {
+            Some((front, back)) => (front.trim(), back.trim()),
+            None => {
+                pending_import.skip_card(0, text, String::from("has no separator"), Some(line));
+                continue;
+            }
+        };
+
+        let (from_text, to_text_primary) = if swap { (back, front) } else { (front, back) };
+
+        let key = normalize_text(from_text, &deck.from_language, true);
+
+        let duplicate = if deck_texts.contains(&key) {
+            Some(String::from("already exists in the deck"))
+        } else {
+            seen_lines
+                .get(&key)
+                .map(|seen_line| format!("repeats line {}", seen_line))
+        };
+
+        if let (true, Some(message)) = (skip_duplicates, &duplicate) {
+            pending_import.skip_card(0, from_text, message.clone(), Some(line));
+            continue;
+        }
+
+        let card = ImportCard {
+            from_text: String::from(from_text),
+            to_text_primary: String::from(to_text_primary),
+            to_text_secondary: None,
+            example_text: None,
+            audio_file: None,
+            audio_url: None,
+            tags: Vec::new(),
+        };
+
+        if !pending_import.add_card(0, card, Some(line)) {
+            continue;
+        }
+
+        if let Some(message) = duplicate {
+            pending_import.flag_card(0, from_text, message, Some(line));
+        }
+
+        seen_lines.entry(key).or_insert(line);
+    }
+
+    pending_import.paste = Some(PasteImportDetails {
+        separator: separator_name(separator),
+        swap,
+        skip_duplicates,
+    });
+
+    Ok(pending_import)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imports::deck_fixture;
+    use crate::validation::Validate;
+
+    fn form(text: &str, separator: Option<&str>) -> PasteImportForm {
+        PasteImportForm {
+            text: Some(String::from(text)),
+            separator: separator.map(String::from),
+            swap: None,
+            skip_duplicates: None,
+        }
+        .validate(true)
+        .unwrap()
+    }
+
+    fn cards(pending_import: &PendingImport) -> Vec<(String, String)> {
+        pending_import.decks[0]
+            .cards
+            .iter()
+            .map(|card| (card.from_text.clone(), card.to_text_primary.clone()))
+            .collect()
+    }
+
+    fn pair(from_text: &str, to_text_primary: &str) -> (String, String) {
+        (String::from(from_text), String::from(to_text_primary))
+    }
+
+    #[test]
+    fn detect_separator_takes_the_most_common_one() {
+        assert_eq!(
+            detect_separator(&[(1, "Hund - dog"), (2, "Katze - cat")]),
+            " - "
+        );
+        assert_eq!(
+            detect_separator(&[(1, "Hund\tdog"), (2, "Katze\tcat")]),
+            "\t"
+        );
+        assert_eq!(
+            detect_separator(&[(1, "Hund; dog"), (2, "Katze: cat"), (3, "Maus; mouse")]),
+            ";"
+        );
+        assert_eq!(detect_separator(&[(1, "E-Mail - email")]), " - ");
+        // a tie goes to the earlier separator
+        assert_eq!(
+            detect_separator(&[(1, "Hund = dog"), (2, "Katze - cat")]),
+            " - "
+        );
+        assert_eq!(detect_separator(&[(1, "Hund")]), "\t");
+    }
+
+    #[test]
+    fn parse_paste_splits_at_the_detected_separator() {
+        let pending_import = parse_paste(
+            1,
+            &deck_fixture("Deutsch"),
+            &HashSet::new(),
+            form(
+                "Hund - dog\n\n  E-Mail - email \nKinder-garten - kindergarten\nKatze",
+                None,
+            ),
+        )
+        .unwrap();
+
+        assert_eq!(
+            cards(&pending_import),
+            vec![
+                pair("Hund", "dog"),
+                pair("E-Mail", "email"),
+                pair("Kinder-garten", "kindergarten"),
+            ]
+        );
+        assert_eq!(pending_import.skipped.len(), 1);
+        assert_eq!(pending_import.skipped[0].line, Some(5));
+        assert_eq!(pending_import.paste.as_ref().unwrap().separator, " - ");
+    }
+
+    #[test]
+    fn parse_paste_keeps_spaces_around_given_dashes() {
+        for separator in ["-", " - "] {
+            let pending_import = parse_paste(
+                1,
+                &deck_fixture("Deutsch"),
+                &HashSet::new(),
+                form("E-Mail - email", Some(separator)),
+            )
+            .unwrap();
+
+            assert_eq!(cards(&pending_import), vec![pair("E-Mail", "email")]);
+            assert_eq!(pending_import.paste.as_ref().unwrap().separator, " - ");
+        }
+
+        let pending_import = parse_paste(
+            1,
+            &deck_fixture("Deutsch"),
+            &HashSet::new(),
+            form("Hund\tdog", Some("tab")),
+        )
+        .unwrap();
+
+        assert_eq!(cards(&pending_import), vec![pair("Hund", "dog")]);
+        assert_eq!(pending_import.paste.as_ref().unwrap().separator, "tab");
+    }
+
+    #[test]
+    fn parse_paste_swaps_and_reports_duplicates() {
+        let deck_texts = HashSet::from([normalize_text("Hund", "Deutsch", true)]);
+
+        let mut paste_import_form = form("dog: der Hund\ncat: Katze\ncats: die Katze", Some(":"));
+        paste_import_form.swap = Some(true);
+
+        let pending_import =
+            parse_paste(1, &deck_fixture("Deutsch"), &deck_texts, paste_import_form).unwrap();
+
+        assert_eq!(
+            cards(&pending_import),
+            vec![
+                pair("der Hund", "dog"),
+                pair("Katze", "cat"),
+                pair("die Katze", "cats"),
+            ]
+        );
+        assert_eq!(pending_import.flagged.len(), 2);
+        assert_eq!(
+            pending_import.flagged[0].message,
+            "already exists in the deck"
+        );
+        assert_eq!(pending_import.flagged[1].message, "repeats line 2");
+
+        let mut paste_import_form = form("dog: der Hund\ncat: Katze\ncats: die Katze", Some(":"));
+        paste_import_form.swap = Some(true);
+        paste_import_form.skip_duplicates = Some(true);
+
+        let pending_import =
+            parse_paste(1, &deck_fixture("Deutsch"), &deck_texts, paste_import_form).unwrap();
+
+        assert_eq!(cards(&pending_import), vec![pair("Katze", "cat")]);
+        assert_eq!(pending_import.skipped.len(), 2);
+    }
+}
@@ -0,0 +1,296 @@
+use crate::queries::split_tag_names;
+use crate::validation::{ValidationErrors, MAX_RATING, MIN_RATING};
+use crate::Card;
+use std::collections::HashMap;
+
+// the fields that can go on the back, the front always shows from_text
+pub const PRINT_FIELDS: [&str; 3] = ["to_text_primary", "to_text_secondary", "example_text"];
+
+const DEFAULT_PRINT_FIELDS: [&str; 2] = ["to_text_primary", "to_text_secondary"];
+
+// a4 fits 3 by 4 cards of about 6 by 6 cm with room for the printer margins
+const DEFAULT_COLUMNS: usize = 3;
+const DEFAULT_ROWS: usize = 4;
+const MAX_COLUMNS: usize = 6;
+const MAX_ROWS: usize = 10;
+
+// the options of a print, read from the query of the print page
+pub struct PrintOptions {
+    pub fields: Vec<String>,
+    // empty for all ratings
+    pub ratings: Vec<i32>,
+    pub tags: Vec<String>,
+    pub columns: usize,
+    pub rows: usize,
+}
+
+// the texts of one side of a card, the example is set in smaller type
+pub struct PrintCell {
+    pub texts: Vec<String>,
+    pub example_text: Option<String>,
+}
+
+// a sheet is printed front and back, the back is mirrored so that each back lands behind its front
+// when the sheet is flipped on the long edge. None for the blank cells that fill up the last sheet
+pub struct PrintSheet {
+    pub fronts: Vec<Option<PrintCell>>,
+    pub backs: Vec<Option<PrintCell>>,
+}
+
+// helpers
+
+// the items of a comma separated list, like fields=to_text_primary,example_text
+pub fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+fn read_count(
+    errors: &mut ValidationErrors,
+    field: &'static str,
+    value: Option<&String>,
+    default: usize,
+    max: usize,
+) -> usize {
+    match value.map(|value| value.trim()) {
+        None | Some("") => default,
+        Some(value) => match value.parse::<usize>() {
+            Ok(count) if (1..=max).contains(&count) => count,
+            _ => {
+                errors.add(field, format!("must be between 1 and {}", max));
+                default
+            }
+        },
+    }
+}
+
+fn back_cell(card: &Card, fields: &[String]) -> PrintCell {
+    let mut texts = Vec::new();
+    let mut example_text = None;
+
+    for field in fields {
+        match field.as_str() {
+            "to_text_primary" => texts.push(card.to_text_primary.clone()),
+            "to_text_secondary" => texts.extend(card.to_text_secondary.clone()),
+            "example_text" => example_text = card.example_text.clone(),
+            _ => {}
+        }
+    }
+
+    PrintCell {
+        texts,
+        example_text,
+    }
+}
+
+// options
+
+impl PrintOptions {
+    pub fn from_query(query: &HashMap<String, String>) -> Result<PrintOptions, ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+
+        let fields: Vec<String> = match query.get("fields") {
+            Some(fields) => split_list(fields),
+            None => DEFAULT_PRINT_FIELDS.map(String::from).to_vec(),
+        };
+
+        if let Some(field) = fields
+            .iter()
+            .find(|field| !PRINT_FIELDS.contains(&field.as_str()))
+        {
+            errors.add(
+                "fields",
+                format!(
+                    "must be a list of {}, not {}",
+                    PRINT_FIELDS.join(", "),
+                    field
+                ),
+            );
+        }
+
+        let mut ratings = Vec::new();
+
+        for rating in split_list(query.get("ratings").map_or("", String::as_str)) {
+            match rating.parse::<i32>() {
+                Ok(rating) if (MIN_RATING..=MAX_RATING).contains(&rating) => ratings.push(rating),
+                _ => errors.add(
+                    "ratings",
+                    format!(
+                        "must be a list of ratings between {} and {}",
+                        MIN_RATING, MAX_RATING
+                    ),
+                ),
+            }
+        }
+
+        let columns = read_count(
+            &mut errors,
+            "columns",
+            query.get("columns"),
+            DEFAULT_COLUMNS,
+            MAX_COLUMNS,
+        );
+        let rows = read_count(
+            &mut errors,
+            "rows",
+            query.get("rows"),
+            DEFAULT_ROWS,
+            MAX_ROWS,
+        );
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        Ok(PrintOptions {
+            fields,
+            ratings,
+            tags: split_tag_names(query.get("tags").map_or("", String::as_str)),
+            columns,
+            rows,
+        })
+    }
+
+    pub fn has_field(&self, field: &str) -> bool {
+        self.fields.iter().any(|selected| selected == field)
+    }
+
+    pub fn has_rating(&self, rating: &i32) -> bool {
+        self.ratings.contains(rating)
+    }
+}
+
+// layout
+
+// lays the cards out in sheets of columns by rows, in the order they are given
+pub fn layout_sheets(cards: &[Card], options: &PrintOptions) -> Vec<PrintSheet> {
+    let cells_per_sheet = options.columns * options.rows;
+
+    cards
+        .chunks(cells_per_sheet)
+        .map(|chunk| {
+            let cell = |index: usize, front: bool| {
+                chunk.get(index).map(|card| {
+                    if front {
+                        PrintCell {
+                            texts: vec![card.from_text.clone()],
+                            example_text: None,
+                        }
+                    } else {
+                        back_cell(card, &options.fields)
+                    }
+                })
+            };
+
+            let fronts = (0..cells_per_sheet)
+                .map(|index| cell(index, true))
+                .collect();
+
+            // each row of the back is read from right to left
+            let backs = (0..cells_per_sheet)
+                .map(|index| {
+                    let row = index / options.columns;
+                    let column = options.columns - 1 - index % options.columns;
+                    cell(row * options.columns + column, false)
+                })
+                .collect();
+
+            PrintSheet { fronts, backs }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imports::card_fixture;
+
+    fn options(columns: usize, rows: usize) -> PrintOptions {
+        PrintOptions {
+            fields: vec![String::from("to_text_primary")],
+            ratings: Vec::new(),
+            tags: Vec::new(),
+            columns,
+            rows,
+        }
+    }
+
+    fn texts(cells: &[Option<PrintCell>]) -> Vec<Option<&str>> {
+        cells
+            .iter()
+            .map(|cell| cell.as_ref().map(|cell| cell.texts[0].as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn split_list_trims_and_drops_empty_items() {
+        assert_eq!(
+            split_list(" example_text,,to_text_primary , "),
+            vec!["example_text", "to_text_primary"]
+        );
+        assert!(split_list("").is_empty());
+    }
+
+    #[test]
+    fn from_query_reads_fields_and_ratings() {
+        let query = HashMap::from([
+            (
+                String::from("fields"),
+                String::from("example_text, to_text_primary"),
+            ),
+            (String::from("ratings"), String::from("1,3")),
+        ]);
+
+        let options = PrintOptions::from_query(&query).unwrap();
+
+        assert_eq!(options.fields, vec!["example_text", "to_text_primary"]);
+        assert_eq!(options.ratings, vec![1, 3]);
+        assert_eq!(
+            (options.columns, options.rows),
+            (DEFAULT_COLUMNS, DEFAULT_ROWS)
+        );
+
+        let query = HashMap::from([
+            (String::from("fields"), String::from("audio_url")),
+            (String::from("ratings"), String::from("9")),
+            (String::from("columns"), String::from("0")),
+        ]);
+
+        assert!(PrintOptions::from_query(&query).is_err());
+    }
+
+    #[test]
+    fn layout_sheets_mirrors_the_backs_of_a_partial_sheet() {
+        let cards: Vec<Card> = (1..=8)
+            .map(|id| card_fixture(id, &format!("front {}", id), &format!("back {}", id)))
+            .collect();
+
+        let sheets = layout_sheets(&cards, &options(3, 2));
+
+        assert_eq!(sheets.len(), 2);
+        assert_eq!(
+            texts(&sheets[0].backs),
+            vec![
+                Some("back 3"),
+                Some("back 2"),
+                Some("back 1"),
+                Some("back 6"),
+                Some("back 5"),
+                Some("back 4"),
+            ]
+        );
+
+        // the two cards of the last sheet keep their places, their backs are on the right
+        assert_eq!(
+            texts(&sheets[1].fronts),
+            vec![Some("front 7"), Some("front 8"), None, None, None, None]
+        );
+        assert_eq!(
+            texts(&sheets[1].backs),
+            vec![None, Some("back 8"), Some("back 7"), None, None, None]
+        );
+    }
+}
@@ -1,9 +1,51 @@
-use crate::{Card, CardForm, Deck, DeckForm, User, UserForm};
-use sqlx::{query_builder::QueryBuilder, Error, Pool, Postgres};
+use crate::bundles::Bundle;
+use crate::duplicates::{resolve_duplicates, DuplicateResolution};
+use crate::imports::ImportDeck;
+use crate::webhooks::generate_secret;
+use crate::{
+    Card, CardExport, CardForm, CardRelationForm, CardRevision, Deck, DeckCloneForm, DeckForm,
+    DeckOverview, DeckStats, RelatedCard, Review, ReviewBatchResult, ReviewForm, SkippedReview,
+    SyncCard, SyncDeleted, SyncPull, Tag, TagForm, User, UserForm, Webhook, WebhookDelivery,
+    WebhookForm,
+};
+use chrono::NaiveDateTime;
+use serde_json::Value;
+use sqlx::{query_builder::QueryBuilder, Error, Pool, Postgres, Transaction};
+use std::collections::{HashMap, HashSet};
+use std::pin::Pin;
+use tokio_stream::{Stream, StreamExt};
 
 #[derive(serde::Serialize)]
 pub struct DatabaseQueryResult {
-    rows_affected: u64,
+    pub rows_affected: u64,
+}
+
+struct SyncChangeRow {
+    entity_type: String,
+    entity_id: i32,
+    deleted: bool,
+}
+
+struct CardRelation {
+    card_id: i32,
+    related_card_id: i32,
+    relation_type: Option<String>,
+}
+
+pub struct CardRelationRecord {
+    pub card_id: i32,
+    pub related_card_id: i32,
+    pub relation_type: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+// everything a bundle holds, read from one snapshot and ordered by id
+pub struct BundleRecords {
+    pub decks: Vec<Deck>,
+    pub cards: Vec<Card>,
+    pub card_tags: HashMap<i32, Vec<String>>,
+    pub relations: Vec<CardRelationRecord>,
+    pub reviews: Vec<Review>,
 }
 
 // database queries
@@ -14,38 +56,29 @@ pub async fn read_users_query(pool: &Pool<Postgres>) -> Result<Vec<User>, Error>
         .await
 }
 
-pub async fn read_user(pool: &Pool<Postgres>, user_id: i32) -> Result<Vec<User>, Error> {
+pub async fn read_user(pool: &Pool<Postgres>, user_id: i32) -> Result<Option<User>, Error> {
     sqlx::query_as!(User, "SELECT * FROM users WHERE id = $1", user_id)
-        .fetch_all(pool)
+        .fetch_optional(pool)
         .await
 }
 
-pub async fn create_user_query(
-    pool: &Pool<Postgres>,
-    user_form: UserForm,
-) -> Result<DatabaseQueryResult, Error> {
-    if let None = user_form.name {
+pub async fn create_user_query(pool: &Pool<Postgres>, user_form: UserForm) -> Result<User, Error> {
+    if user_form.name.is_none() {
         return Err(Error::RowNotFound);
     }
 
-    if let None = user_form.email {
+    if user_form.email.is_none() {
         return Err(Error::RowNotFound);
     }
 
-    let result = sqlx::query!(
-        "INSERT INTO users (name, email) VALUES ($1, $2)",
+    sqlx::query_as!(
+        User,
+        "INSERT INTO users (name, email) VALUES ($1, $2) RETURNING *",
         user_form.name,
         user_form.email,
     )
-    .execute(pool)
-    .await;
-
-    match result {
-        Ok(pg_query_result) => Ok(DatabaseQueryResult {
-            rows_affected: pg_query_result.rows_affected(),
-        }),
-        Err(err) => Err(err),
-    }
+    .fetch_one(pool)
+    .await
 }
 
 pub async fn update_user_query(
@@ -117,18 +150,105 @@ pub async fn read_decks_query(pool: &Pool<Postgres>, user_id: i32) -> Result<Vec
         .await
 }
 
+// counts per deck, a card is due once the interval for its rating has passed since it was last
+// updated: unrated and red (4) cards right away, then 1, 3 and 7 days for ratings 3, 2 and 1
+async fn read_deck_stats_query(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+    deck_id: Option<i32>,
+) -> Result<Vec<DeckStats>, Error> {
+    let due_before = (chrono::Utc::now().date_naive() + chrono::Days::new(1))
+        .and_hms_opt(0, 0, 0)
+        .unwrap();
+
+    sqlx::query_as!(
+        DeckStats,
+        r#"SELECT
+            decks.id AS deck_id,
+            COUNT(cards.id) AS "total!",
+            COUNT(cards.id) FILTER (WHERE cards.rating = 0) AS "unrated!",
+            COUNT(cards.id) FILTER (WHERE cards.rating = 1) AS "rating_1!",
+            COUNT(cards.id) FILTER (WHERE cards.rating = 2) AS "rating_2!",
+            COUNT(cards.id) FILTER (WHERE cards.rating = 3) AS "rating_3!",
+            COUNT(cards.id) FILTER (WHERE cards.rating = 4) AS "rating_4!",
+            COUNT(cards.id) FILTER (WHERE cards.updated_at + make_interval(days => CASE cards.rating WHEN 1 THEN 7 WHEN 2 THEN 3 WHEN 3 THEN 1 ELSE 0 END) < $3) AS "due_today!"
+        FROM decks
+        LEFT JOIN cards ON cards.deck_id = decks.id
+        WHERE decks.user_id = $1 AND ($2::INT IS NULL OR decks.id = $2)
+        GROUP BY decks.id"#,
+        user_id,
+        deck_id,
+        due_before,
+    )
+        .fetch_all(pool)
+        .await
+}
+
+// decks with their cards and counts attached when asked for
+pub async fn read_deck_overviews_query(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+    deck_id: Option<i32>,
+    include_cards: bool,
+    include_stats: bool,
+) -> Result<Vec<DeckOverview>, Error> {
+    let decks = sqlx::query_as!(
+        Deck,
+        "SELECT * FROM decks WHERE user_id = $1 AND ($2::INT IS NULL OR id = $2) ORDER BY id",
+        user_id,
+        deck_id,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut cards: HashMap<i32, Vec<Card>> = HashMap::new();
+
+    if include_cards {
+        let deck_ids: Vec<i32> = decks.iter().map(|deck| deck.id).collect();
+
+        let all_cards = sqlx::query_as!(
+            Card,
+            "SELECT * FROM cards WHERE deck_id = ANY($1) ORDER BY id",
+            &deck_ids
+        )
+        .fetch_all(pool)
+        .await?;
+
+        for card in all_cards {
+            cards.entry(card.deck_id).or_default().push(card);
+        }
+    }
+
+    let mut stats: HashMap<i32, DeckStats> = HashMap::new();
+
+    if include_stats {
+        for deck_stats in read_deck_stats_query(pool, user_id, deck_id).await? {
+            stats.insert(deck_stats.deck_id, deck_stats);
+        }
+    }
+
+    Ok(decks
+        .into_iter()
+        .map(|deck| DeckOverview {
+            cards: include_cards.then(|| cards.remove(&deck.id).unwrap_or_default()),
+            stats: stats.remove(&deck.id),
+            deck,
+        })
+        .collect())
+}
+
 pub async fn read_deck(
     pool: &Pool<Postgres>,
     deck_id: i32,
     user_id: i32,
-) -> Result<Vec<Deck>, Error> {
+) -> Result<Option<Deck>, Error> {
     sqlx::query_as!(
         Deck,
         "SELECT * FROM decks WHERE id = $1 AND user_id = $2",
         deck_id,
         user_id
     )
-    .fetch_all(pool)
+    .fetch_optional(pool)
     .await
 }
 
@@ -136,42 +256,31 @@ pub async fn create_deck_query(
     pool: &Pool<Postgres>,
     deck_form: DeckForm,
     user_id: i32,
-) -> Result<DatabaseQueryResult, Error> {
-    if let None = deck_form.from_language {
+) -> Result<Deck, Error> {
+    if deck_form.from_language.is_none() {
         return Err(Error::RowNotFound);
     }
 
-    if let None = deck_form.to_language_primary {
+    if deck_form.to_language_primary.is_none() {
         return Err(Error::RowNotFound);
     }
 
-    let result = sqlx::query!(
-        "INSERT INTO decks (user_id, from_language, to_language_primary, to_language_secondary, design_key) VALUES ($1, $2, $3, $4, $5)",
+    sqlx::query_as!(
+        Deck,
+        "INSERT INTO decks (user_id, from_language, to_language_primary, to_language_secondary, design_key) VALUES ($1, $2, $3, $4, $5) RETURNING *",
         user_id,
         deck_form.from_language,
         deck_form.to_language_primary,
         deck_form.to_language_secondary,
         deck_form.design_key,
     )
-        .execute(pool)
-        .await;
-
-    match result {
-        Ok(pg_query_result) => Ok(DatabaseQueryResult {
-            rows_affected: pg_query_result.rows_affected(),
-        }),
-        Err(err) => Err(err),
-    }
+        .fetch_one(pool)
+        .await
 }
 
-pub async fn update_deck_query(
-    pool: &Pool<Postgres>,
-    deck_id: i32,
-    deck_form: DeckForm,
-    user_id: i32,
-) -> Result<DatabaseQueryResult, Error> {
-    let mut query = QueryBuilder::new("UPDATE decks SET");
-
+// appends "column = value" pairs for every field set in the form and returns how many were added,
+// empty optional texts are written as NULL
+fn push_deck_form_updates(query: &mut QueryBuilder<'_, Postgres>, deck_form: DeckForm) -> i32 {
     let mut num_updates = 0;
 
     if let Some(from_language) = deck_form.from_language {
@@ -197,7 +306,7 @@ pub async fn update_deck_query(
             query.push(",");
         }
         query.push(" to_language_secondary =");
-        query.push_bind(to_language_secondary);
+        query.push_bind(Some(to_language_secondary).filter(|value| !value.is_empty()));
         num_updates += 1;
     }
 
@@ -206,7 +315,7 @@ pub async fn update_deck_query(
             query.push(",");
         }
         query.push(" design_key =");
-        query.push_bind(design_key);
+        query.push_bind(Some(design_key).filter(|value| !value.is_empty()));
         num_updates += 1;
     }
 
@@ -219,6 +328,19 @@ pub async fn update_deck_query(
         num_updates += 1;
     }
 
+    num_updates
+}
+
+pub async fn update_deck_query(
+    pool: &Pool<Postgres>,
+    deck_id: i32,
+    deck_form: DeckForm,
+    user_id: i32,
+) -> Result<DatabaseQueryResult, Error> {
+    let mut query = QueryBuilder::new("UPDATE decks SET");
+
+    let num_updates = push_deck_form_updates(&mut query, deck_form);
+
     if num_updates == 0 {
         return Err(Error::RowNotFound);
     }
@@ -260,24 +382,100 @@ pub async fn delete_deck_query(
     }
 }
 
-pub async fn read_cards_query(pool: &Pool<Postgres>, deck_id: i32) -> Result<Vec<Card>, Error> {
-    sqlx::query_as!(Card, "SELECT * FROM cards WHERE deck_id = $1", deck_id)
+// with tags given, only cards carrying at least one of them are read
+pub async fn read_cards_query(
+    pool: &Pool<Postgres>,
+    deck_id: i32,
+    tags: &[String],
+) -> Result<Vec<Card>, Error> {
+    sqlx::query_as!(
+        Card,
+        "SELECT * FROM cards WHERE deck_id = $1 AND (cardinality($2::VARCHAR[]) = 0 OR EXISTS (SELECT 1 FROM card_tags JOIN tags ON tags.id = card_tags.tag_id WHERE card_tags.card_id = cards.id AND tags.name = ANY($2)))",
+        deck_id,
+        tags
+    )
         .fetch_all(pool)
         .await
 }
 
+struct CardExportRow {
+    id: i32,
+    deck_id: i32,
+    related_card_ids: Vec<i32>,
+    from_text: String,
+    to_text_primary: String,
+    to_text_secondary: Option<String>,
+    example_text: Option<String>,
+    audio_url: Option<String>,
+    seen_at: NaiveDateTime,
+    seen_for: Option<i32>,
+    rating: i32,
+    prev_rating: i32,
+    created_at: NaiveDateTime,
+    updated_at: NaiveDateTime,
+    tags: Vec<String>,
+    from_language: String,
+    to_language_primary: String,
+    to_language_secondary: Option<String>,
+}
+
+// the cards of a user, or of one of their decks, read row by row for exports of any size
+pub fn stream_card_exports_query(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+    deck_id: Option<i32>,
+) -> Pin<Box<dyn Stream<Item = Result<CardExport, Error>> + Send + '_>> {
+    let rows = sqlx::query_as!(
+        CardExportRow,
+        r#"SELECT cards.*,
+            COALESCE((SELECT array_agg(tags.name ORDER BY tags.name) FROM card_tags JOIN tags ON tags.id = card_tags.tag_id WHERE card_tags.card_id = cards.id), '{}') AS "tags!",
+            decks.from_language, decks.to_language_primary, decks.to_language_secondary
+        FROM cards JOIN decks ON decks.id = cards.deck_id
+        WHERE decks.user_id = $1 AND ($2::INT IS NULL OR decks.id = $2)
+        ORDER BY cards.deck_id, cards.id"#,
+        user_id,
+        deck_id
+    )
+    .fetch(pool);
+
+    Box::pin(rows.map(|row| {
+        row.map(|row| CardExport {
+            card: Card {
+                id: row.id,
+                deck_id: row.deck_id,
+                related_card_ids: row.related_card_ids,
+                from_text: row.from_text,
+                to_text_primary: row.to_text_primary,
+                to_text_secondary: row.to_text_secondary,
+                example_text: row.example_text,
+                audio_url: row.audio_url,
+                seen_at: row.seen_at,
+                seen_for: row.seen_for,
+                rating: row.rating,
+                prev_rating: row.prev_rating,
+                created_at: row.created_at,
+                updated_at: row.updated_at,
+            },
+            tags: row.tags,
+            from_language: row.from_language,
+            to_language_primary: row.to_language_primary,
+            to_language_secondary: row.to_language_secondary,
+        })
+    }))
+}
+
 pub async fn read_card_query(
     pool: &Pool<Postgres>,
     deck_id: i32,
     card_id: i32,
-) -> Result<Vec<Card>, Error> {
+) -> Result<Option<Card>, Error> {
     sqlx::query_as!(
         Card,
         "SELECT * FROM cards WHERE id = $1 AND deck_id = $2",
         card_id,
         deck_id
     )
-    .fetch_all(pool)
+    .fetch_optional(pool)
     .await
 }
 
@@ -285,17 +483,20 @@ pub async fn create_card_query(
     pool: &Pool<Postgres>,
     deck_id: i32,
     card_form: CardForm,
-) -> Result<DatabaseQueryResult, Error> {
-    if let None = card_form.from_text {
+) -> Result<Card, Error> {
+    if card_form.from_text.is_none() {
         return Err(Error::RowNotFound);
     }
 
-    if let None = card_form.to_text_primary {
+    if card_form.to_text_primary.is_none() {
         return Err(Error::RowNotFound);
     }
 
-    let result = sqlx::query!(
-        "INSERT INTO cards (deck_id, from_text, to_text_primary, to_text_secondary, example_text, audio_url) VALUES ($1, $2, $3, $4, $5, $6)",
+    let mut transaction = pool.begin().await?;
+
+    let card = sqlx::query_as!(
+        Card,
+        "INSERT INTO cards (deck_id, from_text, to_text_primary, to_text_secondary, example_text, audio_url) VALUES ($1, $2, $3, $4, $5, $6) RETURNING *",
         deck_id,
         card_form.from_text,
         card_form.to_text_primary,
@@ -303,35 +504,21 @@ pub async fn create_card_query(
         card_form.example_text,
         card_form.audio_url,
     )
-        .execute(pool)
-        .await;
+        .fetch_one(&mut *transaction)
+        .await?;
 
-    match result {
-        Ok(pg_query_result) => Ok(DatabaseQueryResult {
-            rows_affected: pg_query_result.rows_affected(),
-        }),
-        Err(err) => Err(err),
+    if let Some(tags) = card_form.tags {
+        update_card_tags(&mut transaction, card.id, &split_tag_names(&tags)).await?;
     }
-}
 
-pub async fn update_card_query(
-    pool: &Pool<Postgres>,
-    deck_id: i32,
-    card_id: i32,
-    card_form: CardForm,
-) -> Result<DatabaseQueryResult, Error> {
-    let mut query = QueryBuilder::new("UPDATE cards SET");
+    transaction.commit().await?;
 
-    let mut num_updates = 0;
+    Ok(card)
+}
 
-    if let Some(related_card_ids) = card_form.related_card_ids {
-        if num_updates > 0 {
-            query.push(",");
-        }
-        query.push(" related_card_ids =");
-        query.push_bind(related_card_ids);
-        num_updates += 1;
-    }
+// same as push_deck_form_updates for cards
+fn push_card_form_updates(query: &mut QueryBuilder<'_, Postgres>, card_form: CardForm) -> i32 {
+    let mut num_updates = 0;
 
     if let Some(from_text) = card_form.from_text {
         if num_updates > 0 {
@@ -356,7 +543,7 @@ pub async fn update_card_query(
             query.push(",");
         }
         query.push(" to_text_secondary =");
-        query.push_bind(to_text_secondary);
+        query.push_bind(Some(to_text_secondary).filter(|value| !value.is_empty()));
         num_updates += 1;
     }
 
@@ -365,7 +552,7 @@ pub async fn update_card_query(
             query.push(",");
         }
         query.push(" example_text =");
-        query.push_bind(example_text);
+        query.push_bind(Some(example_text).filter(|value| !value.is_empty()));
         num_updates += 1;
     }
 
@@ -374,7 +561,7 @@ pub async fn update_card_query(
             query.push(",");
         }
         query.push(" audio_url =");
-        query.push_bind(audio_url);
+        query.push_bind(Some(audio_url).filter(|value| !value.is_empty()));
         num_updates += 1;
     }
 
@@ -405,7 +592,22 @@ pub async fn update_card_query(
         num_updates += 1;
     }
 
-    if num_updates == 0 {
+    num_updates
+}
+
+pub async fn update_card_query(
+    pool: &Pool<Postgres>,
+    deck_id: i32,
+    card_id: i32,
+    mut card_form: CardForm,
+) -> Result<DatabaseQueryResult, Error> {
+    let tags = card_form.tags.take();
+
+    let mut query = QueryBuilder::new("UPDATE cards SET");
+
+    let num_updates = push_card_form_updates(&mut query, card_form);
+
+    if num_updates == 0 && tags.is_none() {
         return Err(Error::RowNotFound);
     }
 
@@ -415,14 +617,32 @@ pub async fn update_card_query(
     query.push(" AND deck_id =");
     query.push_bind(deck_id);
 
-    let result = query.build().execute(pool).await;
+    let mut transaction = pool.begin().await?;
 
-    match result {
-        Ok(pg_query_result) => Ok(DatabaseQueryResult {
-            rows_affected: pg_query_result.rows_affected(),
-        }),
-        Err(err) => Err(err),
+    let rows_affected = if num_updates > 0 {
+        query
+            .build()
+            .execute(&mut *transaction)
+            .await?
+            .rows_affected()
+    } else {
+        sqlx::query!(
+            "SELECT id FROM cards WHERE id = $1 AND deck_id = $2",
+            card_id,
+            deck_id
+        )
+        .fetch_optional(&mut *transaction)
+        .await?
+        .map_or(0, |_| 1)
+    };
+
+    if let (Some(tags), 1) = (tags, rows_affected) {
+        update_card_tags(&mut transaction, card_id, &split_tag_names(&tags)).await?;
     }
+
+    transaction.commit().await?;
+
+    Ok(DatabaseQueryResult { rows_affected })
 }
 
 pub async fn delete_card_query(
@@ -445,3 +665,1908 @@ pub async fn delete_card_query(
         Err(err) => Err(err),
     }
 }
+
+// keeps the cards and decks triggers from touching updated_at for the rest of the transaction
+async fn preserve_updated_at(transaction: &mut Transaction<'_, Postgres>) -> Result<(), Error> {
+    sqlx::query!("SELECT set_config('cards.preserve_updated_at', 'on', true) AS cards, set_config('decks.preserve_updated_at', 'on', true) AS decks")
+        .fetch_one(&mut **transaction)
+        .await?;
+
+    Ok(())
+}
+
+// inserts copies of the cards into the target deck and returns the new ids in the same order
+async fn insert_card_copies(
+    transaction: &mut Transaction<'_, Postgres>,
+    cards: &[Card],
+    target_deck_id: i32,
+) -> Result<Vec<i32>, Error> {
+    let mut new_card_ids: HashMap<i32, i32> = HashMap::new();
+
+    for card in cards {
+        let new_card_id = sqlx::query_scalar!(
+            "INSERT INTO cards (deck_id, from_text, to_text_primary, to_text_secondary, example_text, audio_url, seen_at, seen_for, rating, prev_rating, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12) RETURNING id",
+            target_deck_id,
+            card.from_text,
+            card.to_text_primary,
+            card.to_text_secondary,
+            card.example_text,
+            card.audio_url,
+            card.seen_at,
+            card.seen_for,
+            card.rating,
+            card.prev_rating,
+            card.created_at,
+            card.updated_at,
+        )
+            .fetch_one(&mut **transaction)
+            .await?;
+
+        new_card_ids.insert(card.id, new_card_id);
+    }
+
+    // relations between copied cards point at the copies, all others stay with the originals
+
+    let card_ids: Vec<i32> = cards.iter().map(|card| card.id).collect();
+
+    let relations = sqlx::query_as!(
+        CardRelation,
+        "SELECT card_id, related_card_id, relation_type FROM card_relations WHERE card_id = ANY($1) OR related_card_id = ANY($1)",
+        &card_ids,
+    )
+    .fetch_all(&mut **transaction)
+    .await?;
+
+    for relation in relations {
+        let card_id = *new_card_ids
+            .get(&relation.card_id)
+            .unwrap_or(&relation.card_id);
+        let related_card_id = *new_card_ids
+            .get(&relation.related_card_id)
+            .unwrap_or(&relation.related_card_id);
+
+        sqlx::query!(
+            "INSERT INTO card_relations (card_id, related_card_id, relation_type) VALUES (LEAST($1::INT, $2::INT), GREATEST($1::INT, $2::INT), $3) ON CONFLICT DO NOTHING",
+            card_id,
+            related_card_id,
+            relation.relation_type,
+        )
+            .execute(&mut **transaction)
+            .await?;
+    }
+
+    Ok(cards.iter().map(|card| new_card_ids[&card.id]).collect())
+}
+
+pub async fn move_cards_query(
+    pool: &Pool<Postgres>,
+    deck_id: i32,
+    target_deck_id: i32,
+    card_ids: Vec<i32>,
+) -> Result<Option<Vec<Card>>, Error> {
+    let mut transaction = pool.begin().await?;
+
+    preserve_updated_at(&mut transaction).await?;
+
+    let cards = sqlx::query_as!(
+        Card,
+        "UPDATE cards SET deck_id = $1 WHERE deck_id = $2 AND id = ANY($3) RETURNING *",
+        target_deck_id,
+        deck_id,
+        &card_ids,
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+
+    if cards.len() != card_ids.len() {
+        transaction.rollback().await?;
+        return Ok(None);
+    }
+
+    transaction.commit().await?;
+
+    Ok(Some(cards))
+}
+
+pub async fn copy_cards_query(
+    pool: &Pool<Postgres>,
+    deck_id: i32,
+    target_deck_id: i32,
+    card_ids: Vec<i32>,
+) -> Result<Option<Vec<Card>>, Error> {
+    let mut transaction = pool.begin().await?;
+
+    preserve_updated_at(&mut transaction).await?;
+
+    let cards = sqlx::query_as!(
+        Card,
+        "SELECT * FROM cards WHERE deck_id = $1 AND id = ANY($2) ORDER BY id",
+        deck_id,
+        &card_ids,
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+
+    if cards.len() != card_ids.len() {
+        transaction.rollback().await?;
+        return Ok(None);
+    }
+
+    let new_card_ids = insert_card_copies(&mut transaction, &cards, target_deck_id).await?;
+
+    let new_cards = sqlx::query_as!(
+        Card,
+        "SELECT * FROM cards WHERE id = ANY($1) ORDER BY id",
+        &new_card_ids,
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(Some(new_cards))
+}
+
+pub async fn clone_deck_query(
+    pool: &Pool<Postgres>,
+    deck_id: i32,
+    deck_clone_form: DeckCloneForm,
+    user_id: i32,
+) -> Result<Option<Deck>, Error> {
+    let reset_progress = deck_clone_form.reset_progress.unwrap_or(false);
+    let swap_languages = deck_clone_form.swap_languages.unwrap_or(false);
+
+    let mut transaction = pool.begin().await?;
+
+    preserve_updated_at(&mut transaction).await?;
+
+    let deck = sqlx::query_as!(
+        Deck,
+        "SELECT * FROM decks WHERE id = $1 AND user_id = $2",
+        deck_id,
+        user_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+
+    let deck = match deck {
+        Some(deck) => deck,
+        None => {
+            transaction.rollback().await?;
+            return Ok(None);
+        }
+    };
+
+    let (from_language, to_language_primary) = if swap_languages {
+        (deck.to_language_primary, deck.from_language)
+    } else {
+        (deck.from_language, deck.to_language_primary)
+    };
+
+    let new_deck = sqlx::query_as!(
+        Deck,
+        "INSERT INTO decks (user_id, from_language, to_language_primary, to_language_secondary, design_key) VALUES ($1, $2, $3, $4, $5) RETURNING *",
+        user_id,
+        from_language,
+        to_language_primary,
+        deck.to_language_secondary,
+        deck.design_key,
+    )
+        .fetch_one(&mut *transaction)
+        .await?;
+
+    let mut cards = sqlx::query_as!(
+        Card,
+        "SELECT * FROM cards WHERE deck_id = $1 ORDER BY id",
+        deck_id
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+
+    let now = chrono::Utc::now().naive_utc();
+
+    for card in &mut cards {
+        if swap_languages {
+            std::mem::swap(&mut card.from_text, &mut card.to_text_primary);
+        }
+
+        if reset_progress {
+            card.seen_at = now;
+            card.seen_for = None;
+            card.rating = 0;
+            card.prev_rating = 0;
+            card.created_at = now;
+            card.updated_at = now;
+        }
+    }
+
+    insert_card_copies(&mut transaction, &cards, new_deck.id).await?;
+
+    transaction.commit().await?;
+
+    Ok(Some(new_deck))
+}
+
+pub async fn read_card_relations_query(
+    pool: &Pool<Postgres>,
+    card_id: i32,
+) -> Result<Vec<RelatedCard>, Error> {
+    let relations = sqlx::query_as!(
+        CardRelation,
+        "SELECT card_id, related_card_id, relation_type FROM card_relations WHERE card_id = $1 OR related_card_id = $1",
+        card_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut relation_types: HashMap<i32, Option<String>> = relations
+        .into_iter()
+        .map(|relation| {
+            if relation.card_id == card_id {
+                (relation.related_card_id, relation.relation_type)
+            } else {
+                (relation.card_id, relation.relation_type)
+            }
+        })
+        .collect();
+
+    let related_card_ids: Vec<i32> = relation_types.keys().copied().collect();
+
+    let cards = sqlx::query_as!(
+        Card,
+        "SELECT * FROM cards WHERE id = ANY($1) ORDER BY id",
+        &related_card_ids
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(cards
+        .into_iter()
+        .map(|card| RelatedCard {
+            relation_type: relation_types.remove(&card.id).flatten(),
+            card,
+        })
+        .collect())
+}
+
+pub async fn read_user_card_query(
+    pool: &Pool<Postgres>,
+    card_id: i32,
+    user_id: i32,
+) -> Result<Option<Card>, Error> {
+    sqlx::query_as!(
+        Card,
+        "SELECT cards.* FROM cards JOIN decks ON decks.id = cards.deck_id WHERE cards.id = $1 AND decks.user_id = $2",
+        card_id,
+        user_id
+    )
+        .fetch_optional(pool)
+        .await
+}
+
+pub async fn create_card_relation_query(
+    pool: &Pool<Postgres>,
+    card_id: i32,
+    card_relation_form: CardRelationForm,
+) -> Result<DatabaseQueryResult, Error> {
+    let result = sqlx::query!(
+        "INSERT INTO card_relations (card_id, related_card_id, relation_type) VALUES (LEAST($1::INT, $2::INT), GREATEST($1::INT, $2::INT), $3) ON CONFLICT (card_id, related_card_id) DO UPDATE SET relation_type = EXCLUDED.relation_type",
+        card_id,
+        card_relation_form.related_card_id,
+        card_relation_form.relation_type,
+    )
+        .execute(pool)
+        .await;
+
+    match result {
+        Ok(pg_query_result) => Ok(DatabaseQueryResult {
+            rows_affected: pg_query_result.rows_affected(),
+        }),
+        Err(err) => Err(err),
+    }
+}
+
+pub async fn delete_card_relation_query(
+    pool: &Pool<Postgres>,
+    card_id: i32,
+    related_card_id: i32,
+) -> Result<DatabaseQueryResult, Error> {
+    let result = sqlx::query!(
+        "DELETE FROM card_relations WHERE card_id = LEAST($1::INT, $2::INT) AND related_card_id = GREATEST($1::INT, $2::INT)",
+        card_id,
+        related_card_id
+    )
+        .execute(pool)
+        .await;
+
+    match result {
+        Ok(pg_query_result) => Ok(DatabaseQueryResult {
+            rows_affected: pg_query_result.rows_affected(),
+        }),
+        Err(err) => Err(err),
+    }
+}
+
+pub async fn read_webhooks_query(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+) -> Result<Vec<Webhook>, Error> {
+    sqlx::query_as!(
+        Webhook,
+        "SELECT * FROM webhooks WHERE user_id = $1 ORDER BY id",
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn read_webhook_query(
+    pool: &Pool<Postgres>,
+    webhook_id: i32,
+    user_id: i32,
+) -> Result<Option<Webhook>, Error> {
+    sqlx::query_as!(
+        Webhook,
+        "SELECT * FROM webhooks WHERE id = $1 AND user_id = $2",
+        webhook_id,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+fn split_event_types(event_types: String) -> Vec<String> {
+    event_types
+        .split(',')
+        .map(str::trim)
+        .filter(|event_type| !event_type.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+pub async fn create_webhook_query(
+    pool: &Pool<Postgres>,
+    webhook_form: WebhookForm,
+    user_id: i32,
+) -> Result<Webhook, Error> {
+    if webhook_form.url.is_none() {
+        return Err(Error::RowNotFound);
+    }
+
+    let secret = webhook_form.secret.unwrap_or_else(generate_secret);
+    let event_types = split_event_types(webhook_form.event_types.unwrap_or_default());
+
+    sqlx::query_as!(
+        Webhook,
+        "INSERT INTO webhooks (user_id, url, secret, event_types, active) VALUES ($1, $2, $3, $4, $5) RETURNING *",
+        user_id,
+        webhook_form.url,
+        secret,
+        &event_types,
+        webhook_form.active.unwrap_or(true),
+    )
+        .fetch_one(pool)
+        .await
+}
+
+pub async fn update_webhook_query(
+    pool: &Pool<Postgres>,
+    webhook_id: i32,
+    webhook_form: WebhookForm,
+    user_id: i32,
+) -> Result<DatabaseQueryResult, Error> {
+    let mut query = QueryBuilder::new("UPDATE webhooks SET");
+
+    let mut num_updates = 0;
+
+    if let Some(url) = webhook_form.url {
+        if num_updates > 0 {
+            query.push(",");
+        }
+        query.push(" url =");
+        query.push_bind(url);
+        num_updates += 1;
+    }
+
+    if let Some(secret) = webhook_form.secret {
+        if num_updates > 0 {
+            query.push(",");
+        }
+        query.push(" secret =");
+        query.push_bind(secret);
+        num_updates += 1;
+    }
+
+    if let Some(event_types) = webhook_form.event_types {
+        if num_updates > 0 {
+            query.push(",");
+        }
+        query.push(" event_types =");
+        query.push_bind(split_event_types(event_types));
+        num_updates += 1;
+    }
+
+    if let Some(active) = webhook_form.active {
+        if num_updates > 0 {
+            query.push(",");
+        }
+        query.push(" active =");
+        query.push_bind(active);
+        num_updates += 1;
+    }
+
+    if num_updates == 0 {
+        return Err(Error::RowNotFound);
+    }
+
+    query.push(" WHERE id =");
+    query.push_bind(webhook_id);
+
+    query.push(" AND user_id =");
+    query.push_bind(user_id);
+
+    let result = query.build().execute(pool).await;
+
+    match result {
+        Ok(pg_query_result) => Ok(DatabaseQueryResult {
+            rows_affected: pg_query_result.rows_affected(),
+        }),
+        Err(err) => Err(err),
+    }
+}
+
+pub async fn delete_webhook_query(
+    pool: &Pool<Postgres>,
+    webhook_id: i32,
+    user_id: i32,
+) -> Result<DatabaseQueryResult, Error> {
+    let result = sqlx::query!(
+        "DELETE FROM webhooks WHERE id = $1 AND user_id = $2",
+        webhook_id,
+        user_id
+    )
+    .execute(pool)
+    .await;
+
+    match result {
+        Ok(pg_query_result) => Ok(DatabaseQueryResult {
+            rows_affected: pg_query_result.rows_affected(),
+        }),
+        Err(err) => Err(err),
+    }
+}
+
+pub async fn read_webhook_deliveries_query(
+    pool: &Pool<Postgres>,
+    webhook_id: i32,
+) -> Result<Vec<WebhookDelivery>, Error> {
+    sqlx::query_as!(
+        WebhookDelivery,
+        "SELECT * FROM webhook_deliveries WHERE webhook_id = $1 ORDER BY id DESC LIMIT 100",
+        webhook_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+// queues a delivery for every active webhook of the user that listens to the event type
+pub async fn create_webhook_deliveries_query(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+    event_type: &str,
+    payload: Value,
+) -> Result<DatabaseQueryResult, Error> {
+    let result = sqlx::query!(
+        "INSERT INTO webhook_deliveries (webhook_id, event_type, payload) SELECT id, $2::VARCHAR, $3 FROM webhooks WHERE user_id = $1 AND active AND (cardinality(event_types) = 0 OR $2::VARCHAR = ANY(event_types))",
+        user_id,
+        event_type,
+        payload,
+    )
+        .execute(pool)
+        .await;
+
+    match result {
+        Ok(pg_query_result) => Ok(DatabaseQueryResult {
+            rows_affected: pg_query_result.rows_affected(),
+        }),
+        Err(err) => Err(err),
+    }
+}
+
+pub struct PendingWebhookDelivery {
+    pub id: i32,
+    pub url: String,
+    pub secret: String,
+    pub event_type: String,
+    pub payload: Value,
+    pub attempts: i32,
+}
+
+// leases due deliveries so that a crashed attempt is picked up again after a while
+pub async fn claim_webhook_deliveries_query(
+    pool: &Pool<Postgres>,
+    limit: i64,
+) -> Result<Vec<PendingWebhookDelivery>, Error> {
+    sqlx::query_as!(
+        PendingWebhookDelivery,
+        r#"WITH claimed AS (
+            UPDATE webhook_deliveries
+            SET next_attempt_at = CURRENT_TIMESTAMP + INTERVAL '5 minutes'
+            WHERE id IN (
+                SELECT id FROM webhook_deliveries
+                WHERE status = 'pending' AND next_attempt_at <= CURRENT_TIMESTAMP
+                AND webhook_id IN (SELECT id FROM webhooks WHERE active)
+                ORDER BY next_attempt_at
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, webhook_id, event_type, payload, attempts
+        )
+        SELECT claimed.id AS "id!", webhooks.url AS "url!", webhooks.secret AS "secret!", claimed.event_type AS "event_type!", claimed.payload AS "payload!", claimed.attempts AS "attempts!"
+        FROM claimed
+        JOIN webhooks ON webhooks.id = claimed.webhook_id"#,
+        limit
+    )
+        .fetch_all(pool)
+        .await
+}
+
+pub async fn update_webhook_delivery_query(
+    pool: &Pool<Postgres>,
+    delivery_id: i32,
+    status: &str,
+    retry_in_seconds: f64,
+    response_status: Option<i32>,
+    last_error: Option<String>,
+) -> Result<DatabaseQueryResult, Error> {
+    let result = sqlx::query!(
+        "UPDATE webhook_deliveries SET status = $2, attempts = attempts + 1, next_attempt_at = CURRENT_TIMESTAMP + make_interval(secs => $3), response_status = $4, last_error = $5 WHERE id = $1",
+        delivery_id,
+        status,
+        retry_in_seconds,
+        response_status,
+        last_error,
+    )
+        .execute(pool)
+        .await;
+
+    match result {
+        Ok(pg_query_result) => Ok(DatabaseQueryResult {
+            rows_affected: pg_query_result.rows_affected(),
+        }),
+        Err(err) => Err(err),
+    }
+}
+
+// the cursor is the oldest transaction still running when the snapshot was taken, so the next pull
+// also picks up rows from transactions that committed after this one read
+
+pub async fn read_sync_changes_query(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+    since: i64,
+) -> Result<SyncPull, Error> {
+    let mut transaction = pool.begin().await?;
+
+    sqlx::query!("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ")
+        .execute(&mut *transaction)
+        .await?;
+
+    let cursor = sqlx::query_scalar!(
+        r#"SELECT pg_snapshot_xmin(pg_current_snapshot())::TEXT::BIGINT AS "cursor!""#
+    )
+    .fetch_one(&mut *transaction)
+    .await?;
+
+    let changes = sqlx::query_as!(
+        SyncChangeRow,
+        "SELECT entity_type, entity_id, deleted FROM sync_changes WHERE user_id = $1 AND transaction_id >= $2",
+        user_id,
+        since,
+    )
+        .fetch_all(&mut *transaction)
+        .await?;
+
+    let mut changed = SyncDeleted::default();
+    let mut deleted = SyncDeleted::default();
+
+    for change in changes {
+        let ids = if change.deleted {
+            &mut deleted
+        } else {
+            &mut changed
+        };
+
+        match change.entity_type.as_str() {
+            "decks" => ids.decks.push(change.entity_id),
+            "cards" => ids.cards.push(change.entity_id),
+            "reviews" => ids.reviews.push(change.entity_id),
+            _ => {}
+        }
+    }
+
+    let decks = sqlx::query_as!(
+        Deck,
+        "SELECT * FROM decks WHERE id = ANY($1) AND user_id = $2 ORDER BY id",
+        &changed.decks,
+        user_id,
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+
+    let cards = sqlx::query_as!(
+        Card,
+        "SELECT * FROM cards WHERE id = ANY($1) AND deck_id IN (SELECT id FROM decks WHERE user_id = $2) ORDER BY id",
+        &changed.cards,
+        user_id,
+    )
+        .fetch_all(&mut *transaction)
+        .await?;
+
+    let card_tags = sqlx::query!(
+        "SELECT card_tags.card_id, tags.name FROM card_tags JOIN tags ON tags.id = card_tags.tag_id WHERE card_tags.card_id = ANY($1) ORDER BY tags.name",
+        &changed.cards,
+    )
+        .fetch_all(&mut *transaction)
+        .await?;
+
+    let mut tags: HashMap<i32, Vec<String>> = HashMap::new();
+
+    for row in card_tags {
+        tags.entry(row.card_id).or_default().push(row.name);
+    }
+
+    let cards = cards
+        .into_iter()
+        .map(|card| SyncCard {
+            tags: tags.remove(&card.id).unwrap_or_default(),
+            card,
+        })
+        .collect();
+
+    let reviews = sqlx::query_as!(
+        Review,
+        "SELECT * FROM reviews WHERE id = ANY($1) ORDER BY id",
+        &changed.reviews,
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(SyncPull {
+        cursor,
+        decks,
+        cards,
+        reviews,
+        deleted,
+    })
+}
+
+// client changes are written with their own timestamps
+pub async fn begin_sync_transaction(
+    pool: &Pool<Postgres>,
+) -> Result<Transaction<'static, Postgres>, Error> {
+    let mut transaction = pool.begin().await?;
+
+    preserve_updated_at(&mut transaction).await?;
+
+    Ok(transaction)
+}
+
+pub async fn read_sync_deck_query(
+    transaction: &mut Transaction<'_, Postgres>,
+    deck_id: i32,
+    user_id: i32,
+) -> Result<Option<Deck>, Error> {
+    sqlx::query_as!(
+        Deck,
+        "SELECT * FROM decks WHERE id = $1 AND user_id = $2 FOR UPDATE",
+        deck_id,
+        user_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+}
+
+pub async fn read_sync_card_query(
+    transaction: &mut Transaction<'_, Postgres>,
+    card_id: i32,
+    user_id: i32,
+) -> Result<Option<Card>, Error> {
+    sqlx::query_as!(
+        Card,
+        "SELECT * FROM cards WHERE id = $1 AND deck_id IN (SELECT id FROM decks WHERE user_id = $2) FOR UPDATE",
+        card_id,
+        user_id
+    )
+        .fetch_optional(&mut **transaction)
+        .await
+}
+
+pub async fn read_sync_deleted_query(
+    transaction: &mut Transaction<'_, Postgres>,
+    entity_type: &str,
+    entity_id: i32,
+    user_id: i32,
+) -> Result<bool, Error> {
+    let deleted = sqlx::query_scalar!(
+        "SELECT deleted FROM sync_changes WHERE entity_type = $1 AND entity_id = $2 AND user_id = $3",
+        entity_type,
+        entity_id,
+        user_id
+    )
+        .fetch_optional(&mut **transaction)
+        .await?;
+
+    Ok(deleted.unwrap_or(false))
+}
+
+pub async fn create_sync_deck_query(
+    transaction: &mut Transaction<'_, Postgres>,
+    deck_form: DeckForm,
+    user_id: i32,
+    updated_at: NaiveDateTime,
+) -> Result<Deck, Error> {
+    sqlx::query_as!(
+        Deck,
+        "INSERT INTO decks (user_id, from_language, to_language_primary, to_language_secondary, design_key, seen_at, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, COALESCE($6::TIMESTAMP, $7), $7, $7) RETURNING *",
+        user_id,
+        deck_form.from_language,
+        deck_form.to_language_primary,
+        deck_form.to_language_secondary,
+        deck_form.design_key,
+        deck_form.seen_at,
+        updated_at,
+    )
+        .fetch_one(&mut **transaction)
+        .await
+}
+
+pub async fn update_sync_deck_query(
+    transaction: &mut Transaction<'_, Postgres>,
+    deck_id: i32,
+    deck_form: DeckForm,
+    updated_at: NaiveDateTime,
+) -> Result<Deck, Error> {
+    let mut query = QueryBuilder::new("UPDATE decks SET");
+
+    let num_updates = push_deck_form_updates(&mut query, deck_form);
+
+    if num_updates > 0 {
+        query.push(",");
+    }
+    query.push(" updated_at =");
+    query.push_bind(updated_at);
+
+    query.push(" WHERE id =");
+    query.push_bind(deck_id);
+
+    query.build().execute(&mut **transaction).await?;
+
+    sqlx::query_as!(Deck, "SELECT * FROM decks WHERE id = $1", deck_id)
+        .fetch_one(&mut **transaction)
+        .await
+}
+
+// decks that still have cards are kept and false is returned
+pub async fn delete_sync_deck_query(
+    transaction: &mut Transaction<'_, Postgres>,
+    deck_id: i32,
+) -> Result<bool, Error> {
+    let has_cards = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM cards WHERE deck_id = $1) AS "exists!""#,
+        deck_id
+    )
+    .fetch_one(&mut **transaction)
+    .await?;
+
+    if has_cards {
+        return Ok(false);
+    }
+
+    sqlx::query!("DELETE FROM decks WHERE id = $1", deck_id)
+        .execute(&mut **transaction)
+        .await?;
+
+    Ok(true)
+}
+
+pub async fn create_sync_card_query(
+    transaction: &mut Transaction<'_, Postgres>,
+    deck_id: i32,
+    card_form: CardForm,
+    updated_at: NaiveDateTime,
+) -> Result<Card, Error> {
+    let card = sqlx::query_as!(
+        Card,
+        "INSERT INTO cards (deck_id, from_text, to_text_primary, to_text_secondary, example_text, audio_url, seen_at, seen_for, rating, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, COALESCE($7::TIMESTAMP, $10), $8, COALESCE($9, 0), $10, $10) RETURNING *",
+        deck_id,
+        card_form.from_text,
+        card_form.to_text_primary,
+        card_form.to_text_secondary,
+        card_form.example_text,
+        card_form.audio_url,
+        card_form.seen_at,
+        card_form.seen_for,
+        card_form.rating,
+        updated_at,
+    )
+        .fetch_one(&mut **transaction)
+        .await?;
+
+    if let Some(tags) = card_form.tags {
+        update_card_tags(transaction, card.id, &split_tag_names(&tags)).await?;
+    }
+
+    Ok(card)
+}
+
+pub async fn update_sync_card_query(
+    transaction: &mut Transaction<'_, Postgres>,
+    card_id: i32,
+    card_form: CardForm,
+    updated_at: NaiveDateTime,
+) -> Result<Card, Error> {
+    let mut query = QueryBuilder::new("UPDATE cards SET");
+
+    let tags = card_form.tags.clone();
+
+    let num_updates = push_card_form_updates(&mut query, card_form);
+
+    if num_updates > 0 {
+        query.push(",");
+    }
+    query.push(" updated_at =");
+    query.push_bind(updated_at);
+
+    query.push(" WHERE id =");
+    query.push_bind(card_id);
+
+    query.build().execute(&mut **transaction).await?;
+
+    if let Some(tags) = tags {
+        update_card_tags(transaction, card_id, &split_tag_names(&tags)).await?;
+    }
+
+    sqlx::query_as!(Card, "SELECT * FROM cards WHERE id = $1", card_id)
+        .fetch_one(&mut **transaction)
+        .await
+}
+
+pub async fn delete_sync_card_query(
+    transaction: &mut Transaction<'_, Postgres>,
+    card_id: i32,
+) -> Result<(), Error> {
+    sqlx::query!("DELETE FROM cards WHERE id = $1", card_id)
+        .execute(&mut **transaction)
+        .await?;
+
+    Ok(())
+}
+
+pub fn split_tag_names(tags: &str) -> Vec<String> {
+    tags.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+// replaces the tags of a card, tags that do not exist yet are created for the owner of the card
+async fn update_card_tags(
+    transaction: &mut Transaction<'_, Postgres>,
+    card_id: i32,
+    names: &[String],
+) -> Result<(), Error> {
+    sqlx::query!(
+        "DELETE FROM card_tags USING tags WHERE tags.id = card_tags.tag_id AND card_tags.card_id = $1 AND NOT (tags.name = ANY($2))",
+        card_id,
+        names
+    )
+        .execute(&mut **transaction)
+        .await?;
+
+    add_card_tags(transaction, card_id, names).await
+}
+
+// adds tags to a card and keeps the ones it has
+async fn add_card_tags(
+    transaction: &mut Transaction<'_, Postgres>,
+    card_id: i32,
+    names: &[String],
+) -> Result<(), Error> {
+    sqlx::query!(
+        "INSERT INTO tags (user_id, name) SELECT decks.user_id, UNNEST($2::VARCHAR[]) FROM cards JOIN decks ON decks.id = cards.deck_id WHERE cards.id = $1 ON CONFLICT (user_id, name) DO NOTHING",
+        card_id,
+        names
+    )
+        .execute(&mut **transaction)
+        .await?;
+
+    sqlx::query!(
+        "INSERT INTO card_tags (card_id, tag_id) SELECT cards.id, tags.id FROM cards JOIN decks ON decks.id = cards.deck_id JOIN tags ON tags.user_id = decks.user_id WHERE cards.id = $1 AND tags.name = ANY($2) ON CONFLICT DO NOTHING",
+        card_id,
+        names
+    )
+        .execute(&mut **transaction)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn read_card_tags_query(pool: &Pool<Postgres>, card_id: i32) -> Result<Vec<Tag>, Error> {
+    sqlx::query_as!(
+        Tag,
+        "SELECT tags.* FROM tags JOIN card_tags ON card_tags.tag_id = tags.id WHERE card_tags.card_id = $1 ORDER BY tags.name",
+        card_id
+    )
+        .fetch_all(pool)
+        .await
+}
+
+// tag names of every tagged card of a deck, used where the cards are read in bulk
+pub async fn read_deck_card_tags_query(
+    pool: &Pool<Postgres>,
+    deck_id: i32,
+) -> Result<HashMap<i32, Vec<String>>, Error> {
+    let rows = sqlx::query!(
+        "SELECT card_tags.card_id, tags.name FROM card_tags JOIN tags ON tags.id = card_tags.tag_id JOIN cards ON cards.id = card_tags.card_id WHERE cards.deck_id = $1 ORDER BY tags.name",
+        deck_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut card_tags: HashMap<i32, Vec<String>> = HashMap::new();
+
+    for row in rows {
+        card_tags.entry(row.card_id).or_default().push(row.name);
+    }
+
+    Ok(card_tags)
+}
+
+// the front texts of the cards of a deck as an import compares them, with the key of duplicates
+// or of words
+pub async fn read_deck_texts_query(
+    pool: &Pool<Postgres>,
+    deck: &Deck,
+    key: fn(&str, &str, bool) -> String,
+) -> Result<HashSet<String>, Error> {
+    let texts = sqlx::query_scalar!("SELECT from_text FROM cards WHERE deck_id = $1", deck.id)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(texts
+        .iter()
+        .map(|text| key(text, &deck.from_language, true))
+        .collect())
+}
+
+pub async fn read_tags_query(pool: &Pool<Postgres>, user_id: i32) -> Result<Vec<Tag>, Error> {
+    sqlx::query_as!(
+        Tag,
+        "SELECT * FROM tags WHERE user_id = $1 ORDER BY name",
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn read_tag_query(
+    pool: &Pool<Postgres>,
+    tag_id: i32,
+    user_id: i32,
+) -> Result<Option<Tag>, Error> {
+    sqlx::query_as!(
+        Tag,
+        "SELECT * FROM tags WHERE id = $1 AND user_id = $2",
+        tag_id,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn create_tag_query(
+    pool: &Pool<Postgres>,
+    tag_form: TagForm,
+    user_id: i32,
+) -> Result<Tag, Error> {
+    if tag_form.name.is_none() {
+        return Err(Error::RowNotFound);
+    }
+
+    sqlx::query_as!(
+        Tag,
+        "INSERT INTO tags (user_id, name) VALUES ($1, $2) RETURNING *",
+        user_id,
+        tag_form.name,
+    )
+    .fetch_one(pool)
+    .await
+}
+
+// the cards that carry a tag of the user
+pub async fn read_tag_cards_query(
+    pool: &Pool<Postgres>,
+    tag_id: i32,
+    user_id: i32,
+) -> Result<Vec<Card>, Error> {
+    sqlx::query_as!(
+        Card,
+        "SELECT cards.* FROM cards JOIN card_tags ON card_tags.card_id = cards.id JOIN tags ON tags.id = card_tags.tag_id WHERE tags.id = $1 AND tags.user_id = $2 ORDER BY cards.id",
+        tag_id,
+        user_id
+    )
+        .fetch_all(pool)
+        .await
+}
+
+pub async fn update_tag_query(
+    pool: &Pool<Postgres>,
+    tag_id: i32,
+    tag_form: TagForm,
+    user_id: i32,
+) -> Result<DatabaseQueryResult, Error> {
+    if tag_form.name.is_none() {
+        return Err(Error::RowNotFound);
+    }
+
+    let result = sqlx::query!(
+        "UPDATE tags SET name = $1 WHERE id = $2 AND user_id = $3",
+        tag_form.name,
+        tag_id,
+        user_id
+    )
+    .execute(pool)
+    .await;
+
+    match result {
+        Ok(pg_query_result) => Ok(DatabaseQueryResult {
+            rows_affected: pg_query_result.rows_affected(),
+        }),
+        Err(err) => Err(err),
+    }
+}
+
+pub async fn delete_tag_query(
+    pool: &Pool<Postgres>,
+    tag_id: i32,
+    user_id: i32,
+) -> Result<DatabaseQueryResult, Error> {
+    let result = sqlx::query!(
+        "DELETE FROM tags WHERE id = $1 AND user_id = $2",
+        tag_id,
+        user_id
+    )
+    .execute(pool)
+    .await;
+
+    match result {
+        Ok(pg_query_result) => Ok(DatabaseQueryResult {
+            rows_affected: pg_query_result.rows_affected(),
+        }),
+        Err(err) => Err(err),
+    }
+}
+
+// inserts the cards of an import, or merges them into cards of the deck with the same text, and
+// returns the inserted and the merged cards
+async fn insert_import_cards(
+    transaction: &mut Transaction<'_, Postgres>,
+    deck: &Deck,
+    import_deck: ImportDeck,
+    audio_urls: &HashMap<String, String>,
+    duplicates: &str,
+    ignore_articles: bool,
+) -> Result<(Vec<Card>, Vec<Card>), Error> {
+    let deck_cards = sqlx::query_as!(
+        Card,
+        "SELECT * FROM cards WHERE deck_id = $1 ORDER BY id",
+        deck.id
+    )
+    .fetch_all(&mut **transaction)
+    .await?;
+
+    let resolutions = resolve_duplicates(
+        import_deck.cards,
+        &deck_cards,
+        &deck.from_language,
+        duplicates,
+        ignore_articles,
+    );
+
+    let mut cards = Vec::new();
+    let mut merged_cards = Vec::new();
+
+    for resolution in resolutions {
+        match resolution {
+            DuplicateResolution::Insert(import_card) => {
+                let audio_url = import_card
+                    .audio_file
+                    .and_then(|audio_file| audio_urls.get(&audio_file).cloned())
+                    .or(import_card.audio_url);
+
+                let card = sqlx::query_as!(
+                    Card,
+                    "INSERT INTO cards (deck_id, from_text, to_text_primary, to_text_secondary, example_text, audio_url) VALUES ($1, $2, $3, $4, $5, $6) RETURNING *",
+                    deck.id,
+                    import_card.from_text,
+                    import_card.to_text_primary,
+                    import_card.to_text_secondary,
+                    import_card.example_text,
+                    audio_url,
+                )
+                    .fetch_one(&mut **transaction)
+                    .await?;
+
+                if !import_card.tags.is_empty() {
+                    update_card_tags(transaction, card.id, &import_card.tags).await?;
+                }
+
+                cards.push(card);
+            }
+            DuplicateResolution::Merge(card_id, import_card) => {
+                let audio_url = import_card
+                    .audio_file
+                    .and_then(|audio_file| audio_urls.get(&audio_file).cloned())
+                    .or(import_card.audio_url);
+
+                // only blank fields are filled, the texts of the card stay as they are
+                let card = sqlx::query_as!(
+                    Card,
+                    "UPDATE cards SET to_text_secondary = COALESCE(to_text_secondary, $2), example_text = COALESCE(example_text, $3), audio_url = COALESCE(audio_url, $4) WHERE id = $1 RETURNING *",
+                    card_id,
+                    import_card.to_text_secondary,
+                    import_card.example_text,
+                    audio_url,
+                )
+                    .fetch_one(&mut **transaction)
+                    .await?;
+
+                if !import_card.tags.is_empty() {
+                    add_card_tags(transaction, card.id, &import_card.tags).await?;
+                }
+
+                merged_cards.push(card);
+            }
+        }
+    }
+
+    Ok((cards, merged_cards))
+}
+
+// creates a deck per imported deck in one transaction, the forms carry the validated languages
+pub async fn create_import_query(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+    import_decks: Vec<(DeckForm, ImportDeck)>,
+    audio_urls: &HashMap<String, String>,
+    duplicates: &str,
+    ignore_articles: bool,
+) -> Result<Vec<DeckOverview>, Error> {
+    let mut transaction = pool.begin().await?;
+
+    let mut deck_overviews = Vec::new();
+
+    for (deck_form, import_deck) in import_decks {
+        let deck = sqlx::query_as!(
+            Deck,
+            "INSERT INTO decks (user_id, from_language, to_language_primary, to_language_secondary, design_key) VALUES ($1, $2, $3, $4, $5) RETURNING *",
+            user_id,
+            deck_form.from_language,
+            deck_form.to_language_primary,
+            deck_form.to_language_secondary,
+            deck_form.design_key,
+        )
+            .fetch_one(&mut *transaction)
+            .await?;
+
+        // a new deck has nothing to merge with, only the cards of the import among themselves
+        let (cards, _) = insert_import_cards(
+            &mut transaction,
+            &deck,
+            import_deck,
+            audio_urls,
+            duplicates,
+            ignore_articles,
+        )
+        .await?;
+
+        deck_overviews.push(DeckOverview {
+            deck,
+            cards: Some(cards),
+            stats: None,
+        });
+    }
+
+    transaction.commit().await?;
+
+    Ok(deck_overviews)
+}
+
+// adds the cards of an import to existing decks in one transaction, returns the decks with the
+// inserted cards and the cards that were merged into
+pub async fn create_deck_import_query(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+    import_decks: Vec<(i32, ImportDeck)>,
+    audio_urls: &HashMap<String, String>,
+    duplicates: &str,
+    ignore_articles: bool,
+) -> Result<(Vec<DeckOverview>, Vec<Card>), Error> {
+    let mut transaction = pool.begin().await?;
+
+    let mut deck_overviews: Vec<DeckOverview> = Vec::new();
+    let mut merged_cards = Vec::new();
+
+    for (deck_id, import_deck) in import_decks {
+        // locked, so that the deck is not deleted while the cards go in
+        let deck = sqlx::query_as!(
+            Deck,
+            "SELECT * FROM decks WHERE id = $1 AND user_id = $2 FOR UPDATE",
+            deck_id,
+            user_id
+        )
+        .fetch_optional(&mut *transaction)
+        .await?
+        .ok_or(Error::RowNotFound)?;
+
+        let (cards, deck_merged_cards) = insert_import_cards(
+            &mut transaction,
+            &deck,
+            import_deck,
+            audio_urls,
+            duplicates,
+            ignore_articles,
+        )
+        .await?;
+
+        merged_cards.extend(deck_merged_cards);
+
+        // several imported decks can go into the same deck
+        match deck_overviews
+            .iter_mut()
+            .find(|deck_overview| deck_overview.deck.id == deck.id)
+        {
+            Some(deck_overview) => deck_overview
+                .cards
+                .get_or_insert_with(Vec::new)
+                .extend(cards),
+            None => deck_overviews.push(DeckOverview {
+                deck,
+                cards: Some(cards),
+                stats: None,
+            }),
+        }
+    }
+
+    transaction.commit().await?;
+
+    Ok((deck_overviews, merged_cards))
+}
+
+// reads the decks of the user, or just the one deck, with their cards, relations and reviews,
+// relations to cards of other decks are left out of a single deck
+pub async fn read_bundle_records_query(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+    deck_id: Option<i32>,
+) -> Result<Option<BundleRecords>, Error> {
+    let mut transaction = pool.begin().await?;
+
+    sqlx::query!("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ")
+        .execute(&mut *transaction)
+        .await?;
+
+    let decks = sqlx::query_as!(
+        Deck,
+        "SELECT * FROM decks WHERE user_id = $1 AND ($2::INTEGER IS NULL OR id = $2) ORDER BY id",
+        user_id,
+        deck_id
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+
+    if deck_id.is_some() && decks.is_empty() {
+        return Ok(None);
+    }
+
+    let deck_ids: Vec<i32> = decks.iter().map(|deck| deck.id).collect();
+
+    let cards = sqlx::query_as!(
+        Card,
+        "SELECT * FROM cards WHERE deck_id = ANY($1) ORDER BY id",
+        &deck_ids
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+
+    let tag_rows = sqlx::query!(
+        "SELECT card_tags.card_id, tags.name FROM card_tags JOIN tags ON tags.id = card_tags.tag_id JOIN cards ON cards.id = card_tags.card_id WHERE cards.deck_id = ANY($1) ORDER BY tags.name",
+        &deck_ids
+    )
+        .fetch_all(&mut *transaction)
+        .await?;
+
+    let mut card_tags: HashMap<i32, Vec<String>> = HashMap::new();
+
+    for row in tag_rows {
+        card_tags.entry(row.card_id).or_default().push(row.name);
+    }
+
+    let relations = sqlx::query_as!(
+        CardRelationRecord,
+        "SELECT card_relations.card_id, card_relations.related_card_id, card_relations.relation_type, card_relations.created_at FROM card_relations JOIN cards ON cards.id = card_relations.card_id JOIN cards AS related_cards ON related_cards.id = card_relations.related_card_id WHERE cards.deck_id = ANY($1) AND related_cards.deck_id = ANY($1) ORDER BY card_relations.card_id, card_relations.related_card_id",
+        &deck_ids
+    )
+        .fetch_all(&mut *transaction)
+        .await?;
+
+    let reviews = sqlx::query_as!(
+        Review,
+        "SELECT reviews.* FROM reviews JOIN cards ON cards.id = reviews.card_id WHERE cards.deck_id = ANY($1) ORDER BY reviews.id",
+        &deck_ids
+    )
+        .fetch_all(&mut *transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(Some(BundleRecords {
+        decks,
+        cards,
+        card_tags,
+        relations,
+        reviews,
+    }))
+}
+
+// restores a checked bundle as new decks in one transaction, the ids of the bundle are mapped to
+// new ones and the timestamps are kept as they were
+pub async fn create_bundle_query(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+    bundle: &Bundle,
+    audio_urls: &HashMap<String, String>,
+) -> Result<Vec<DeckOverview>, Error> {
+    let mut transaction = pool.begin().await?;
+
+    preserve_updated_at(&mut transaction).await?;
+
+    let mut deck_ids: HashMap<i32, i32> = HashMap::new();
+    let mut decks = Vec::new();
+
+    for bundle_deck in &bundle.decks {
+        let deck = sqlx::query_as!(
+            Deck,
+            "INSERT INTO decks (user_id, from_language, to_language_primary, to_language_secondary, design_key, seen_at, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING *",
+            user_id,
+            bundle_deck.from_language,
+            bundle_deck.to_language_primary,
+            bundle_deck.to_language_secondary,
+            bundle_deck.design_key,
+            bundle_deck.seen_at,
+            bundle_deck.created_at,
+            bundle_deck.updated_at,
+        )
+            .fetch_one(&mut *transaction)
+            .await?;
+
+        deck_ids.insert(bundle_deck.id, deck.id);
+        decks.push(deck);
+    }
+
+    let mut card_ids: HashMap<i32, i32> = HashMap::new();
+
+    for bundle_card in &bundle.cards {
+        let audio_url = bundle_card
+            .audio_file
+            .as_ref()
+            .and_then(|audio_file| audio_urls.get(audio_file).cloned())
+            .or(bundle_card.audio_url.clone());
+
+        let card_id = sqlx::query_scalar!(
+            "INSERT INTO cards (deck_id, from_text, to_text_primary, to_text_secondary, example_text, audio_url, seen_at, seen_for, rating, prev_rating, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12) RETURNING id",
+            deck_ids[&bundle_card.deck_id],
+            bundle_card.from_text,
+            bundle_card.to_text_primary,
+            bundle_card.to_text_secondary,
+            bundle_card.example_text,
+            audio_url,
+            bundle_card.seen_at,
+            bundle_card.seen_for,
+            bundle_card.rating,
+            bundle_card.prev_rating,
+            bundle_card.created_at,
+            bundle_card.updated_at,
+        )
+            .fetch_one(&mut *transaction)
+            .await?;
+
+        if !bundle_card.tags.is_empty() {
+            update_card_tags(&mut transaction, card_id, &bundle_card.tags).await?;
+        }
+
+        card_ids.insert(bundle_card.id, card_id);
+    }
+
+    for relation in &bundle.relations {
+        let card_id = card_ids[&relation.card_id];
+        let related_card_id = card_ids[&relation.related_card_id];
+
+        sqlx::query!(
+            "INSERT INTO card_relations (card_id, related_card_id, relation_type, created_at) VALUES (LEAST($1::INTEGER, $2::INTEGER), GREATEST($1::INTEGER, $2::INTEGER), $3, $4) ON CONFLICT DO NOTHING",
+            card_id,
+            related_card_id,
+            relation.relation_type,
+            relation.created_at,
+        )
+            .execute(&mut *transaction)
+            .await?;
+    }
+
+    for review in &bundle.reviews {
+        sqlx::query!(
+            "INSERT INTO reviews (card_id, rating, prev_rating, seen_at, seen_for, reviewed_at, created_at) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            card_ids[&review.card_id],
+            review.rating,
+            review.prev_rating,
+            review.seen_at,
+            review.seen_for,
+            review.reviewed_at,
+            review.created_at,
+        )
+            .execute(&mut *transaction)
+            .await?;
+    }
+
+    // read back once the relations are in, they fill related_card_ids
+    let new_deck_ids: Vec<i32> = decks.iter().map(|deck| deck.id).collect();
+
+    let mut cards = sqlx::query_as!(
+        Card,
+        "SELECT * FROM cards WHERE deck_id = ANY($1) ORDER BY id",
+        &new_deck_ids
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    let mut deck_overviews = Vec::new();
+
+    for deck in decks {
+        let (deck_cards, other_cards) = cards.into_iter().partition(|card| card.deck_id == deck.id);
+        cards = other_cards;
+
+        deck_overviews.push(DeckOverview {
+            deck,
+            cards: Some(deck_cards),
+            stats: None,
+        });
+    }
+
+    Ok(deck_overviews)
+}
+
+pub async fn read_card_revisions_query(
+    pool: &Pool<Postgres>,
+    card_id: i32,
+) -> Result<Vec<CardRevision>, Error> {
+    sqlx::query_as!(
+        CardRevision,
+        "SELECT * FROM card_revisions WHERE card_id = $1 ORDER BY id DESC",
+        card_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+// copies the given fields of a revision back onto the card, the replaced texts become a revision
+pub async fn restore_card_revision_query(
+    pool: &Pool<Postgres>,
+    deck_id: i32,
+    card_id: i32,
+    revision_id: i32,
+    fields: Vec<String>,
+) -> Result<Option<Card>, Error> {
+    sqlx::query_as!(
+        Card,
+        r#"UPDATE cards SET
+            from_text = CASE WHEN 'from_text' = ANY($4) THEN card_revisions.from_text ELSE cards.from_text END,
+            to_text_primary = CASE WHEN 'to_text_primary' = ANY($4) THEN card_revisions.to_text_primary ELSE cards.to_text_primary END,
+            to_text_secondary = CASE WHEN 'to_text_secondary' = ANY($4) THEN card_revisions.to_text_secondary ELSE cards.to_text_secondary END,
+            example_text = CASE WHEN 'example_text' = ANY($4) THEN card_revisions.example_text ELSE cards.example_text END
+        FROM card_revisions
+        WHERE card_revisions.id = $1 AND card_revisions.card_id = cards.id AND cards.id = $2 AND cards.deck_id = $3
+        RETURNING cards.*"#,
+        revision_id,
+        card_id,
+        deck_id,
+        &fields,
+    )
+        .fetch_optional(pool)
+        .await
+}
+
+// applies reviews oldest first, so prev_rating and the study order come out as if they had been
+// submitted live. reviews that are not newer than the card's last review are skipped, which also
+// makes resubmitting a batch harmless
+pub async fn create_reviews_query(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+    mut review_forms: Vec<ReviewForm>,
+) -> Result<ReviewBatchResult, Error> {
+    review_forms.sort_by_key(|review_form| review_form.reviewed_at);
+
+    let now = chrono::Utc::now().naive_utc();
+
+    let mut transaction = pool.begin().await?;
+
+    preserve_updated_at(&mut transaction).await?;
+
+    let mut applied = Vec::new();
+    let mut skipped = Vec::new();
+    let mut cards: HashMap<i32, Card> = HashMap::new();
+
+    for review_form in review_forms {
+        let reviewed_at = review_form.reviewed_at.min(now);
+
+        let card = read_sync_card_query(&mut transaction, review_form.card_id, user_id).await?;
+
+        if card.is_none() {
+            skipped.push(SkippedReview {
+                card_id: review_form.card_id,
+                reviewed_at,
+                message: String::from("Card not found"),
+            });
+            continue;
+        }
+
+        let last_reviewed_at = sqlx::query_scalar!(
+            "SELECT MAX(reviewed_at) FROM reviews WHERE card_id = $1",
+            review_form.card_id
+        )
+        .fetch_one(&mut *transaction)
+        .await?;
+
+        if last_reviewed_at.is_some_and(|last_reviewed_at| last_reviewed_at >= reviewed_at) {
+            skipped.push(SkippedReview {
+                card_id: review_form.card_id,
+                reviewed_at,
+                message: String::from("Card was already reviewed at or after this time"),
+            });
+            continue;
+        }
+
+        // edits made after the review keep their later updated_at
+        let card = sqlx::query_as!(
+            Card,
+            "UPDATE cards SET rating = $1, seen_for = COALESCE($2, seen_for), seen_at = $3, updated_at = GREATEST(updated_at, $3) WHERE id = $4 RETURNING *",
+            review_form.rating,
+            review_form.seen_for,
+            reviewed_at,
+            review_form.card_id,
+        )
+            .fetch_one(&mut *transaction)
+            .await?;
+
+        let review = sqlx::query_as!(
+            Review,
+            "SELECT * FROM reviews WHERE card_id = $1 ORDER BY id DESC LIMIT 1",
+            card.id
+        )
+        .fetch_one(&mut *transaction)
+        .await?;
+
+        applied.push(review);
+        cards.insert(card.id, card);
+    }
+
+    transaction.commit().await?;
+
+    Ok(ReviewBatchResult {
+        applied,
+        skipped,
+        cards: cards.into_values().collect(),
+    })
+}
+
+// reverts the card of the deck's last review to its state before that review and drops the
+// review, so repeated undos walk further back. reviews submitted before `since` are left alone
+pub async fn undo_last_review_query(
+    pool: &Pool<Postgres>,
+    deck_id: i32,
+    since: Option<NaiveDateTime>,
+) -> Result<Option<Card>, Error> {
+    let mut transaction = pool.begin().await?;
+
+    preserve_updated_at(&mut transaction).await?;
+
+    sqlx::query!("SELECT set_config('reviews.undo', 'on', true)")
+        .fetch_one(&mut *transaction)
+        .await?;
+
+    let review = sqlx::query_as!(
+        Review,
+        "SELECT reviews.* FROM reviews JOIN cards ON cards.id = reviews.card_id WHERE cards.deck_id = $1 AND ($2::TIMESTAMP IS NULL OR reviews.created_at >= $2) ORDER BY reviews.id DESC LIMIT 1 FOR UPDATE OF reviews",
+        deck_id,
+        since,
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+
+    let review = match review {
+        Some(review) => review,
+        None => return Ok(None),
+    };
+
+    // reviews recorded before the undo state was kept only give back the previous rating
+    let card = sqlx::query_as!(
+        Card,
+        "UPDATE cards SET rating = COALESCE($7::INTEGER, $1), prev_rating = COALESCE($2, prev_rating), seen_at = COALESCE($3, seen_at), seen_for = CASE WHEN $3::TIMESTAMP IS NULL THEN seen_for ELSE $4 END, updated_at = COALESCE($5, updated_at) WHERE id = $6 RETURNING *",
+        review.prev_rating,
+        review.undo_prev_rating,
+        review.undo_seen_at,
+        review.undo_seen_for,
+        review.undo_updated_at,
+        review.card_id,
+        review.undo_rating,
+    )
+        .fetch_one(&mut *transaction)
+        .await?;
+
+    sqlx::query!("DELETE FROM reviews WHERE id = $1", review.id)
+        .execute(&mut *transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(Some(card))
+}
+
+pub struct StoredIdempotencyKey {
+    pub request_hash: String,
+    pub response_status: Option<i32>,
+    pub response_body: Option<String>,
+}
+
+// returns None if the key was free and is now taken by this request, otherwise the stored request,
+// a claim without a response that is older than the timeout is taken over by the same request
+pub async fn claim_idempotency_key_query(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+    idempotency_key: &str,
+    request_hash: &str,
+    claim_timeout_seconds: f64,
+) -> Result<Option<StoredIdempotencyKey>, Error> {
+    sqlx::query!(
+        "DELETE FROM idempotency_keys WHERE user_id = $1 AND created_at < CURRENT_TIMESTAMP - INTERVAL '24 hours'",
+        user_id
+    )
+        .execute(pool)
+        .await?;
+
+    let claimed = sqlx::query_scalar!(
+        r#"INSERT INTO idempotency_keys (user_id, idempotency_key, request_hash) VALUES ($1, $2, $3)
+        ON CONFLICT (user_id, idempotency_key) DO UPDATE SET claimed_at = CURRENT_TIMESTAMP
+        WHERE idempotency_keys.response_status IS NULL
+            AND idempotency_keys.request_hash = EXCLUDED.request_hash
+            AND idempotency_keys.claimed_at < CURRENT_TIMESTAMP - make_interval(secs => $4)
+        RETURNING id"#,
+        user_id,
+        idempotency_key,
+        request_hash,
+        claim_timeout_seconds,
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    if claimed.is_some() {
+        return Ok(None);
+    }
+
+    sqlx::query_as!(
+        StoredIdempotencyKey,
+        "SELECT request_hash, response_status, response_body FROM idempotency_keys WHERE user_id = $1 AND idempotency_key = $2",
+        user_id,
+        idempotency_key,
+    )
+        .fetch_optional(pool)
+        .await
+}
+
+pub async fn complete_idempotency_key_query(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+    idempotency_key: &str,
+    response_status: i32,
+    response_body: String,
+) -> Result<DatabaseQueryResult, Error> {
+    let result = sqlx::query!(
+        "UPDATE idempotency_keys SET response_status = $1, response_body = $2 WHERE user_id = $3 AND idempotency_key = $4",
+        response_status,
+        response_body,
+        user_id,
+        idempotency_key,
+    )
+        .execute(pool)
+        .await;
+
+    match result {
+        Ok(pg_query_result) => Ok(DatabaseQueryResult {
+            rows_affected: pg_query_result.rows_affected(),
+        }),
+        Err(err) => Err(err),
+    }
+}
+
+pub async fn delete_idempotency_key_query(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+    idempotency_key: &str,
+) -> Result<DatabaseQueryResult, Error> {
+    let result = sqlx::query!(
+        "DELETE FROM idempotency_keys WHERE user_id = $1 AND idempotency_key = $2",
+        user_id,
+        idempotency_key,
+    )
+    .execute(pool)
+    .await;
+
+    match result {
+        Ok(pg_query_result) => Ok(DatabaseQueryResult {
+            rows_affected: pg_query_result.rows_affected(),
+        }),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // a user with a deck holding one card
+    async fn create_fixture(pool: &Pool<Postgres>) -> (User, Deck, Card) {
+        let user_form =
+            serde_json::from_value(json!({ "name": "Anna", "email": "anna@example.com" }));
+        let user = create_user_query(pool, user_form.unwrap()).await.unwrap();
+
+        let deck_form = serde_json::from_value(json!({
+            "from_language": "Deutsch",
+            "to_language_primary": "English",
+        }));
+        let deck = create_deck_query(pool, deck_form.unwrap(), user.id)
+            .await
+            .unwrap();
+
+        let card_form = serde_json::from_value(json!({
+            "from_text": "Hund",
+            "to_text_primary": "dog",
+        }));
+        let card = create_card_query(pool, deck.id, card_form.unwrap())
+            .await
+            .unwrap();
+
+        (user, deck, card)
+    }
+
+    async fn rate(pool: &Pool<Postgres>, card: &Card, rating: i32) {
+        let card_form = serde_json::from_value(json!({ "rating": rating })).unwrap();
+
+        update_card_query(pool, card.deck_id, card.id, card_form)
+            .await
+            .unwrap();
+    }
+
+    async fn ratings(pool: &Pool<Postgres>, card: &Card) -> (i32, i32) {
+        let card = read_card_query(pool, card.deck_id, card.id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        (card.rating, card.prev_rating)
+    }
+
+    #[sqlx::test]
+    async fn undo_restores_the_rating_before_the_review(pool: Pool<Postgres>) {
+        let (_, deck, card) = create_fixture(&pool).await;
+
+        rate(&pool, &card, 1).await;
+        rate(&pool, &card, 3).await;
+        assert_eq!(ratings(&pool, &card).await, (3, 1));
+
+        let undone = undo_last_review_query(&pool, deck.id, None)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!((undone.rating, undone.prev_rating), (1, 0));
+    }
+
+    #[sqlx::test]
+    async fn undo_restores_an_unchanged_rating(pool: Pool<Postgres>) {
+        let (_, deck, card) = create_fixture(&pool).await;
+
+        rate(&pool, &card, 1).await;
+        rate(&pool, &card, 3).await;
+        rate(&pool, &card, 3).await;
+        assert_eq!(ratings(&pool, &card).await, (3, 1));
+
+        undo_last_review_query(&pool, deck.id, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(ratings(&pool, &card).await, (3, 1));
+
+        undo_last_review_query(&pool, deck.id, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(ratings(&pool, &card).await, (1, 0));
+    }
+
+    #[sqlx::test]
+    async fn undo_without_reviews_finds_nothing(pool: Pool<Postgres>) {
+        let (_, deck, _) = create_fixture(&pool).await;
+
+        assert!(undo_last_review_query(&pool, deck.id, None)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    async fn pulled_tags(
+        pool: &Pool<Postgres>,
+        user: &User,
+        since: i64,
+    ) -> (i64, Vec<Vec<String>>) {
+        let pull = read_sync_changes_query(pool, user.id, since).await.unwrap();
+
+        (
+            pull.cursor,
+            pull.cards.into_iter().map(|card| card.tags).collect(),
+        )
+    }
+
+    #[sqlx::test]
+    async fn tag_changes_sync_their_cards(pool: Pool<Postgres>) {
+        let (user, deck, card) = create_fixture(&pool).await;
+
+        let card_form = serde_json::from_value(json!({ "tags": "tiere, a1" })).unwrap();
+        update_card_query(&pool, deck.id, card.id, card_form)
+            .await
+            .unwrap();
+
+        let (cursor, tags) = pulled_tags(&pool, &user, 0).await;
+        assert_eq!(tags, vec![vec!["a1", "tiere"]]);
+
+        let tag_ids: HashMap<String, i32> = read_tags_query(&pool, user.id)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|tag| (tag.name, tag.id))
+            .collect();
+
+        let tag_form = serde_json::from_value(json!({ "name": "animals" })).unwrap();
+        update_tag_query(&pool, tag_ids["tiere"], tag_form, user.id)
+            .await
+            .unwrap();
+
+        let (cursor, tags) = pulled_tags(&pool, &user, cursor).await;
+        assert_eq!(tags, vec![vec!["a1", "animals"]]);
+
+        delete_tag_query(&pool, tag_ids["a1"], user.id)
+            .await
+            .unwrap();
+
+        let (cursor, tags) = pulled_tags(&pool, &user, cursor).await;
+        assert_eq!(tags, vec![vec!["animals"]]);
+
+        let (_, tags) = pulled_tags(&pool, &user, cursor).await;
+        assert!(tags.is_empty());
+    }
+}
@@ -0,0 +1,522 @@
+use crate::duplicates::normalize_text;
+use crate::imports::{ImportCard, ImportDeck, PendingImport};
+use crate::validation::ValidationErrors;
+use crate::{CsvImportForm, Deck};
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_8};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+// the delimiters a file is checked for, by the name they are given with in the form
+pub const CSV_DELIMITERS: [(&str, u8); 4] = [(",", b','), (";", b';'), ("tab", b'\t'), ("|", b'|')];
+
+const DELIMITER_SAMPLE_LINES: usize = 20;
+
+// the card fields a column can be mapped to
+const CSV_FIELDS: [&str; 5] = [
+    "from_text",
+    "to_text_primary",
+    "to_text_secondary",
+    "example_text",
+    "audio_url",
+];
+
+// how the upload was read, so that a wrong guess can be corrected in the form
+#[derive(Clone, serde::Serialize)]
+pub struct CsvImportDetails {
+    pub encoding: String,
+    pub delimiter: String,
+    pub header: bool,
+    // names from the header row, numbers for files without one
+    pub columns: Vec<String>,
+    pub mapping: Vec<CsvColumnMapping>,
+}
+
+// a card field and the column it is read from, numbered from 1
+#[derive(Clone, serde::Serialize)]
+pub struct CsvColumnMapping {
+    pub field: String,
+    pub column: usize,
+    pub name: String,
+}
+
+struct CsvRow {
+    line: u64,
+    cells: Vec<String>,
+}
+
+// helpers
+
+// spreadsheet programs save utf-8 with or without a bom, utf-16 or the legacy encoding of the system
+pub fn decode(data: &[u8]) -> (String, &'static Encoding) {
+    if let Some((encoding, bom_length)) = Encoding::for_bom(data) {
+        let (text, _) = encoding.decode_without_bom_handling(&data[bom_length..]);
+        return (text.into_owned(), encoding);
+    }
+
+    if let Ok(text) = std::str::from_utf8(data) {
+        return (String::from(text), UTF_8);
+    }
+
+    let mut detector = EncodingDetector::new();
+    detector.feed(data, true);
+
+    let encoding = detector.guess(None, true);
+    let (text, _) = encoding.decode_without_bom_handling(data);
+
+    (text.into_owned(), encoding)
+}
+
+// the delimiter that appears equally often in the most lines, quoted cells make this a guess
+fn detect_delimiter(text: &str) -> (&'static str, u8) {
+    let lines: Vec<&str> = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .take(DELIMITER_SAMPLE_LINES)
+        .collect();
+
+    let mut best = CSV_DELIMITERS[0];
+    let mut best_score = (false, 0, 0);
+
+    for (name, delimiter) in CSV_DELIMITERS {
+        let counts: Vec<usize> = lines
+            .iter()
+            .map(|line| line.bytes().filter(|byte| *byte == delimiter).count())
+            .collect();
+
+        let min = counts.iter().copied().min().unwrap_or(0);
+        let max = counts.iter().copied().max().unwrap_or(0);
+
+        let score = (min > 0 && min == max, min, counts.iter().sum::<usize>());
+
+        if score > best_score {
+            best = (name, delimiter);
+            best_score = score;
+        }
+    }
+
+    best
+}
+
+fn normalize_column_name(name: &str) -> String {
+    name.trim().to_lowercase().replace([' ', '-'], "_")
+}
+
+// a column is given by its name in the header or its number, counted from 1
+fn resolve_column(reference: &str, columns: &[String]) -> Option<usize> {
+    if let Ok(number) = reference.parse::<usize>() {
+        return (1..=columns.len()).contains(&number).then(|| number - 1);
+    }
+
+    let reference = normalize_column_name(reference);
+
+    columns
+        .iter()
+        .position(|column| normalize_column_name(column) == reference)
+}
+
+fn read_rows(text: &str, delimiter: u8) -> Result<Vec<CsvRow>, csv::Error> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(text.as_bytes());
+
+    let mut rows = Vec::new();
+
+    for record in reader.records() {
+        let record = record?;
+
+        if record.iter().all(|cell| cell.trim().is_empty()) {
+            continue;
+        }
+
+        rows.push(CsvRow {
+            line: record.position().map_or(0, |position| position.line()),
+            cells: record
+                .iter()
+                .map(|cell| String::from(cell.trim()))
+                .collect(),
+        });
+    }
+
+    Ok(rows)
+}
+
+// import
+
+// reads a csv or tsv upload into a pending import for the deck, rows that can not become a card
+// are skipped and rows that repeat a card of the file or the deck are flagged
+pub fn parse_csv(
+    data: &[u8],
+    user_id: i32,
+    deck: &Deck,
+    deck_texts: &HashSet<String>,
+    csv_import_form: CsvImportForm,
+) -> Result<PendingImport, ValidationErrors> {
+    let mut errors = ValidationErrors::default();
+
+    let (text, encoding) = decode(data);
+
+    let (delimiter_name, delimiter) = match &csv_import_form.delimiter {
+        Some(name) => CSV_DELIMITERS
+            .into_iter()
+            .find(|(delimiter_name, _)| delimiter_name == name)
+            .unwrap_or(CSV_DELIMITERS[0]),
+        None => detect_delimiter(&text),
+    };
+
+    let mut rows = match read_rows(&text, delimiter) {
+        Ok(rows) => rows,
+        Err(err) => {
+            errors.add("file", format!("could not be read: {}", err));
+            return Err(errors);
+        }
+    };
+
+    let num_columns = rows.iter().map(|row| row.cells.len()).max().unwrap_or(0);
+    let header = csv_import_form.header.as_deref() != Some("false");
+
+    let mut columns: Vec<String> = if header && !rows.is_empty() {
+        rows.remove(0).cells
+    } else {
+        Vec::new()
+    };
+
+    if rows.is_empty() {
+        errors.add("file", String::from("does not contain any rows"));
+        return Err(errors);
+    }
+
+    // columns without a name are referred to by their number
+    for number in columns.len() + 1..=num_columns {
+        columns.push(number.to_string());
+    }
+
+    let references = [
+        csv_import_form.from_text,
+        csv_import_form.to_text_primary,
+        csv_import_form.to_text_secondary,
+        csv_import_form.example_text,
+        csv_import_form.audio_url,
+    ];
+
+    let mut mapping: BTreeMap<&str, usize> = BTreeMap::new();
+
+    for (field, reference) in CSV_FIELDS.into_iter().zip(references) {
+        let column = match reference {
+            Some(reference) => match resolve_column(&reference, &columns) {
+                Some(column) => Some(column),
+                None => {
+                    errors.add(field, String::from("is not a column of the file"));
+                    continue;
+                }
+            },
+            None if header => resolve_column(field, &columns),
+            None => None,
+        };
+
+        if let Some(column) = column {
+            mapping.insert(field, column);
+        }
+    }
+
+    // without a mapping the first two columns hold the front and back
+    for (field, column) in [("from_text", 0), ("to_text_primary", 1)] {
+        let is_free = column < num_columns && !mapping.values().any(|mapped| *mapped == column);
+
+        if !mapping.contains_key(field) && is_free {
+            mapping.insert(field, column);
+        }
+    }
+
+    for field in ["from_text", "to_text_primary"] {
+        if !mapping.contains_key(field) {
+            errors.add(field, String::from("must be mapped to a column"));
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let mut pending_import = PendingImport::new(user_id, "csv");
+
+    pending_import.decks.push(ImportDeck {
+        deck_id: Some(deck.id),
+        name: format!("{} - {}", deck.from_language, deck.to_language_primary),
+        from_language: deck.from_language.clone(),
+        to_language_primary: deck.to_language_primary.clone(),
+        to_language_secondary: deck.to_language_secondary.clone(),
+        cards: Vec::new(),
+    });
+
+    // line of the first row per front text, compared like duplicates
+    let mut seen_lines: HashMap<String, u64> = HashMap::new();
+
+    for row in rows {
+        let cell = |field: &str| {
+            mapping
+                .get(field)
+                .and_then(|column| row.cells.get(*column))
+                .filter(|cell| !cell.is_empty())
+                .cloned()
+        };
+
+        let card = ImportCard {
+            from_text: cell("from_text").unwrap_or_default(),
+            to_text_primary: cell("to_text_primary").unwrap_or_default(),
+            to_text_secondary: cell("to_text_secondary"),
+            example_text: cell("example_text"),
+            audio_file: None,
+            audio_url: cell("audio_url"),
+            tags: Vec::new(),
+        };
+
+        let from_text = card.from_text.clone();
+
+        if !pending_import.add_card(0, card, Some(row.line)) {
+            continue;
+        }
+
+        if mapping.values().any(|column| *column >= row.cells.len()) {
+            pending_import.flag_card(
+                0,
+                &from_text,
+                format!("has {} of {} columns", row.cells.len(), num_columns),
+                Some(row.line),
+            );
+        }
+
+        let key = normalize_text(&from_text, &deck.from_language, true);
+
+        if deck_texts.contains(&key) {
+            pending_import.flag_card(
+                0,
+                &from_text,
+                String::from("already exists in the deck"),
+                Some(row.line),
+            );
+        } else if let Some(line) = seen_lines.get(&key) {
+            pending_import.flag_card(
+                0,
+                &from_text,
+                format!("repeats line {}", line),
+                Some(row.line),
+            );
+        }
+
+        seen_lines.entry(key).or_insert(row.line);
+    }
+
+    pending_import.csv = Some(CsvImportDetails {
+        encoding: String::from(encoding.name()),
+        delimiter: String::from(delimiter_name),
+        header,
+        mapping: CSV_FIELDS
+            .into_iter()
+            .filter_map(|field| {
+                mapping.get(field).map(|column| CsvColumnMapping {
+                    field: String::from(field),
+                    column: column + 1,
+                    name: columns[*column].clone(),
+                })
+            })
+            .collect(),
+        columns,
+    });
+
+    Ok(pending_import)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imports::deck_fixture;
+
+    fn form() -> CsvImportForm {
+        CsvImportForm {
+            delimiter: None,
+            header: None,
+            from_text: None,
+            to_text_primary: None,
+            to_text_secondary: None,
+            example_text: None,
+            audio_url: None,
+        }
+    }
+
+    fn cards(pending_import: &PendingImport) -> Vec<(String, String, Option<String>)> {
+        pending_import.decks[0]
+            .cards
+            .iter()
+            .map(|card| {
+                (
+                    card.from_text.clone(),
+                    card.to_text_primary.clone(),
+                    card.example_text.clone(),
+                )
+            })
+            .collect()
+    }
+
+    fn card(
+        from_text: &str,
+        to_text_primary: &str,
+        example_text: Option<&str>,
+    ) -> (String, String, Option<String>) {
+        (
+            String::from(from_text),
+            String::from(to_text_primary),
+            example_text.map(String::from),
+        )
+    }
+
+    #[test]
+    fn detect_delimiter_prefers_consistent_counts() {
+        assert_eq!(detect_delimiter("a,b\nc,d\n").0, ",");
+        assert_eq!(detect_delimiter("a;b\nc;d\n").0, ";");
+        assert_eq!(detect_delimiter("a\tb\nc\td\n").0, "tab");
+        assert_eq!(detect_delimiter("a|b\nc|d\n").0, "|");
+        // commas inside the cells of some lines do not win over the semicolons of every line
+        assert_eq!(
+            detect_delimiter("Hund;dog, hound\nKatze;cat\nMaus;mouse, mice, rat\n").0,
+            ";"
+        );
+        assert_eq!(detect_delimiter("").0, ",");
+    }
+
+    #[test]
+    fn decode_reads_boms_and_legacy_encodings() {
+        let (text, encoding) = decode(b"\xef\xbb\xbfHund,dog");
+        assert_eq!((text.as_str(), encoding.name()), ("Hund,dog", "UTF-8"));
+
+        let (text, encoding) = decode(b"\xff\xfeH\x00\xfc\x00");
+        assert_eq!((text.as_str(), encoding.name()), ("Hü", "UTF-16LE"));
+
+        let (text, _) = decode(b"M\xfcde,tired\nSch\xf6n,beautiful\nStra\xdfe,street");
+        assert_eq!(text, "Müde,tired\nSchön,beautiful\nStraße,street");
+    }
+
+    #[test]
+    fn parse_csv_maps_columns_by_header_name() {
+        let data =
+            "Beispiel;to_text_primary;from_text\n\"Der Hund; bellt.\";dog;Hund\n;cat;Katze\n";
+
+        let pending_import = parse_csv(
+            data.as_bytes(),
+            1,
+            &deck_fixture("Deutsch"),
+            &HashSet::new(),
+            CsvImportForm {
+                example_text: Some(String::from("beispiel")),
+                ..form()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            cards(&pending_import),
+            vec![
+                card("Hund", "dog", Some("Der Hund; bellt.")),
+                card("Katze", "cat", None),
+            ]
+        );
+
+        let details = pending_import.csv.unwrap();
+
+        assert_eq!(details.delimiter, ";");
+        assert!(details.header);
+        assert_eq!(
+            details.columns,
+            vec!["Beispiel", "to_text_primary", "from_text"]
+        );
+    }
+
+    #[test]
+    fn parse_csv_reads_files_without_header_by_column_number() {
+        let data = "dog\tHund\tDer Hund bellt.\ncat\tKatze\n";
+
+        let pending_import = parse_csv(
+            data.as_bytes(),
+            1,
+            &deck_fixture("Deutsch"),
+            &HashSet::new(),
+            CsvImportForm {
+                header: Some(String::from("false")),
+                from_text: Some(String::from("2")),
+                to_text_primary: Some(String::from("1")),
+                example_text: Some(String::from("3")),
+                ..form()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            cards(&pending_import),
+            vec![
+                card("Hund", "dog", Some("Der Hund bellt.")),
+                card("Katze", "cat", None),
+            ]
+        );
+        assert_eq!(pending_import.flagged.len(), 1);
+        assert_eq!(pending_import.flagged[0].message, "has 2 of 3 columns");
+        assert_eq!(pending_import.flagged[0].line, Some(2));
+    }
+
+    #[test]
+    fn parse_csv_defaults_to_the_first_two_columns() {
+        let data = "Wort,Übersetzung\nHund,dog\nhund,hound\nKatze\n";
+        let deck_texts = HashSet::from([normalize_text("Maus", "Deutsch", true)]);
+
+        let pending_import = parse_csv(
+            data.as_bytes(),
+            1,
+            &deck_fixture("Deutsch"),
+            &deck_texts,
+            form(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            cards(&pending_import),
+            vec![card("Hund", "dog", None), card("hund", "hound", None)]
+        );
+        assert_eq!(pending_import.flagged.len(), 1);
+        assert_eq!(pending_import.flagged[0].message, "repeats line 2");
+        assert_eq!(pending_import.skipped.len(), 1);
+        assert_eq!(pending_import.skipped[0].line, Some(4));
+    }
+
+    #[test]
+    fn parse_csv_reports_unknown_columns() {
+        let errors = parse_csv(
+            b"from_text,to_text_primary\nHund,dog\n",
+            1,
+            &deck_fixture("Deutsch"),
+            &HashSet::new(),
+            CsvImportForm {
+                example_text: Some(String::from("example")),
+                ..form()
+            },
+        )
+        .err()
+        .unwrap();
+
+        assert_eq!(
+            errors.to_message(),
+            "example_text is not a column of the file"
+        );
+
+        let errors = parse_csv(
+            b"from_text\n",
+            1,
+            &deck_fixture("Deutsch"),
+            &HashSet::new(),
+            form(),
+        )
+        .err()
+        .unwrap();
+
+        assert_eq!(errors.to_message(), "file does not contain any rows");
+    }
+}
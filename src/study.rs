@@ -0,0 +1,242 @@
+use crate::pages::read_cards_and_set_deck_timestamp_query;
+use crate::queries::{read_card_query, read_deck};
+use crate::{AppState, Card, Deck};
+use chrono::NaiveDateTime;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use sqlx::Error;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+const DAILY_REVIEW_COUNT: usize = 9;
+const SESSION_MAX_AGE_HOURS: i64 = 24;
+
+// study model
+
+#[derive(Clone, serde::Serialize)]
+pub struct StudySession {
+    pub id: String,
+    pub user_id: i32,
+    pub deck_id: i32,
+    // the session only holds cards with one of these tags, empty for the whole deck
+    pub tags: Vec<String>,
+    #[serde(skip)]
+    pub card_ids: Vec<i32>,
+    pub num_cards: usize,
+    pub position: usize,
+    pub reviewed: usize,
+    pub started_at: NaiveDateTime,
+}
+
+#[derive(serde::Serialize)]
+pub struct StudySessionCard {
+    pub session: StudySession,
+    // None once every card of the session was rated
+    pub card: Option<Card>,
+}
+
+// ordering
+
+// puts the cards of a deck in study order, shared by the html flow and the session api
+pub fn order_study_cards(deck: &Deck, mut cards: Vec<Card>) -> Vec<Card> {
+    if cards.is_empty() {
+        return cards;
+    }
+
+    cards.sort_by_key(|card| Reverse(card.updated_at));
+
+    let mut weights: HashMap<i32, i32> = HashMap::new();
+
+    for card in &cards {
+        // 1. All set to 4 after deck last seen
+
+        if card.rating == 4 && deck.seen_at < card.updated_at {
+            weights.insert(card.id, 1_000_000);
+        }
+
+        // 2. All unrated
+
+        if card.rating == 0 {
+            weights.insert(card.id, 100_000);
+        }
+    }
+
+    let span = cards[0].updated_at - cards[cards.len() - 1].updated_at; // youngest - oldest
+
+    for card in &cards {
+        // Continue if weights already include card id
+
+        if weights.contains_key(&card.id) {
+            continue;
+        }
+
+        // 3. If num < DAILY_REVIEW_COUNT, fill with youngest
+
+        if weights.len() < DAILY_REVIEW_COUNT {
+            weights.insert(card.id, 100_000);
+        }
+
+        // 4. Weight by rating times weight by last seen - ceil((youngest - current) / span * 4)
+        // TODO: Weight by time looked at: max(lower_limit, min(x, upper_limit))
+
+        let current_age = cards[0].updated_at - card.updated_at;
+
+        let span_number = span.num_milliseconds() as f32;
+        let current_age_number = current_age.num_milliseconds() as f32;
+
+        let weight_by_last_seen: f32 = (current_age_number / span_number * 4_f32).ceil();
+
+        weights.insert(card.id, card.rating + weight_by_last_seen as i32);
+    }
+
+    // Sort cards by weight
+
+    cards.sort_by(|a, b| weights.get(&b.id).cmp(&weights.get(&a.id)));
+
+    cards
+}
+
+// sessions
+
+fn generate_session_id() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+pub async fn start_study_session(
+    app_state: &AppState,
+    user_id: i32,
+    deck_id: i32,
+    tags: Vec<String>,
+) -> Result<Option<StudySession>, Error> {
+    // read before the timestamp is set, the ordering compares against the previous visit
+    let deck = match read_deck(&app_state.pool, deck_id, user_id).await? {
+        Some(deck) => deck,
+        None => return Ok(None),
+    };
+
+    let cards =
+        read_cards_and_set_deck_timestamp_query(&app_state.pool, deck_id, user_id, &tags).await?;
+
+    let card_ids: Vec<i32> = order_study_cards(&deck, cards)
+        .iter()
+        .map(|card| card.id)
+        .collect();
+
+    let now = chrono::Utc::now().naive_utc();
+
+    let session = StudySession {
+        id: generate_session_id(),
+        user_id,
+        deck_id,
+        tags,
+        num_cards: card_ids.len(),
+        card_ids,
+        position: 0,
+        reviewed: 0,
+        started_at: now,
+    };
+
+    let mut sessions = app_state.study_sessions.write().unwrap();
+
+    // sessions that were never ended are dropped after a day
+    sessions.retain(|_, session| (now - session.started_at).num_hours() < SESSION_MAX_AGE_HOURS);
+
+    sessions.insert(session.id.clone(), session.clone());
+
+    Ok(Some(session))
+}
+
+pub fn read_study_session(
+    app_state: &AppState,
+    session_id: &str,
+    user_id: i32,
+) -> Option<StudySession> {
+    app_state
+        .study_sessions
+        .read()
+        .unwrap()
+        .get(session_id)
+        .filter(|session| session.user_id == user_id)
+        .cloned()
+}
+
+// returns the card at the current position, skipping cards deleted since the session started
+pub async fn read_study_session_card(
+    app_state: &AppState,
+    mut session: StudySession,
+) -> Result<StudySessionCard, Error> {
+    while let Some(card_id) = session.card_ids.get(session.position) {
+        if let Some(card) = read_card_query(&app_state.pool, session.deck_id, *card_id).await? {
+            return Ok(StudySessionCard {
+                session,
+                card: Some(card),
+            });
+        }
+
+        session.position += 1;
+    }
+
+    Ok(StudySessionCard {
+        session,
+        card: None,
+    })
+}
+
+// moves the session past the given card, unless another request already did
+pub fn advance_study_session(
+    app_state: &AppState,
+    session_id: &str,
+    card_id: i32,
+) -> Option<StudySession> {
+    let mut sessions = app_state.study_sessions.write().unwrap();
+
+    let session = sessions.get_mut(session_id)?;
+
+    if let Some(position) = session.card_ids[session.position..]
+        .iter()
+        .position(|id| *id == card_id)
+    {
+        session.position += position + 1;
+        session.reviewed += 1;
+    }
+
+    Some(session.clone())
+}
+
+// moves the session back to a rated card, so that it is shown again after its review was undone
+pub fn rewind_study_session(
+    app_state: &AppState,
+    session_id: &str,
+    card_id: i32,
+) -> Option<StudySession> {
+    let mut sessions = app_state.study_sessions.write().unwrap();
+
+    let session = sessions.get_mut(session_id)?;
+
+    if let Some(position) = session.card_ids[..session.position]
+        .iter()
+        .rposition(|id| *id == card_id)
+    {
+        session.position = position;
+        session.reviewed = session.reviewed.saturating_sub(1);
+    }
+
+    Some(session.clone())
+}
+
+pub fn end_study_session(
+    app_state: &AppState,
+    session_id: &str,
+    user_id: i32,
+) -> Option<StudySession> {
+    let mut sessions = app_state.study_sessions.write().unwrap();
+
+    match sessions.get(session_id) {
+        Some(session) if session.user_id == user_id => sessions.remove(session_id),
+        _ => None,
+    }
+}
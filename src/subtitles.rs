@@ -0,0 +1,409 @@
+use crate::duplicates::fold_text;
+use crate::imports::{strip_html, ImportCard, ImportDeck, PendingImport, TRANSLATION_PLACEHOLDER};
+use crate::spreadsheet::decode;
+use crate::validation::EXAMPLE_TEXT_MAX_LENGTH;
+use crate::Deck;
+use std::collections::{HashMap, HashSet};
+
+const SUBTITLES_TAG: &str = "subtitles";
+
+// shorter words are mostly interjections or the remains of elisions
+const MIN_WORD_LENGTH: usize = 2;
+
+// longer file names are cut in the source of an example, so that the line keeps most of the room
+const SOURCE_NAME_MAX_LENGTH: usize = 60;
+
+// a subtitle shown from its start, with its lines joined
+struct SubtitleCue {
+    // like 00:12:34, without the milliseconds
+    start: String,
+    text: String,
+}
+
+// helpers
+
+// srt writes 00:12:34,500 and vtt 00:12:34.500 or 12:34.500 for times under an hour
+fn parse_timestamp(timestamp: &str) -> Option<String> {
+    let timestamp = timestamp.split([',', '.']).next()?.trim();
+
+    let parts = timestamp
+        .split(':')
+        .map(|part| part.parse::<u32>().ok())
+        .collect::<Option<Vec<u32>>>()?;
+
+    let (hours, minutes, seconds) = match parts.as_slice() {
+        [hours, minutes, seconds] => (*hours, *minutes, *seconds),
+        [minutes, seconds] => (0, *minutes, *seconds),
+        _ => return None,
+    };
+
+    Some(format!("{:02}:{:02}:{:02}", hours, minutes, seconds))
+}
+
+// drops the styling of the lines, html tags and the {\an8} position tags of converted ass files
+fn clean_line(line: &str) -> String {
+    let mut text = String::with_capacity(line.len());
+    let mut in_tag = false;
+
+    for char in line.chars() {
+        match char {
+            '{' => in_tag = true,
+            '}' if in_tag => in_tag = false,
+            _ if !in_tag => text.push(char),
+            _ => {}
+        }
+    }
+
+    strip_html(&text)
+}
+
+// reads the cues of an srt or vtt file, blocks without a timing line like the vtt header, notes
+// and styles are left out
+fn parse_cues(text: &str) -> Vec<SubtitleCue> {
+    let text = text.replace("\r\n", "\n");
+
+    let mut cues = Vec::new();
+
+    for block in text.split("\n\n") {
+        let mut lines = block.lines().map(str::trim);
+
+        let start = lines
+            .by_ref()
+            .find_map(|line| line.split_once("-->"))
+            .and_then(|(start, _)| parse_timestamp(start));
+
+        let start = match start {
+            Some(start) => start,
+            None => continue,
+        };
+
+        let text = lines
+            .map(clean_line)
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        if !text.is_empty() {
+            cues.push(SubtitleCue { start, text });
+        }
+    }
+
+    cues
+}
+
+// letters with apostrophes and hyphens inside, like don't or peut-être
+fn split_words(text: &str) -> Vec<&str> {
+    text.split(|char: char| !(char.is_alphabetic() || char == '\'' || char == '’' || char == '-'))
+        .map(|word| word.trim_matches(|char: char| !char.is_alphabetic()))
+        .filter(|word| word.chars().count() >= MIN_WORD_LENGTH)
+        .collect()
+}
+
+// cuts the text at a word to fit the length, with an ellipsis for what is left out
+fn truncate_text(text: &str, max_length: usize) -> String {
+    if text.chars().count() <= max_length {
+        return String::from(text);
+    }
+
+    let cut: String = text.chars().take(max_length - 1).collect();
+
+    let cut = match cut.rsplit_once(' ') {
+        Some((words, _)) if !words.trim().is_empty() => words,
+        _ => &cut,
+    };
+
+    format!("{}…", cut.trim_end())
+}
+
+// the file name without its extension and the time of the cue, like "Dark.S01E01 00:12:34"
+fn source_reference(file_name: &str, start: &str) -> String {
+    let name = file_name
+        .rsplit_once('.')
+        .map_or(file_name, |(name, _)| name)
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ");
+
+    let name = truncate_text(&name, SOURCE_NAME_MAX_LENGTH);
+
+    format!("{} {}", name, start).trim_start().to_string()
+}
+
+// the line with its source after it, the line is cut to make room for the source
+fn example_text(line: &str, source: &str) -> String {
+    let line = truncate_text(line, EXAMPLE_TEXT_MAX_LENGTH - source.chars().count() - 3);
+
+    format!("{} ({})", line, source)
+}
+
+// import
+
+// reads a subtitle file into a pending import for the deck with a card for every word that is not
+// in the deck yet, in the order they are first said. the card keeps the line the word was first
+// said in as its example, followed by the file and time of the line
+pub fn parse_subtitles(
+    data: &[u8],
+    file_name: &str,
+    user_id: i32,
+    deck: &Deck,
+    deck_texts: &HashSet<String>,
+) -> Result<PendingImport, String> {
+    let (text, _) = decode(data);
+
+    let cues = parse_cues(&text);
+
+    if cues.is_empty() {
+        return Err(String::from("does not contain any subtitles (.srt, .vtt)"));
+    }
+
+    let mut pending_import = PendingImport::new(user_id, "subtitles");
+
+    pending_import.decks.push(ImportDeck {
+        deck_id: Some(deck.id),
+        name: String::from(file_name),
+        from_language: deck.from_language.clone(),
+        to_language_primary: deck.to_language_primary.clone(),
+        to_language_secondary: deck.to_language_secondary.clone(),
+        cards: Vec::new(),
+    });
+
+    // index of the card per word, compared without case but with diacritics
+    let mut word_indexes: HashMap<String, usize> = HashMap::new();
+
+    for cue in &cues {
+        let source = source_reference(file_name, &cue.start);
+
+        for word in split_words(&cue.text) {
+            let key = fold_text(word, &deck.from_language, false);
+
+            if deck_texts.contains(&key) {
+                continue;
+            }
+
+            if let Some(index) = word_indexes.get(&key) {
+                // a word at the start of a sentence is capitalised, another mention shows its case
+                let card = &mut pending_import.decks[0].cards[*index];
+
+                if word.chars().next().is_some_and(char::is_lowercase) {
+                    card.from_text = String::from(word);
+                }
+
+                continue;
+            }
+
+            let card = ImportCard {
+                from_text: String::from(word),
+                to_text_primary: String::from(TRANSLATION_PLACEHOLDER),
+                to_text_secondary: None,
+                example_text: Some(example_text(&cue.text, &source)),
+                audio_file: None,
+                audio_url: None,
+                tags: vec![String::from(SUBTITLES_TAG)],
+            };
+
+            if pending_import.add_card(0, card, None) {
+                word_indexes.insert(key, pending_import.decks[0].cards.len() - 1);
+            }
+        }
+    }
+
+    Ok(pending_import)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imports::deck_fixture;
+
+    fn cues(text: &str) -> Vec<(String, String)> {
+        parse_cues(text)
+            .into_iter()
+            .map(|cue| (cue.start, cue.text))
+            .collect()
+    }
+
+    fn cue(start: &str, text: &str) -> (String, String) {
+        (String::from(start), String::from(text))
+    }
+
+    #[test]
+    fn parse_timestamp_reads_srt_and_vtt_times() {
+        assert_eq!(
+            parse_timestamp("00:12:34,500"),
+            Some(String::from("00:12:34"))
+        );
+        assert_eq!(
+            parse_timestamp("01:02:03.004 "),
+            Some(String::from("01:02:03"))
+        );
+        assert_eq!(parse_timestamp("12:34.500"), Some(String::from("00:12:34")));
+        assert_eq!(parse_timestamp(" 1:2:3"), Some(String::from("01:02:03")));
+
+        assert_eq!(parse_timestamp("12"), None);
+        assert_eq!(parse_timestamp("aa:bb:cc"), None);
+        assert_eq!(parse_timestamp("1:2:3:4"), None);
+    }
+
+    #[test]
+    fn parse_cues_reads_srt_files() {
+        let text = "1\r\n00:00:01,000 --> 00:00:02,000\r\n<i>Wo ist</i> der Hund?\r\nIm {\\an8}Garten.\r\n\r\n2\r\n00:00:03,000 --> 00:00:04,000\r\n\r\n3\r\n00:01:05,000 --> 00:01:06,000\r\nDanke!\r\n";
+
+        assert_eq!(
+            cues(text),
+            vec![
+                cue("00:00:01", "Wo ist der Hund? Im Garten."),
+                cue("00:01:05", "Danke!"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_cues_reads_vtt_files() {
+        let text = "WEBVTT\n\nNOTE written by hand\n\nSTYLE\n::cue { color: yellow }\n\nintro\n00:05.000 --> 00:07.000 align:start\n<v Anna>Guten Morgen!\n\n01:00:00.000 --> 01:00:01.000\nTschüss.\n";
+
+        assert_eq!(
+            cues(text),
+            vec![
+                cue("00:00:05", "Guten Morgen!"),
+                cue("01:00:00", "Tschüss."),
+            ]
+        );
+    }
+
+    #[test]
+    fn split_words_keeps_apostrophes_and_hyphens_inside_words() {
+        assert_eq!(
+            split_words("Don't go – it's peut-être 42 km, o.k.? \"Ja!\" - a"),
+            vec!["Don't", "go", "it's", "peut-être", "km", "Ja"]
+        );
+        assert_eq!(split_words("'Hallo' -Welt- ’n"), vec!["Hallo", "Welt"]);
+    }
+
+    #[test]
+    fn truncate_text_cuts_at_a_word() {
+        assert_eq!(truncate_text("kurz", 10), "kurz");
+        assert_eq!(truncate_text("ein ganz langer Satz", 12), "ein ganz…");
+        assert_eq!(truncate_text("Donaudampfschiff", 6), "Donau…");
+        assert_eq!(truncate_text("äöüäöüäöü", 5).chars().count(), 5);
+    }
+
+    #[test]
+    fn source_reference_names_the_file_and_time() {
+        assert_eq!(
+            source_reference("Dark.S01E01.srt", "00:12:34"),
+            "Dark.S01E01 00:12:34"
+        );
+        assert_eq!(source_reference("a,  b.vtt", "00:00:01"), "a, b 00:00:01");
+        assert_eq!(source_reference(".srt", "00:00:01"), "00:00:01");
+
+        let source = source_reference(&format!("{}.srt", "x".repeat(200)), "00:12:34");
+
+        assert_eq!(source.chars().count(), SOURCE_NAME_MAX_LENGTH + 9);
+        assert!(source.ends_with("… 00:12:34"));
+    }
+
+    #[test]
+    fn parse_subtitles_lists_new_words_with_their_first_line() {
+        let text = "1\n00:00:01,000 --> 00:00:02,000\nDer Hund schläft.\n\n2\n00:00:03,000 --> 00:00:04,000\nDer Garten, der Hund und der Ball.\n";
+        let deck_texts = HashSet::from([fold_text("hund", "Deutsch", false)]);
+
+        let pending_import = parse_subtitles(
+            text.as_bytes(),
+            "Dark.S01E01.srt",
+            1,
+            &deck_fixture("Deutsch"),
+            &deck_texts,
+        )
+        .unwrap();
+
+        let cards = &pending_import.decks[0].cards;
+        let words: Vec<&str> = cards.iter().map(|card| card.from_text.as_str()).collect();
+
+        // "Der" is said lowercase later on
+        assert_eq!(words, vec!["der", "schläft", "Garten", "und", "Ball"]);
+        assert_eq!(
+            cards[0].example_text.as_deref(),
+            Some("Der Hund schläft. (Dark.S01E01 00:00:01)")
+        );
+        assert_eq!(
+            cards[2].example_text.as_deref(),
+            Some("Der Garten, der Hund und der Ball. (Dark.S01E01 00:00:03)")
+        );
+
+        for card in cards {
+            assert_eq!(card.tags, vec!["subtitles"]);
+        }
+    }
+
+    #[test]
+    fn parse_subtitles_keeps_words_of_long_lines() {
+        let line = format!("Wort {}", "lange Zeile ".repeat(30));
+        let text = format!("1\n00:00:01,000 --> 00:00:02,000\n{}\n{}\n", line, line);
+        let file_name = format!("{}.srt", "Serie.Staffel.Folge.".repeat(10));
+
+        let pending_import = parse_subtitles(
+            text.as_bytes(),
+            &file_name,
+            1,
+            &deck_fixture("Deutsch"),
+            &HashSet::new(),
+        )
+        .unwrap();
+
+        let cards = &pending_import.decks[0].cards;
+        let words: Vec<&str> = cards.iter().map(|card| card.from_text.as_str()).collect();
+        let source = source_reference(&file_name, "00:00:01");
+
+        assert_eq!(words, vec!["Wort", "lange", "Zeile"]);
+        assert!(pending_import.skipped.is_empty());
+
+        for card in cards {
+            let example_text = card.example_text.as_deref().unwrap();
+
+            assert!(example_text.chars().count() <= EXAMPLE_TEXT_MAX_LENGTH);
+            assert!(example_text.starts_with("Wort lange Zeile"));
+            assert!(example_text.ends_with(&format!("… ({})", source)));
+        }
+    }
+
+    #[test]
+    fn parse_subtitles_keeps_words_with_diacritics_apart() {
+        let text = "1\n00:00:01,000 --> 00:00:02,000\nDas ist schon schön, zahlen und zählen.\n";
+        let deck_texts = HashSet::from([fold_text("die Schön", "Deutsch", true)]);
+
+        let pending_import = parse_subtitles(
+            text.as_bytes(),
+            "a.srt",
+            1,
+            &deck_fixture("Deutsch"),
+            &deck_texts,
+        )
+        .unwrap();
+
+        let words: Vec<&str> = pending_import.decks[0]
+            .cards
+            .iter()
+            .map(|card| card.from_text.as_str())
+            .collect();
+
+        assert_eq!(
+            words,
+            vec!["Das", "ist", "schon", "zahlen", "und", "zählen"]
+        );
+    }
+
+    #[test]
+    fn parse_subtitles_needs_cues() {
+        let err = parse_subtitles(
+            b"WEBVTT\n\n",
+            "empty.vtt",
+            1,
+            &deck_fixture("Deutsch"),
+            &HashSet::new(),
+        )
+        .err()
+        .unwrap();
+
+        assert_eq!(err, "does not contain any subtitles (.srt, .vtt)");
+    }
+}
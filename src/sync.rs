@@ -0,0 +1,536 @@
+use crate::events::EventType;
+use crate::queries::{
+    begin_sync_transaction, create_sync_card_query, create_sync_deck_query, delete_sync_card_query,
+    delete_sync_deck_query, read_sync_card_query, read_sync_deck_query, read_sync_deleted_query,
+    update_sync_card_query, update_sync_deck_query,
+};
+use crate::validation::{Validate, ValidationErrors};
+use crate::{CardForm, DeckForm, SyncChange, SyncForm};
+use chrono::NaiveDateTime;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use sqlx::{Error, Pool, Postgres, Transaction};
+use std::collections::HashMap;
+
+pub const STRATEGY_LAST_WRITER_WINS: &str = "last_writer_wins";
+pub const STRATEGY_FIELD_MERGE: &str = "field_merge";
+
+// push result model
+
+#[derive(Serialize)]
+struct SyncApplied {
+    entity_type: &'static str,
+    id: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client_id: Option<String>,
+    deleted: bool,
+}
+
+#[derive(Serialize)]
+struct SyncConflict {
+    entity_type: &'static str,
+    id: i32,
+    reason: &'static str,
+    fields: Vec<String>,
+    // whose values ended up in the database, "client" or "server"
+    resolution: &'static str,
+    // the server row as it was before the change
+    server: Value,
+}
+
+#[derive(Serialize)]
+struct SyncRejected {
+    entity_type: &'static str,
+    id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client_id: Option<String>,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fields: Option<ValidationErrors>,
+}
+
+#[derive(Default, Serialize)]
+pub struct SyncPushResult {
+    applied: Vec<SyncApplied>,
+    conflicts: Vec<SyncConflict>,
+    rejected: Vec<SyncRejected>,
+    // published once the transaction is committed
+    #[serde(skip)]
+    pub events: Vec<(EventType, Value)>,
+}
+
+impl SyncPushResult {
+    fn apply(&mut self, entity_type: &'static str, id: i32, change: &SyncChange) {
+        self.applied.push(SyncApplied {
+            entity_type,
+            id,
+            client_id: change.client_id.clone(),
+            deleted: change.deleted,
+        });
+    }
+
+    fn conflict(
+        &mut self,
+        entity_type: &'static str,
+        id: i32,
+        reason: &'static str,
+        merge: &Merge,
+        server: Value,
+    ) {
+        self.conflicts.push(SyncConflict {
+            entity_type,
+            id,
+            reason,
+            fields: merge.conflicts.clone(),
+            resolution: if merge.client_wins {
+                "client"
+            } else {
+                "server"
+            },
+            server,
+        });
+    }
+
+    fn reject(
+        &mut self,
+        entity_type: &'static str,
+        change: &SyncChange,
+        message: String,
+        fields: Option<ValidationErrors>,
+    ) {
+        self.rejected.push(SyncRejected {
+            entity_type,
+            id: change.id,
+            client_id: change.client_id.clone(),
+            message,
+            fields,
+        });
+    }
+}
+
+// merge
+
+struct Merge {
+    fields: Map<String, Value>,
+    conflicts: Vec<String>,
+    client_wins: bool,
+}
+
+impl Merge {
+    fn server_wins() -> Self {
+        Merge {
+            fields: Map::new(),
+            conflicts: Vec::new(),
+            client_wins: false,
+        }
+    }
+}
+
+// picks the changed fields to write over the server row
+//
+// last writer wins takes all of them if the change is newer and none otherwise. field merge takes
+// every field the server has not touched since the client's base values, and settles the others
+// by updated_at
+fn merge_fields(
+    change: &SyncChange,
+    server: &Value,
+    server_updated_at: NaiveDateTime,
+    updated_at: NaiveDateTime,
+    field_merge: bool,
+) -> Merge {
+    let client_wins = updated_at >= server_updated_at;
+
+    let mut fields = Map::new();
+    let mut conflicts = Vec::new();
+
+    for (field, value) in &change.fields {
+        let server_value = server.get(field).unwrap_or(&Value::Null);
+
+        if value == server_value {
+            continue;
+        }
+
+        let server_unchanged = field_merge
+            && change.base.as_ref().and_then(|base| base.get(field)) == Some(server_value);
+
+        if server_unchanged {
+            fields.insert(field.clone(), value.clone());
+        } else if client_wins {
+            if field_merge {
+                conflicts.push(field.clone());
+            }
+            fields.insert(field.clone(), value.clone());
+        } else {
+            conflicts.push(field.clone());
+        }
+    }
+
+    Merge {
+        fields,
+        conflicts,
+        client_wins,
+    }
+}
+
+fn parse_form<T: DeserializeOwned + Validate>(
+    fields: Map<String, Value>,
+    is_create: bool,
+) -> Result<T, (String, Option<ValidationErrors>)> {
+    let form: T =
+        serde_json::from_value(Value::Object(fields)).map_err(|err| (err.to_string(), None))?;
+
+    form.validate(is_create)
+        .map_err(|errors| (String::from("Validation failed"), Some(errors)))
+}
+
+// apply
+
+struct SyncContext {
+    transaction: Transaction<'static, Postgres>,
+    user_id: i32,
+    field_merge: bool,
+    now: NaiveDateTime,
+    // ids of decks created in this batch, by client id
+    deck_ids: HashMap<String, i32>,
+    result: SyncPushResult,
+}
+
+async fn apply_deck_change(context: &mut SyncContext, change: SyncChange) -> Result<(), Error> {
+    // clocks running ahead must not win every later conflict
+    let updated_at = change.updated_at.min(context.now);
+
+    let deck_id = match change.id {
+        Some(deck_id) => deck_id,
+        None => {
+            let deck_form = match parse_form::<DeckForm>(change.fields.clone(), true) {
+                Ok(deck_form) => deck_form,
+                Err((message, fields)) => {
+                    context.result.reject("decks", &change, message, fields);
+                    return Ok(());
+                }
+            };
+
+            let deck = create_sync_deck_query(
+                &mut context.transaction,
+                deck_form,
+                context.user_id,
+                updated_at,
+            )
+            .await?;
+
+            if let Some(client_id) = &change.client_id {
+                context.deck_ids.insert(client_id.clone(), deck.id);
+            }
+
+            context.result.apply("decks", deck.id, &change);
+            context
+                .result
+                .events
+                .push((EventType::DeckCreated, json!(deck)));
+
+            return Ok(());
+        }
+    };
+
+    let deck = read_sync_deck_query(&mut context.transaction, deck_id, context.user_id).await?;
+
+    let deck = match deck {
+        Some(deck) => deck,
+        None => {
+            let deleted = read_sync_deleted_query(
+                &mut context.transaction,
+                "decks",
+                deck_id,
+                context.user_id,
+            )
+            .await?;
+
+            if deleted && change.deleted {
+                context.result.apply("decks", deck_id, &change);
+            } else if deleted {
+                context.result.conflict(
+                    "decks",
+                    deck_id,
+                    "deleted_on_server",
+                    &Merge::server_wins(),
+                    Value::Null,
+                );
+            } else {
+                context
+                    .result
+                    .reject("decks", &change, String::from("Deck not found"), None);
+            }
+
+            return Ok(());
+        }
+    };
+
+    if change.deleted {
+        if deck.updated_at > updated_at {
+            context.result.conflict(
+                "decks",
+                deck_id,
+                "server_newer",
+                &Merge::server_wins(),
+                json!(deck),
+            );
+        } else if delete_sync_deck_query(&mut context.transaction, deck_id).await? {
+            context.result.apply("decks", deck_id, &change);
+            context
+                .result
+                .events
+                .push((EventType::DeckDeleted, json!(deck)));
+        } else {
+            context
+                .result
+                .reject("decks", &change, String::from("Deck still has cards"), None);
+        }
+
+        return Ok(());
+    }
+
+    let merge = merge_fields(
+        &change,
+        &json!(deck),
+        deck.updated_at,
+        updated_at,
+        context.field_merge,
+    );
+
+    if !merge.fields.is_empty() {
+        let deck_form = match parse_form::<DeckForm>(merge.fields.clone(), false) {
+            Ok(deck_form) => deck_form,
+            Err((message, fields)) => {
+                context.result.reject("decks", &change, message, fields);
+                return Ok(());
+            }
+        };
+
+        let updated_deck = update_sync_deck_query(
+            &mut context.transaction,
+            deck_id,
+            deck_form,
+            updated_at.max(deck.updated_at),
+        )
+        .await?;
+
+        context.result.apply("decks", deck_id, &change);
+        context
+            .result
+            .events
+            .push((EventType::DeckUpdated, json!(updated_deck)));
+    } else if merge.conflicts.is_empty() {
+        context.result.apply("decks", deck_id, &change);
+    }
+
+    if !merge.conflicts.is_empty() {
+        let reason = if context.field_merge {
+            "field_conflict"
+        } else {
+            "server_newer"
+        };
+
+        context
+            .result
+            .conflict("decks", deck_id, reason, &merge, json!(deck));
+    }
+
+    Ok(())
+}
+
+async fn apply_card_change(context: &mut SyncContext, change: SyncChange) -> Result<(), Error> {
+    let updated_at = change.updated_at.min(context.now);
+
+    let card_id = match change.id {
+        Some(card_id) => card_id,
+        None => {
+            let deck_id = match (change.deck_id, &change.deck_client_id) {
+                (Some(deck_id), _) => {
+                    read_sync_deck_query(&mut context.transaction, deck_id, context.user_id)
+                        .await?
+                        .map(|deck| deck.id)
+                }
+                (None, Some(deck_client_id)) => context.deck_ids.get(deck_client_id).copied(),
+                (None, None) => None,
+            };
+
+            let deck_id = match deck_id {
+                Some(deck_id) => deck_id,
+                None => {
+                    context
+                        .result
+                        .reject("cards", &change, String::from("Deck not found"), None);
+                    return Ok(());
+                }
+            };
+
+            let card_form = match parse_form::<CardForm>(change.fields.clone(), true) {
+                Ok(card_form) => card_form,
+                Err((message, fields)) => {
+                    context.result.reject("cards", &change, message, fields);
+                    return Ok(());
+                }
+            };
+
+            let card =
+                create_sync_card_query(&mut context.transaction, deck_id, card_form, updated_at)
+                    .await?;
+
+            context.result.apply("cards", card.id, &change);
+            context
+                .result
+                .events
+                .push((EventType::CardCreated, json!(card)));
+
+            return Ok(());
+        }
+    };
+
+    let card = read_sync_card_query(&mut context.transaction, card_id, context.user_id).await?;
+
+    let card = match card {
+        Some(card) => card,
+        None => {
+            let deleted = read_sync_deleted_query(
+                &mut context.transaction,
+                "cards",
+                card_id,
+                context.user_id,
+            )
+            .await?;
+
+            if deleted && change.deleted {
+                context.result.apply("cards", card_id, &change);
+            } else if deleted {
+                context.result.conflict(
+                    "cards",
+                    card_id,
+                    "deleted_on_server",
+                    &Merge::server_wins(),
+                    Value::Null,
+                );
+            } else {
+                context
+                    .result
+                    .reject("cards", &change, String::from("Card not found"), None);
+            }
+
+            return Ok(());
+        }
+    };
+
+    if change.deleted {
+        if card.updated_at > updated_at {
+            context.result.conflict(
+                "cards",
+                card_id,
+                "server_newer",
+                &Merge::server_wins(),
+                json!(card),
+            );
+        } else {
+            delete_sync_card_query(&mut context.transaction, card_id).await?;
+
+            context.result.apply("cards", card_id, &change);
+            context
+                .result
+                .events
+                .push((EventType::CardDeleted, json!(card)));
+        }
+
+        return Ok(());
+    }
+
+    let merge = merge_fields(
+        &change,
+        &json!(card),
+        card.updated_at,
+        updated_at,
+        context.field_merge,
+    );
+
+    if !merge.fields.is_empty() {
+        let event_type = if merge.fields.contains_key("rating") {
+            EventType::CardRated
+        } else {
+            EventType::CardUpdated
+        };
+
+        let card_form = match parse_form::<CardForm>(merge.fields.clone(), false) {
+            Ok(card_form) => card_form,
+            Err((message, fields)) => {
+                context.result.reject("cards", &change, message, fields);
+                return Ok(());
+            }
+        };
+
+        let updated_card = update_sync_card_query(
+            &mut context.transaction,
+            card_id,
+            card_form,
+            updated_at.max(card.updated_at),
+        )
+        .await?;
+
+        context.result.apply("cards", card_id, &change);
+        context
+            .result
+            .events
+            .push((event_type, json!(updated_card)));
+    } else if merge.conflicts.is_empty() {
+        context.result.apply("cards", card_id, &change);
+    }
+
+    if !merge.conflicts.is_empty() {
+        let reason = if context.field_merge {
+            "field_conflict"
+        } else {
+            "server_newer"
+        };
+
+        context
+            .result
+            .conflict("cards", card_id, reason, &merge, json!(card));
+    }
+
+    Ok(())
+}
+
+// applies a batch in one transaction, decks first so new cards can point at new decks and deck
+// deletions last so the cards in them can be deleted in the same batch
+pub async fn apply_sync_form(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+    sync_form: SyncForm,
+) -> Result<SyncPushResult, Error> {
+    let mut context = SyncContext {
+        transaction: begin_sync_transaction(pool).await?,
+        user_id,
+        field_merge: sync_form.strategy.as_deref() == Some(STRATEGY_FIELD_MERGE),
+        now: chrono::Utc::now().naive_utc(),
+        deck_ids: HashMap::new(),
+        result: SyncPushResult::default(),
+    };
+
+    let (deck_deletions, deck_changes): (Vec<SyncChange>, Vec<SyncChange>) = sync_form
+        .decks
+        .into_iter()
+        .partition(|change| change.deleted);
+
+    for change in deck_changes {
+        apply_deck_change(&mut context, change).await?;
+    }
+
+    for change in sync_form.cards {
+        apply_card_change(&mut context, change).await?;
+    }
+
+    for change in deck_deletions {
+        apply_deck_change(&mut context, change).await?;
+    }
+
+    context.transaction.commit().await?;
+
+    Ok(context.result)
+}
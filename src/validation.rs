@@ -0,0 +1,1018 @@
+use crate::duplicates::DUPLICATE_POLICIES;
+use crate::events::EventType;
+use crate::spreadsheet::CSV_DELIMITERS;
+use crate::sync::{STRATEGY_FIELD_MERGE, STRATEGY_LAST_WRITER_WINS};
+use crate::{
+    CardForm, CardRelationForm, CardRevisionRestoreForm, CardTransferForm, CsvImportForm, DeckForm,
+    ImportCommitForm, PasteImportForm, ReviewBatchForm, StudyRatingForm, StudySessionForm,
+    SyncChange, SyncForm, TagForm, UserForm, WebhookForm,
+};
+use serde::{de, Deserialize, Deserializer};
+use std::collections::BTreeMap;
+
+// limits from the database schema
+
+const NAME_MAX_LENGTH: usize = 100;
+const EMAIL_MAX_LENGTH: usize = 100;
+const LANGUAGE_MAX_LENGTH: usize = 100;
+const DESIGN_KEY_MAX_LENGTH: usize = 100;
+const CARD_TEXT_MAX_LENGTH: usize = 100;
+pub const EXAMPLE_TEXT_MAX_LENGTH: usize = 255;
+const AUDIO_URL_MAX_LENGTH: usize = 255;
+const RELATION_TYPE_MAX_LENGTH: usize = 100;
+const WEBHOOK_URL_MAX_LENGTH: usize = 255;
+const WEBHOOK_SECRET_MAX_LENGTH: usize = 100;
+pub const TAG_NAME_MAX_LENGTH: usize = 100;
+
+const REVIEW_BATCH_MAX_SIZE: usize = 1000;
+const CSV_COLUMN_MAX_LENGTH: usize = 100;
+const PASTE_TEXT_MAX_LENGTH: usize = 100_000;
+const PASTE_SEPARATOR_MAX_LENGTH: usize = 10;
+const SPACED_PASTE_SEPARATORS: [&str; 3] = ["-", "–", "="];
+
+pub const MIN_RATING: i32 = 0;
+pub const MAX_RATING: i32 = 4;
+
+pub const RELATION_TYPES: [&str; 3] = ["synonym", "antonym", "false_friend"];
+
+pub const REVISION_FIELDS: [&str; 4] = [
+    "from_text",
+    "to_text_primary",
+    "to_text_secondary",
+    "example_text",
+];
+
+// validation model
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct ValidationErrors(BTreeMap<&'static str, String>);
+
+impl ValidationErrors {
+    pub fn add(&mut self, field: &'static str, message: String) {
+        self.0.entry(field).or_insert(message);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // e.g. "from_text must not be blank", for reports that are not tied to a form
+    pub fn to_message(&self) -> String {
+        self.0
+            .iter()
+            .map(|(field, message)| format!("{} {}", field, message))
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+}
+
+// trims a form and checks it against the database constraints, on update only sent fields are checked
+pub trait Validate: Sized {
+    fn validate(self, is_create: bool) -> Result<Self, ValidationErrors>;
+}
+
+// helpers
+
+fn is_email(value: &str) -> bool {
+    if value.contains(char::is_whitespace) {
+        return false;
+    }
+
+    let mut parts = value.split('@');
+
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(local), Some(domain), None) => {
+            !local.is_empty()
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+        }
+        _ => false,
+    }
+}
+
+// accepts absolute http(s) urls and paths to files served by this app
+fn is_url(value: &str) -> bool {
+    if value.contains(char::is_whitespace) {
+        return false;
+    }
+
+    if let Some(path) = value.strip_prefix('/') {
+        return !path.is_empty() && !path.starts_with('/');
+    }
+
+    let rest = value
+        .strip_prefix("https://")
+        .or_else(|| value.strip_prefix("http://"));
+
+    match rest {
+        Some(rest) => rest
+            .split(['/', '?', '#'])
+            .next()
+            .is_some_and(|host| !host.is_empty()),
+        None => false,
+    }
+}
+
+fn required_text(
+    errors: &mut ValidationErrors,
+    field: &'static str,
+    value: Option<String>,
+    max_length: usize,
+    is_create: bool,
+) -> Option<String> {
+    match value.map(|value| value.trim().to_string()) {
+        None => {
+            if is_create {
+                errors.add(field, String::from("is required"));
+            }
+            None
+        }
+        Some(value) if value.is_empty() => {
+            errors.add(field, String::from("must not be blank"));
+            None
+        }
+        Some(value) => {
+            if value.chars().count() > max_length {
+                errors.add(field, format!("must be at most {} characters", max_length));
+            }
+            Some(value)
+        }
+    }
+}
+
+fn optional_text(
+    errors: &mut ValidationErrors,
+    field: &'static str,
+    value: Option<String>,
+    max_length: usize,
+) -> Option<String> {
+    match value.map(|value| value.trim().to_string()) {
+        Some(value) if value.is_empty() => None,
+        Some(value) => {
+            if value.chars().count() > max_length {
+                errors.add(field, format!("must be at most {} characters", max_length));
+            }
+            Some(value)
+        }
+        None => None,
+    }
+}
+
+// like optional_text, but on update a blank value is kept as "" so the column is cleared
+fn clearable_text(
+    errors: &mut ValidationErrors,
+    field: &'static str,
+    value: Option<String>,
+    max_length: usize,
+    is_create: bool,
+) -> Option<String> {
+    let is_blank = value
+        .as_deref()
+        .is_some_and(|value| value.trim().is_empty());
+
+    if is_blank && !is_create {
+        return Some(String::new());
+    }
+
+    optional_text(errors, field, value, max_length)
+}
+
+// reads a comma separated list of ids like "1,2,3" from a form field
+pub fn deserialize_id_list<'de, D>(deserializer: D) -> Result<Vec<i32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = String::deserialize(deserializer)?;
+
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(|id| id.parse::<i32>().map_err(de::Error::custom))
+        .collect()
+}
+
+// form validation
+
+// a comma separated list of tag names, trimmed and without duplicates
+fn tag_name_list(errors: &mut ValidationErrors, field: &'static str, value: &str) -> String {
+    let mut names: Vec<&str> = Vec::new();
+
+    for name in value.split(',').map(str::trim) {
+        if name.is_empty() || names.contains(&name) {
+            continue;
+        }
+
+        if name.chars().count() > TAG_NAME_MAX_LENGTH {
+            errors.add(
+                field,
+                format!(
+                    "tag names must be at most {} characters",
+                    TAG_NAME_MAX_LENGTH
+                ),
+            );
+        }
+
+        names.push(name);
+    }
+
+    names.join(",")
+}
+
+impl Validate for UserForm {
+    fn validate(self, is_create: bool) -> Result<Self, ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+
+        let name = required_text(&mut errors, "name", self.name, NAME_MAX_LENGTH, is_create);
+        let email = required_text(
+            &mut errors,
+            "email",
+            self.email,
+            EMAIL_MAX_LENGTH,
+            is_create,
+        );
+
+        if let Some(email) = &email {
+            if !is_email(email) {
+                errors.add("email", String::from("must be a valid email address"));
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        Ok(UserForm { name, email })
+    }
+}
+
+impl Validate for DeckForm {
+    fn validate(self, is_create: bool) -> Result<Self, ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+
+        let from_language = required_text(
+            &mut errors,
+            "from_language",
+            self.from_language,
+            LANGUAGE_MAX_LENGTH,
+            is_create,
+        );
+        let to_language_primary = required_text(
+            &mut errors,
+            "to_language_primary",
+            self.to_language_primary,
+            LANGUAGE_MAX_LENGTH,
+            is_create,
+        );
+        let to_language_secondary = clearable_text(
+            &mut errors,
+            "to_language_secondary",
+            self.to_language_secondary,
+            LANGUAGE_MAX_LENGTH,
+            is_create,
+        );
+        let design_key = clearable_text(
+            &mut errors,
+            "design_key",
+            self.design_key,
+            DESIGN_KEY_MAX_LENGTH,
+            is_create,
+        );
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        Ok(DeckForm {
+            from_language,
+            to_language_primary,
+            to_language_secondary,
+            design_key,
+            seen_at: self.seen_at,
+        })
+    }
+}
+
+impl Validate for CardForm {
+    fn validate(self, is_create: bool) -> Result<Self, ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+
+        let from_text = required_text(
+            &mut errors,
+            "from_text",
+            self.from_text,
+            CARD_TEXT_MAX_LENGTH,
+            is_create,
+        );
+        let to_text_primary = required_text(
+            &mut errors,
+            "to_text_primary",
+            self.to_text_primary,
+            CARD_TEXT_MAX_LENGTH,
+            is_create,
+        );
+        let to_text_secondary = clearable_text(
+            &mut errors,
+            "to_text_secondary",
+            self.to_text_secondary,
+            CARD_TEXT_MAX_LENGTH,
+            is_create,
+        );
+        let example_text = clearable_text(
+            &mut errors,
+            "example_text",
+            self.example_text,
+            EXAMPLE_TEXT_MAX_LENGTH,
+            is_create,
+        );
+        let audio_url = clearable_text(
+            &mut errors,
+            "audio_url",
+            self.audio_url,
+            AUDIO_URL_MAX_LENGTH,
+            is_create,
+        );
+
+        if let Some(audio_url) = audio_url
+            .as_deref()
+            .filter(|audio_url| !audio_url.is_empty())
+        {
+            if !is_url(audio_url) {
+                errors.add("audio_url", String::from("must be a valid URL"));
+            }
+        }
+
+        if let Some(rating) = self.rating {
+            if !(MIN_RATING..=MAX_RATING).contains(&rating) {
+                errors.add(
+                    "rating",
+                    format!("must be between {} and {}", MIN_RATING, MAX_RATING),
+                );
+            }
+        }
+
+        if let Some(seen_for) = self.seen_for {
+            if seen_for < 0 {
+                errors.add("seen_for", String::from("must not be negative"));
+            }
+        }
+
+        let tags = self
+            .tags
+            .map(|tags| tag_name_list(&mut errors, "tags", &tags));
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        Ok(CardForm {
+            from_text,
+            to_text_primary,
+            to_text_secondary,
+            example_text,
+            audio_url,
+            seen_at: self.seen_at,
+            seen_for: self.seen_for,
+            rating: self.rating,
+            tags,
+        })
+    }
+}
+
+impl Validate for CardTransferForm {
+    fn validate(self, _is_create: bool) -> Result<Self, ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+
+        let mut card_ids = self.card_ids;
+
+        card_ids.sort_unstable();
+        card_ids.dedup();
+
+        if card_ids.is_empty() {
+            errors.add("card_ids", String::from("must not be empty"));
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        Ok(CardTransferForm {
+            card_ids,
+            target_deck_id: self.target_deck_id,
+        })
+    }
+}
+
+impl Validate for CardRelationForm {
+    fn validate(self, _is_create: bool) -> Result<Self, ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+
+        let relation_type = optional_text(
+            &mut errors,
+            "relation_type",
+            self.relation_type,
+            RELATION_TYPE_MAX_LENGTH,
+        );
+
+        if let Some(relation_type) = &relation_type {
+            if !RELATION_TYPES.contains(&relation_type.as_str()) {
+                errors.add(
+                    "relation_type",
+                    format!("must be one of {}", RELATION_TYPES.join(", ")),
+                );
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        Ok(CardRelationForm {
+            related_card_id: self.related_card_id,
+            relation_type,
+        })
+    }
+}
+
+impl Validate for WebhookForm {
+    fn validate(self, is_create: bool) -> Result<Self, ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+
+        let url = required_text(
+            &mut errors,
+            "url",
+            self.url,
+            WEBHOOK_URL_MAX_LENGTH,
+            is_create,
+        );
+
+        if let Some(url) = &url {
+            if !is_url(url) || url.starts_with('/') {
+                errors.add("url", String::from("must be a valid http(s) URL"));
+            }
+        }
+
+        let secret = optional_text(
+            &mut errors,
+            "secret",
+            self.secret,
+            WEBHOOK_SECRET_MAX_LENGTH,
+        );
+
+        let event_types = self.event_types.map(|event_types| {
+            let event_types: Vec<&str> = event_types
+                .split(',')
+                .map(str::trim)
+                .filter(|event_type| !event_type.is_empty())
+                .collect();
+
+            for event_type in &event_types {
+                if !EventType::ALL
+                    .iter()
+                    .any(|known| known.as_str() == *event_type)
+                {
+                    errors.add("event_types", format!("unknown event type {}", event_type));
+                }
+            }
+
+            event_types.join(",")
+        });
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        Ok(WebhookForm {
+            url,
+            secret,
+            event_types,
+            active: self.active,
+        })
+    }
+}
+
+// checks the shape of each change, the fields themselves are validated when the change is applied
+fn validate_sync_changes(
+    errors: &mut ValidationErrors,
+    field: &'static str,
+    changes: &[SyncChange],
+    needs_deck: bool,
+) {
+    for (index, change) in changes.iter().enumerate() {
+        if change.id.is_none() && change.deleted {
+            errors.add(field, format!("change {} deletes without an id", index));
+        }
+
+        if change.id.is_none() && change.client_id.is_none() {
+            errors.add(
+                field,
+                format!("change {} needs an id or a client_id", index),
+            );
+        }
+
+        if needs_deck
+            && change.id.is_none()
+            && change.deck_id.is_none()
+            && change.deck_client_id.is_none()
+        {
+            errors.add(
+                field,
+                format!("change {} needs a deck_id or a deck_client_id", index),
+            );
+        }
+    }
+}
+
+impl Validate for SyncForm {
+    fn validate(self, _is_create: bool) -> Result<Self, ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+
+        let strategy = self
+            .strategy
+            .map(|strategy| strategy.trim().to_string())
+            .filter(|strategy| !strategy.is_empty())
+            .unwrap_or_else(|| String::from(STRATEGY_LAST_WRITER_WINS));
+
+        if strategy != STRATEGY_LAST_WRITER_WINS && strategy != STRATEGY_FIELD_MERGE {
+            errors.add(
+                "strategy",
+                format!(
+                    "must be one of {}, {}",
+                    STRATEGY_LAST_WRITER_WINS, STRATEGY_FIELD_MERGE
+                ),
+            );
+        }
+
+        validate_sync_changes(&mut errors, "decks", &self.decks, false);
+        validate_sync_changes(&mut errors, "cards", &self.cards, true);
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        Ok(SyncForm {
+            strategy: Some(strategy),
+            decks: self.decks,
+            cards: self.cards,
+        })
+    }
+}
+
+impl Validate for StudyRatingForm {
+    fn validate(self, _is_create: bool) -> Result<Self, ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+
+        match self.rating {
+            None => errors.add("rating", String::from("is required")),
+            Some(rating) if !(MIN_RATING..=MAX_RATING).contains(&rating) => errors.add(
+                "rating",
+                format!("must be between {} and {}", MIN_RATING, MAX_RATING),
+            ),
+            Some(_) => {}
+        }
+
+        if let Some(seen_for) = self.seen_for {
+            if seen_for < 0 {
+                errors.add("seen_for", String::from("must not be negative"));
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        Ok(self)
+    }
+}
+
+impl Validate for ReviewBatchForm {
+    fn validate(self, _is_create: bool) -> Result<Self, ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+
+        if self.reviews.is_empty() {
+            errors.add("reviews", String::from("must not be empty"));
+        }
+
+        if self.reviews.len() > REVIEW_BATCH_MAX_SIZE {
+            errors.add(
+                "reviews",
+                format!("must contain at most {} reviews", REVIEW_BATCH_MAX_SIZE),
+            );
+        }
+
+        for (index, review) in self.reviews.iter().enumerate() {
+            match review.rating {
+                None => errors.add("reviews", format!("review {} needs a rating", index)),
+                Some(rating) if !(MIN_RATING..=MAX_RATING).contains(&rating) => errors.add(
+                    "reviews",
+                    format!(
+                        "review {} needs a rating between {} and {}",
+                        index, MIN_RATING, MAX_RATING
+                    ),
+                ),
+                Some(_) => {}
+            }
+
+            if review.seen_for.is_some_and(|seen_for| seen_for < 0) {
+                errors.add(
+                    "reviews",
+                    format!("review {} has a negative seen_for", index),
+                );
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        Ok(self)
+    }
+}
+
+impl Validate for CardRevisionRestoreForm {
+    fn validate(self, _is_create: bool) -> Result<Self, ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+
+        let mut fields: Vec<&str> = self
+            .fields
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|field| !field.is_empty())
+            .collect();
+
+        for field in &fields {
+            if !REVISION_FIELDS.contains(field) {
+                errors.add(
+                    "fields",
+                    format!("must be a list of {}", REVISION_FIELDS.join(", ")),
+                );
+            }
+        }
+
+        // no fields means the whole revision
+        if fields.is_empty() {
+            fields = REVISION_FIELDS.to_vec();
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        Ok(CardRevisionRestoreForm {
+            fields: Some(fields.join(",")),
+        })
+    }
+}
+
+impl Validate for TagForm {
+    fn validate(self, is_create: bool) -> Result<Self, ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+
+        let name = required_text(
+            &mut errors,
+            "name",
+            self.name,
+            TAG_NAME_MAX_LENGTH,
+            is_create,
+        );
+
+        // tags are given as comma separated lists on cards and sessions
+        if name.as_ref().is_some_and(|name| name.contains(',')) {
+            errors.add("name", String::from("must not contain a comma"));
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        Ok(TagForm { name })
+    }
+}
+
+impl Validate for StudySessionForm {
+    fn validate(self, _is_create: bool) -> Result<Self, ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+
+        let tags = self
+            .tags
+            .map(|tags| tag_name_list(&mut errors, "tags", &tags));
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        Ok(StudySessionForm {
+            deck_id: self.deck_id,
+            tags,
+        })
+    }
+}
+
+impl Validate for CsvImportForm {
+    fn validate(self, _is_create: bool) -> Result<Self, ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+
+        // a tab would be trimmed away, so it is read before trimming
+        let delimiter = match self.delimiter.as_deref() {
+            Some("\t") => Some(String::from("tab")),
+            Some(delimiter) => match delimiter.trim() {
+                "" | "auto" => None,
+                delimiter if CSV_DELIMITERS.iter().any(|(name, _)| *name == delimiter) => {
+                    Some(String::from(delimiter))
+                }
+                _ => {
+                    let names: Vec<&str> = CSV_DELIMITERS.iter().map(|(name, _)| *name).collect();
+                    errors.add("delimiter", format!("must be one of {}", names.join(" ")));
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let header = match self.header.as_deref().map(str::trim) {
+            None | Some("") => None,
+            Some(header @ ("true" | "false")) => Some(String::from(header)),
+            Some(_) => {
+                errors.add("header", String::from("must be true or false"));
+                None
+            }
+        };
+
+        let from_text = optional_text(
+            &mut errors,
+            "from_text",
+            self.from_text,
+            CSV_COLUMN_MAX_LENGTH,
+        );
+        let to_text_primary = optional_text(
+            &mut errors,
+            "to_text_primary",
+            self.to_text_primary,
+            CSV_COLUMN_MAX_LENGTH,
+        );
+        let to_text_secondary = optional_text(
+            &mut errors,
+            "to_text_secondary",
+            self.to_text_secondary,
+            CSV_COLUMN_MAX_LENGTH,
+        );
+        let example_text = optional_text(
+            &mut errors,
+            "example_text",
+            self.example_text,
+            CSV_COLUMN_MAX_LENGTH,
+        );
+        let audio_url = optional_text(
+            &mut errors,
+            "audio_url",
+            self.audio_url,
+            CSV_COLUMN_MAX_LENGTH,
+        );
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        Ok(CsvImportForm {
+            delimiter,
+            header,
+            from_text,
+            to_text_primary,
+            to_text_secondary,
+            example_text,
+            audio_url,
+        })
+    }
+}
+
+impl Validate for PasteImportForm {
+    fn validate(self, _is_create: bool) -> Result<Self, ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+
+        let text = required_text(&mut errors, "text", self.text, PASTE_TEXT_MAX_LENGTH, true);
+
+        // a tab would be trimmed away, so it is read before trimming
+        let separator = match self.separator.as_deref() {
+            Some("\t") => Some(String::from("\t")),
+            Some(separator) => match separator.trim() {
+                "" | "auto" => None,
+                "tab" => Some(String::from("\t")),
+                // dashes and equals signs split only with spaces around them, like "E-Mail - email"
+                separator if SPACED_PASTE_SEPARATORS.contains(&separator) => {
+                    Some(format!(" {} ", separator))
+                }
+                separator if separator.chars().count() > PASTE_SEPARATOR_MAX_LENGTH => {
+                    errors.add(
+                        "separator",
+                        format!("must be at most {} characters", PASTE_SEPARATOR_MAX_LENGTH),
+                    );
+                    None
+                }
+                separator => Some(String::from(separator)),
+            },
+            None => None,
+        };
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        Ok(PasteImportForm {
+            text,
+            separator,
+            swap: self.swap,
+            skip_duplicates: self.skip_duplicates,
+        })
+    }
+}
+
+impl Validate for ImportCommitForm {
+    fn validate(self, _is_create: bool) -> Result<Self, ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+
+        let duplicates = match self.duplicates.as_deref().map(str::trim) {
+            None | Some("") => None,
+            Some(duplicates) if DUPLICATE_POLICIES.contains(&duplicates) => {
+                Some(String::from(duplicates))
+            }
+            Some(_) => {
+                errors.add(
+                    "duplicates",
+                    format!("must be one of {}", DUPLICATE_POLICIES.join(", ")),
+                );
+                None
+            }
+        };
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        Ok(ImportCommitForm { duplicates, ..self })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn some(value: &str) -> Option<String> {
+        Some(String::from(value))
+    }
+
+    #[test]
+    fn required_text_trims_and_checks_presence() {
+        let mut errors = ValidationErrors::default();
+
+        assert_eq!(
+            required_text(&mut errors, "name", some("  Anna "), 10, true),
+            some("Anna")
+        );
+        assert!(errors.is_empty());
+
+        assert_eq!(required_text(&mut errors, "name", None, 10, false), None);
+        assert!(errors.is_empty());
+
+        assert_eq!(required_text(&mut errors, "name", None, 10, true), None);
+        assert_eq!(errors.to_message(), "name is required");
+
+        let mut errors = ValidationErrors::default();
+
+        assert_eq!(
+            required_text(&mut errors, "name", some("   "), 10, false),
+            None
+        );
+        assert_eq!(errors.to_message(), "name must not be blank");
+    }
+
+    #[test]
+    fn required_text_counts_characters() {
+        let mut errors = ValidationErrors::default();
+
+        required_text(&mut errors, "name", some("ÄÖÜäöüß"), 7, true);
+        assert!(errors.is_empty());
+
+        required_text(&mut errors, "name", some("ÄÖÜäöüßx"), 7, true);
+        assert_eq!(errors.to_message(), "name must be at most 7 characters");
+    }
+
+    #[test]
+    fn optional_text_drops_blank_values() {
+        let mut errors = ValidationErrors::default();
+
+        assert_eq!(optional_text(&mut errors, "field", None, 10), None);
+        assert_eq!(optional_text(&mut errors, "field", some("  "), 10), None);
+        assert_eq!(
+            optional_text(&mut errors, "field", some(" text "), 10),
+            some("text")
+        );
+        assert!(errors.is_empty());
+
+        optional_text(&mut errors, "field", some("too long text"), 10);
+        assert_eq!(errors.to_message(), "field must be at most 10 characters");
+    }
+
+    #[test]
+    fn clearable_text_keeps_blank_values_on_update() {
+        let mut errors = ValidationErrors::default();
+
+        assert_eq!(
+            clearable_text(&mut errors, "field", some(" "), 10, true),
+            None
+        );
+        assert_eq!(
+            clearable_text(&mut errors, "field", some(" "), 10, false),
+            some("")
+        );
+        assert_eq!(clearable_text(&mut errors, "field", None, 10, false), None);
+        assert_eq!(
+            clearable_text(&mut errors, "field", some(" text "), 10, false),
+            some("text")
+        );
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn card_form_update_clears_optional_fields() {
+        let card_form: CardForm = serde_json::from_value(json!({
+            "to_text_secondary": "",
+            "example_text": " ",
+            "audio_url": "",
+        }))
+        .unwrap();
+
+        let card_form = card_form.validate(false).unwrap();
+
+        assert_eq!(card_form.from_text, None);
+        assert_eq!(card_form.to_text_secondary, some(""));
+        assert_eq!(card_form.example_text, some(""));
+        assert_eq!(card_form.audio_url, some(""));
+    }
+
+    #[test]
+    fn card_form_create_rejects_invalid_audio_url() {
+        let card_form: CardForm = serde_json::from_value(json!({
+            "from_text": "Hund",
+            "to_text_primary": "dog",
+            "audio_url": "not a url",
+        }))
+        .unwrap();
+
+        let errors = card_form.validate(true).err().unwrap();
+
+        assert_eq!(errors.to_message(), "audio_url must be a valid URL");
+    }
+
+    #[test]
+    fn is_email_accepts_plain_addresses() {
+        assert!(is_email("anna@example.com"));
+        assert!(is_email("anna.b+cards@mail.example.org"));
+
+        assert!(!is_email("anna"));
+        assert!(!is_email("@example.com"));
+        assert!(!is_email("anna@example"));
+        assert!(!is_email("anna@.example.com"));
+        assert!(!is_email("anna@example.com."));
+        assert!(!is_email("anna@b@example.com"));
+        assert!(!is_email("anna @example.com"));
+    }
+
+    #[test]
+    fn is_url_accepts_http_urls_and_local_paths() {
+        assert!(is_url("https://example.com"));
+        assert!(is_url("http://example.com/audio.mp3?v=1"));
+        assert!(is_url("/media/audio.mp3"));
+
+        assert!(!is_url("example.com"));
+        assert!(!is_url("ftp://example.com"));
+        assert!(!is_url("https://"));
+        assert!(!is_url("https:///path"));
+        assert!(!is_url("/"));
+        assert!(!is_url("//example.com"));
+        assert!(!is_url("https://example.com/a b"));
+    }
+
+    #[derive(Deserialize)]
+    struct IdList {
+        #[serde(deserialize_with = "deserialize_id_list")]
+        ids: Vec<i32>,
+    }
+
+    fn id_list(value: &str) -> Result<Vec<i32>, serde_json::Error> {
+        serde_json::from_value::<IdList>(json!({ "ids": value })).map(|id_list| id_list.ids)
+    }
+
+    #[test]
+    fn deserialize_id_list_reads_comma_separated_ids() {
+        assert_eq!(id_list("1,2,3").unwrap(), vec![1, 2, 3]);
+        assert_eq!(id_list(" 4 , 5,,6, ").unwrap(), vec![4, 5, 6]);
+        assert_eq!(id_list("").unwrap(), Vec::<i32>::new());
+
+        assert!(id_list("1,x").is_err());
+        assert!(id_list("1.5").is_err());
+    }
+}
@@ -0,0 +1,180 @@
+use crate::events::EventType;
+use crate::queries::{
+    claim_webhook_deliveries_query, create_webhook_deliveries_query, update_webhook_delivery_query,
+    PendingWebhookDelivery,
+};
+use hmac::{Hmac, Mac};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde_json::Value;
+use sha2::Sha256;
+use sqlx::{Pool, Postgres};
+use std::time::Duration;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const BATCH_SIZE: i64 = 20;
+const MAX_ATTEMPTS: i32 = 8;
+const BASE_RETRY_SECONDS: f64 = 30.0;
+
+pub fn generate_secret() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+// dispatch
+
+pub async fn dispatch_event(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+    event_type: EventType,
+    payload: Value,
+) {
+    let result = create_webhook_deliveries_query(pool, user_id, event_type.as_str(), payload).await;
+
+    if let Err(err) = result {
+        eprintln!(
+            "Failed to queue webhook deliveries for {}. Error: {}",
+            event_type.as_str(),
+            err
+        );
+    }
+}
+
+// delivery worker
+
+fn sign_payload(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("hmac should accept keys of any length");
+
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+// 30s, 1m, 2m, 4m, ... after each failed attempt
+fn retry_in_seconds(attempts: i32) -> f64 {
+    BASE_RETRY_SECONDS * 2_f64.powi(attempts)
+}
+
+async fn deliver(
+    client: &reqwest::Client,
+    pool: &Pool<Postgres>,
+    delivery: PendingWebhookDelivery,
+) {
+    let body = delivery.payload.to_string();
+    let timestamp = chrono::Utc::now().timestamp();
+
+    let response = client
+        .post(&delivery.url)
+        .header("Content-Type", "application/json")
+        .header("X-Cards-Event", &delivery.event_type)
+        .header("X-Cards-Delivery", delivery.id)
+        .header("X-Cards-Timestamp", timestamp)
+        .header(
+            "X-Cards-Signature",
+            format!(
+                "sha256={}",
+                sign_payload(&delivery.secret, timestamp, &body)
+            ),
+        )
+        .body(body)
+        .send()
+        .await;
+
+    let (response_status, last_error) = match response {
+        Ok(response) if response.status().is_success() => (Some(response.status().as_u16()), None),
+        Ok(response) => (
+            Some(response.status().as_u16()),
+            Some(format!("Receiver responded with {}", response.status())),
+        ),
+        Err(err) => (None, Some(err.to_string())),
+    };
+
+    let status = if last_error.is_none() {
+        "delivered"
+    } else if delivery.attempts + 1 >= MAX_ATTEMPTS {
+        "failed"
+    } else {
+        "pending"
+    };
+
+    let result = update_webhook_delivery_query(
+        pool,
+        delivery.id,
+        status,
+        retry_in_seconds(delivery.attempts),
+        response_status.map(i32::from),
+        last_error,
+    )
+    .await;
+
+    if let Err(err) = result {
+        eprintln!(
+            "Failed to record webhook delivery {}. Error: {}",
+            delivery.id, err
+        );
+    }
+}
+
+pub async fn run_webhook_worker(pool: Pool<Postgres>) {
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .expect("http client should build");
+
+    loop {
+        match claim_webhook_deliveries_query(&pool, BATCH_SIZE).await {
+            Ok(deliveries) => {
+                for delivery in deliveries {
+                    deliver(&client, &pool, delivery).await;
+                }
+            }
+            Err(err) => eprintln!("Failed to claim webhook deliveries. Error: {}", err),
+        }
+
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_payload_is_the_hmac_of_timestamp_and_body() {
+        let signature = sign_payload(
+            "whsec_test",
+            1_700_000_000,
+            r#"{"event":"card.created","data":{"id":1}}"#,
+        );
+
+        assert_eq!(
+            signature,
+            "62de19f417271c8d6e9af1ca9a259fdad7ce434e4237213013d3f6a298f46ac8"
+        );
+        assert_ne!(
+            sign_payload(
+                "whsec_test",
+                1_700_000_001,
+                r#"{"event":"card.created","data":{"id":1}}"#
+            ),
+            signature
+        );
+    }
+
+    #[test]
+    fn retry_in_seconds_doubles_after_each_attempt() {
+        assert_eq!(retry_in_seconds(0), 30.0);
+        assert_eq!(retry_in_seconds(1), 60.0);
+        assert_eq!(retry_in_seconds(2), 120.0);
+        assert_eq!(retry_in_seconds(MAX_ATTEMPTS - 1), 3840.0);
+    }
+}